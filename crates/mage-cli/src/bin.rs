@@ -209,7 +209,7 @@ pub mod repl {
             // Fallback to basic keyword highlighting
             let keywords = [
                 "conjure", "incant", "curse", "evoke", "scry", "morph", "lest", "chant", "recite",
-                "channel", "loop", "enchant", "cast", "yield",
+                "channel", "loop", "enchant", "ritual", "cast", "yield",
             ];
 
             // Apply simple syntax highlighting with colors
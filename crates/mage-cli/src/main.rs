@@ -1,9 +1,13 @@
 mod bin;
+mod pkg;
 mod setup;
 mod syntax;
 
 use clap::{Parser, Subcommand};
-use mage_core::{format, run};
+use mage_core::package::{IntegrityStatus, PackageResolver, VendorStatus};
+use mage_core::registry::Registry;
+use mage_core::tasks::{TaskFile, TaskStatus};
+use mage_core::{RunOptions, format, journal, run, run_with_options, scripts};
 use std::fs;
 use std::path::Path;
 
@@ -17,10 +21,14 @@ struct Cli {
     #[arg(short = 'c', long = "command")]
     inline_command: Option<String>,
 
-    /// Optional script file to run (shorthand for `run <SCRIPT>`)
+    /// Script file or mage.toml script to run (shorthand for `run <SCRIPT>`)
     #[arg(global = false)]
     script: Option<String>,
 
+    /// Arguments passed to the script
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    script_args: Vec<String>,
+
     /// Override shell for script execution
     #[arg(long, global = true)]
     shell: Option<String>,
@@ -32,6 +40,80 @@ enum Commands {
     Run {
         /// Script file to run
         file: String,
+
+        /// Report side effects (commands, file writes, installs) without performing them
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Snapshot files before modifying them so the run can be undone
+        #[arg(long)]
+        journal: bool,
+
+        /// Treat reading an undefined variable as an error
+        #[arg(long)]
+        strict: bool,
+
+        /// Arguments passed to the script
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Run a script from mage.toml with its pre/post hooks (lists scripts without a name)
+    RunScript {
+        /// Script name
+        name: Option<String>,
+
+        /// Arguments passed to the script
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Run tasks declared with `ritual` in a task file
+    Task {
+        /// Tasks to run (defaults to the `default` task)
+        names: Vec<String>,
+
+        /// Show the task graph instead of running tasks
+        #[arg(long)]
+        list: bool,
+
+        /// Task file to load
+        #[arg(short, long, default_value = "tasks.mage")]
+        file: String,
+
+        /// Number of tasks to run at once (defaults to the number of CPUs)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// Report side effects (commands, file writes, installs) without performing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Restore files changed by a journaled run (defaults to the most recent run)
+    Undo {
+        /// Run id to undo
+        run_id: Option<String>,
+
+        /// List journaled runs instead of undoing one
+        #[arg(long)]
+        list: bool,
+    },
+    /// Manage project dependencies in mage.toml and mage.lock
+    Pkg {
+        #[command(subcommand)]
+        command: pkg::PkgCommand,
+    },
+    /// Check installed packages against the checksums in mage.lock
+    Verify {},
+    /// Pack this project into a mage registry
+    Publish {
+        /// Registry directory (defaults to MAGE_REGISTRY or registry= in .mageconfig)
+        #[arg(long)]
+        registry: Option<String>,
+    },
+    /// Copy the packages in mage.lock into vendor/ for offline installs
+    Vendor {
+        /// Use only the package cache, without the network
+        #[arg(long)]
+        offline: bool,
     },
     /// Start an interactive REPL
     Repl {},
@@ -61,8 +143,51 @@ fn main() {
     let cli = Cli::parse();
 
     match &cli.command {
-        Some(Commands::Run { file }) => {
-            run_script(file, cli.shell.as_deref());
+        Some(Commands::Run {
+            file,
+            dry_run,
+            journal,
+            strict,
+            args,
+        }) => {
+            let options = RunOptions {
+                dry_run: *dry_run,
+                journal: *journal,
+                strict: *strict,
+                args: args.clone(),
+            };
+            run_script_with_options(file, cli.shell.as_deref(), &options);
+        }
+        Some(Commands::RunScript { name, args }) => match name {
+            Some(name) => run_manifest_script(name, args, cli.shell.as_deref()),
+            None => list_manifest_scripts(),
+        },
+        Some(Commands::Task {
+            names,
+            list,
+            file,
+            jobs,
+            dry_run,
+        }) => {
+            run_tasks(file, names, *list, *jobs, *dry_run, cli.shell.as_deref());
+        }
+        Some(Commands::Undo { run_id, list }) => {
+            undo_run(run_id.as_deref(), *list);
+        }
+        Some(Commands::Pkg { command }) => {
+            if let Err(e) = pkg::run(command) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Verify {}) => {
+            verify_packages();
+        }
+        Some(Commands::Publish { registry }) => {
+            publish_package(registry.as_deref());
+        }
+        Some(Commands::Vendor { offline }) => {
+            vendor_packages(*offline);
         }
         Some(Commands::Repl {}) => {
             if let Err(e) = bin::repl::run_repl(cli.shell.as_deref()) {
@@ -92,7 +217,7 @@ fn main() {
             }
             // If no command but a script is provided, run it
             else if let Some(script) = cli.script {
-                run_script(&script, cli.shell.as_deref());
+                run_script_or_manifest_script(&script, &cli.script_args, cli.shell.as_deref());
             } else {
                 // No subcommand and no script, start REPL by default
                 if let Err(e) = bin::repl::run_repl(cli.shell.as_deref()) {
@@ -104,7 +229,35 @@ fn main() {
     }
 }
 
-fn run_script(path: &str, shell: Option<&str>) {
+/// Run `mage <name>`: a script file, or a mage.toml script when no file by
+/// that name exists.
+fn run_script_or_manifest_script(name: &str, args: &[String], shell: Option<&str>) {
+    let is_file = Path::new(name).is_file();
+    let is_manifest_script = std::env::current_dir()
+        .ok()
+        .and_then(|root| scripts::list_scripts(&root).ok())
+        .is_some_and(|scripts| scripts.contains_key(name));
+
+    match (is_file, is_manifest_script) {
+        (true, true) => {
+            eprintln!(
+                "'{}' is both a file and a script in mage.toml; use `mage run {}` or `mage run-script {}`",
+                name, name, name
+            );
+            std::process::exit(1);
+        }
+        (false, true) => run_manifest_script(name, args, shell),
+        _ => {
+            let options = RunOptions {
+                args: args.to_vec(),
+                ..Default::default()
+            };
+            run_script_with_options(name, shell, &options);
+        }
+    }
+}
+
+fn run_script_with_options(path: &str, shell: Option<&str>, options: &RunOptions) {
     let code = match fs::read_to_string(path) {
         Ok(content) => content,
         Err(e) => {
@@ -113,7 +266,11 @@ fn run_script(path: &str, shell: Option<&str>) {
         }
     };
 
-    if let Err(e) = run(&code, shell) {
+    if options.dry_run {
+        println!("🔮 Dry-run: no commands will be executed and no files will be changed");
+    }
+
+    if let Err(e) = run_with_options(&code, shell, options) {
         eprintln!("{}", e);
         std::process::exit(1);
     }
@@ -126,6 +283,265 @@ fn run_inline_command(code: &str, shell: Option<&str>) {
     }
 }
 
+fn undo_run(run_id: Option<&str>, list: bool) {
+    let root = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Failed to get current directory: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if list {
+        let runs = journal::list_runs(&root);
+        if runs.is_empty() {
+            println!("No journaled runs");
+        }
+        for info in runs {
+            println!(
+                "{}  {}  {} change(s)",
+                info.run_id, info.started_at, info.changes
+            );
+        }
+        return;
+    }
+
+    match journal::undo(&root, run_id) {
+        Ok(restored) => {
+            for path in &restored {
+                println!("↩️  Restored {}", path.display());
+            }
+            println!("✅ Undo complete ({} path(s) restored)", restored.len());
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_manifest_script(name: &str, args: &[String], shell: Option<&str>) {
+    let root = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Failed to get current directory: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let steps = match scripts::script_steps(&root, name) {
+        Ok(steps) => steps,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    for step in &steps {
+        println!("▶️  {}: {}", step.name, step.command);
+        if let Err(e) = scripts::run_step(&root, step, args, shell, &RunOptions::default()) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn list_manifest_scripts() {
+    let root = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Failed to get current directory: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match scripts::list_scripts(&root) {
+        Ok(scripts) if scripts.is_empty() => println!("No scripts defined in mage.toml"),
+        Ok(scripts) => {
+            let width = scripts.keys().map(|name| name.len()).max().unwrap_or(0);
+            for (name, command) in &scripts {
+                println!("  {:<width$}  {}", name, command, width = width);
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_tasks(
+    file: &str,
+    names: &[String],
+    list: bool,
+    jobs: Option<usize>,
+    dry_run: bool,
+    shell: Option<&str>,
+) {
+    let options = RunOptions {
+        dry_run,
+        ..Default::default()
+    };
+    let tasks = match TaskFile::load(Path::new(file), shell, &options) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if tasks.tasks().is_empty() {
+        println!("No tasks declared in {}", file);
+        return;
+    }
+
+    let names = match names {
+        [] if !list && tasks.task("default").is_some() => vec!["default".to_string()],
+        [] => {
+            print!("{}", tasks.graph());
+            return;
+        }
+        _ if list => {
+            print!("{}", tasks.graph());
+            return;
+        }
+        names => names.to_vec(),
+    };
+
+    if dry_run {
+        println!("🔮 Dry-run: no commands will be executed and no files will be changed");
+    }
+
+    let jobs = jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    match tasks.run(&names, jobs, &options) {
+        Ok(outcomes) => {
+            let skipped = outcomes
+                .iter()
+                .filter(|o| o.status == TaskStatus::UpToDate)
+                .count();
+            println!(
+                "✨ {} task(s) run, {} up to date",
+                outcomes.len() - skipped,
+                skipped
+            );
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn verify_packages() {
+    let root = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Failed to get current directory: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let checks = match PackageResolver::new(&root).verify_installed() {
+        Ok(checks) => checks,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut failed = 0;
+    for check in &checks {
+        match &check.status {
+            IntegrityStatus::Verified => println!("✅ {}", check.name),
+            IntegrityStatus::Mismatch { expected, actual } => {
+                failed += 1;
+                println!("❌ {}: expected {}, found {}", check.name, expected, actual);
+            }
+            IntegrityStatus::NotInstalled => println!("⏭️  {} (not installed)", check.name),
+            IntegrityStatus::Unchecked => {
+                println!("➖ {} (system package, no checksum)", check.name)
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("{} package(s) failed verification", failed);
+        std::process::exit(1);
+    }
+    println!("✅ All installed packages match mage.lock");
+}
+
+fn publish_package(registry: Option<&str>) {
+    let root = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Failed to get current directory: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let Some(registry) = registry.map(Registry::new).or_else(Registry::configured) else {
+        eprintln!("No registry configured; pass --registry <dir> or set MAGE_REGISTRY");
+        std::process::exit(1);
+    };
+
+    match registry.publish(&root) {
+        Ok(entry) => {
+            println!("📦 Published {} to {}", entry.archive, registry.location());
+            println!("🔐 {}", entry.checksum);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn vendor_packages(offline: bool) {
+    let root = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Failed to get current directory: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let report = match PackageResolver::new(&root).with_offline(offline).vendor() {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut vendored = 0;
+    for package in &report.packages {
+        match package.status {
+            VendorStatus::Vendored => {
+                vendored += 1;
+                println!("📦 {} {}", package.name, package.version);
+            }
+            VendorStatus::AlreadyVendored => {
+                vendored += 1;
+                println!("✅ {} {} (already vendored)", package.name, package.version);
+            }
+            VendorStatus::Skipped(reason) => {
+                println!("⏭️  {} {} ({})", package.name, package.version, reason)
+            }
+        }
+    }
+    if report.removed > 0 {
+        println!("🧹 Removed {} stale package(s)", report.removed);
+    }
+    println!(
+        "✨ {} package(s) in vendor/; `mage pkg install --offline` installs from it",
+        vendored
+    );
+}
+
 fn highlight_script(path: &str) {
     let code = match fs::read_to_string(path) {
         Ok(content) => content,
@@ -0,0 +1,203 @@
+use clap::Subcommand;
+use mage_core::package::{
+    PackageDependency, PackageResolver, PackageSource, validate_package_name,
+};
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand)]
+pub enum PkgCommand {
+    /// Create a new mage project in a new directory
+    New {
+        /// Project name, also used as the directory name
+        name: String,
+    },
+    /// Add a dependency to mage.toml and update mage.lock
+    Add {
+        /// Package name
+        name: String,
+
+        /// Version constraint, such as ^1.2, ~1.2.3 or latest
+        #[arg(default_value = "latest")]
+        version: String,
+
+        /// Add as a development dependency
+        #[arg(long)]
+        dev: bool,
+
+        /// Fetch the package from a git repository
+        #[arg(long, conflicts_with_all = ["path", "url", "manager"])]
+        git: Option<String>,
+
+        /// Branch, tag or commit to use with --git
+        #[arg(long, requires = "git")]
+        rev: Option<String>,
+
+        /// Use a local package directory
+        #[arg(long, conflicts_with_all = ["url", "manager"])]
+        path: Option<String>,
+
+        /// Download the package from a URL
+        #[arg(long, conflicts_with = "manager")]
+        url: Option<String>,
+
+        /// Install with npm, pip, cargo, gem or mage (the mage registry) instead of the system package manager
+        #[arg(long)]
+        manager: Option<String>,
+
+        /// Only install on this platform
+        #[arg(long, value_parser = ["linux", "macos", "windows"])]
+        platform: Option<String>,
+
+        /// Mark the dependency as optional
+        #[arg(long)]
+        optional: bool,
+    },
+    /// Remove a dependency from mage.toml
+    Remove {
+        /// Package name
+        name: String,
+    },
+    /// Install the packages recorded in mage.lock
+    Install {
+        /// Also install development dependencies
+        #[arg(long)]
+        dev: bool,
+
+        /// Fail instead of updating a missing or outdated mage.lock
+        #[arg(long, visible_alias = "locked")]
+        frozen: bool,
+
+        /// Install only from vendor/ and the package cache, without the network
+        #[arg(long)]
+        offline: bool,
+    },
+    /// Move packages to the newest versions their constraints allow
+    Update {
+        /// Packages to update (defaults to all)
+        packages: Vec<String>,
+    },
+    /// List dependencies with newer versions available
+    Outdated {},
+    /// Show the locked dependency tree
+    Tree {},
+}
+
+pub fn run(command: &PkgCommand) -> Result<(), String> {
+    let root =
+        std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+
+    match command {
+        PkgCommand::New { name } => new_project(&root, name),
+        PkgCommand::Add {
+            name,
+            version,
+            dev,
+            git,
+            rev,
+            path,
+            url,
+            manager,
+            platform,
+            optional,
+        } => {
+            let source = if let Some(git) = git {
+                PackageSource::Git {
+                    url: git.clone(),
+                    rev: rev.clone(),
+                }
+            } else if let Some(path) = path {
+                PackageSource::Path(path.clone())
+            } else if let Some(url) = url {
+                PackageSource::Url(url.clone())
+            } else {
+                PackageSource::Registry(manager.clone().unwrap_or_else(|| "auto".to_string()))
+            };
+            let dependency = PackageDependency {
+                version: version.clone(),
+                source,
+                optional: *optional,
+                platform: platform.clone(),
+            };
+
+            PackageResolver::new(&root).add(name, dependency, *dev)?;
+            println!(
+                "✅ Added {} {}{}",
+                name,
+                version,
+                if *dev { " (dev)" } else { "" }
+            );
+            Ok(())
+        }
+        PkgCommand::Remove { name } => {
+            PackageResolver::new(&root).remove_dependency(name)?;
+            println!("✅ Removed {}", name);
+            Ok(())
+        }
+        PkgCommand::Install {
+            dev,
+            frozen,
+            offline,
+        } => {
+            PackageResolver::new(&root)
+                .with_offline(*offline)
+                .install_dependencies(*dev, *frozen)?;
+            println!("✅ Dependencies installed");
+            Ok(())
+        }
+        PkgCommand::Update { packages } => {
+            let updates = PackageResolver::new(&root).update(packages)?;
+            if updates.is_empty() {
+                println!("✅ Everything is up to date");
+            }
+            for update in updates {
+                match (update.from, update.to) {
+                    (Some(from), Some(to)) => println!("⬆️  {} {} -> {}", update.name, from, to),
+                    (None, Some(to)) => println!("➕ {} {}", update.name, to),
+                    (Some(from), None) => println!("➖ {} {}", update.name, from),
+                    (None, None) => {}
+                }
+            }
+            Ok(())
+        }
+        PkgCommand::Outdated {} => {
+            let outdated = PackageResolver::new(&root).outdated()?;
+            if outdated.is_empty() {
+                println!("✅ All dependencies are up to date");
+                return Ok(());
+            }
+            println!(
+                "{:<20} {:<12} {:<12} {:<12}",
+                "Package", "Current", "Wanted", "Latest"
+            );
+            for package in outdated {
+                println!(
+                    "{:<20} {:<12} {:<12} {:<12}",
+                    package.name, package.current, package.wanted, package.latest
+                );
+            }
+            Ok(())
+        }
+        PkgCommand::Tree {} => {
+            print!("{}", PackageResolver::new(&root).dependency_tree()?);
+            Ok(())
+        }
+    }
+}
+
+fn new_project(root: &Path, name: &str) -> Result<(), String> {
+    validate_package_name(name)?;
+    let project_dir: PathBuf = root.join(name);
+    if project_dir.exists() {
+        return Err(format!("{} already exists", project_dir.display()));
+    }
+    std::fs::create_dir_all(&project_dir)
+        .map_err(|e| format!("Failed to create {}: {}", project_dir.display(), e))?;
+
+    PackageResolver::new(&project_dir).init_project(name)?;
+    println!(
+        "✅ Created mage project {} in {}",
+        name,
+        project_dir.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::temp_dir;
+use mage_core::archive::extract_archive;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+enum Entry<'a> {
+    File(&'a str, &'a str),
+    Symlink(&'a str, &'a str),
+}
+
+/// A fresh directory with an empty `dest/` inside it, so anything written
+/// next to `dest` escaped the extraction.
+fn workspace(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::create_dir_all(dir.join("dest")).unwrap();
+    dir
+}
+
+/// Write a tar archive with `entries`, storing names verbatim so `..` and
+/// absolute paths make it into the archive.
+fn tar_archive(path: &Path, entries: &[Entry]) {
+    let mut builder = tar::Builder::new(File::create(path).unwrap());
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        let (name, data) = match entry {
+            Entry::File(name, content) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(content.len() as u64);
+                (*name, content.as_bytes())
+            }
+            Entry::Symlink(name, target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                header.set_link_name(target).unwrap();
+                (*name, &b""[..])
+            }
+        };
+        let field = &mut header.as_old_mut().name;
+        field.fill(0);
+        field[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+    builder.finish().unwrap();
+}
+
+fn zip_archive(path: &Path, entries: &[Entry]) {
+    let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+    let options = zip::write::SimpleFileOptions::default();
+    for entry in entries {
+        match entry {
+            Entry::File(name, content) => {
+                zip.start_file(*name, options).unwrap();
+                std::io::Write::write_all(&mut zip, content.as_bytes()).unwrap();
+            }
+            Entry::Symlink(name, target) => zip.add_symlink(*name, *target, options).unwrap(),
+        }
+    }
+    zip.finish().unwrap();
+}
+
+/// Extract `entries` packed as both a tar and a zip archive.
+fn extract_both(name: &str, entries: &[Entry]) -> Vec<(PathBuf, Result<Vec<PathBuf>, String>)> {
+    let mut results = Vec::new();
+    for (format, write) in [
+        ("tar", tar_archive as fn(&Path, &[Entry])),
+        ("zip", zip_archive),
+    ] {
+        let dir = workspace(&format!("{}_{}", name, format));
+        let archive = dir.join(format!("archive.{}", format));
+        write(&archive, entries);
+        let result = extract_archive(&archive, &dir.join("dest"), 0);
+        results.push((dir, result));
+    }
+    results
+}
+
+#[test]
+fn test_extract_rejects_parent_components() {
+    for (dir, result) in extract_both(
+        "mage_archive_parent",
+        &[Entry::File("../escaped.txt", "escaped")],
+    ) {
+        let error = result.unwrap_err();
+        assert!(error.contains("path escapes the destination"), "{}", error);
+        assert!(!dir.join("escaped.txt").exists());
+    }
+}
+
+#[test]
+fn test_extract_rejects_absolute_paths() {
+    let outside = std::env::temp_dir().join("mage_archive_absolute_escape.txt");
+    let _ = fs::remove_file(&outside);
+    let name = outside.display().to_string();
+    for (_, result) in extract_both("mage_archive_absolute", &[Entry::File(&name, "escaped")]) {
+        let error = result.unwrap_err();
+        assert!(error.contains("path escapes the destination"), "{}", error);
+        assert!(!outside.exists());
+    }
+}
+
+#[test]
+fn test_extract_rejects_links_out_of_the_destination() {
+    for (dir, result) in extract_both(
+        "mage_archive_link",
+        &[
+            Entry::Symlink("link", "../.."),
+            Entry::File("link/escaped.txt", "escaped"),
+        ],
+    ) {
+        let error = result.unwrap_err();
+        assert!(error.contains("outside the destination"), "{}", error);
+        assert!(!dir.join("dest/link").exists());
+    }
+}
+
+#[test]
+fn test_extract_rejects_paths_through_chained_links() {
+    // Each link looks harmless alone, but a/b resolves to the parent of dest
+    for (dir, result) in extract_both(
+        "mage_archive_chain",
+        &[
+            Entry::Symlink("a", "."),
+            Entry::Symlink("a/b", ".."),
+            Entry::File("a/b/escaped.txt", "escaped"),
+        ],
+    ) {
+        let error = result.unwrap_err();
+        assert!(error.contains("is a symlink"), "{}", error);
+        assert!(!dir.join("escaped.txt").exists());
+        assert!(!dir.join("b").exists());
+    }
+
+    for (dir, result) in extract_both(
+        "mage_archive_chain_target",
+        &[Entry::Symlink("b", "."), Entry::Symlink("c", "b/..")],
+    ) {
+        let error = result.unwrap_err();
+        assert!(error.contains("through the symlink"), "{}", error);
+        assert!(fs::symlink_metadata(dir.join("dest/c")).is_err());
+    }
+}
+
+#[test]
+fn test_extract_keeps_links_inside_the_destination() {
+    for (dir, result) in extract_both(
+        "mage_archive_inside",
+        &[
+            Entry::File("src/lib.mage", "incant \"hi\""),
+            Entry::Symlink("lib", "src"),
+            Entry::Symlink("bin/run", "../src/lib.mage"),
+        ],
+    ) {
+        result.unwrap();
+        let dest = dir.join("dest");
+        assert_eq!(
+            fs::read_to_string(dest.join("lib/lib.mage")).unwrap(),
+            "incant \"hi\""
+        );
+        assert_eq!(
+            fs::read_to_string(dest.join("bin/run")).unwrap(),
+            "incant \"hi\""
+        );
+    }
+}
@@ -0,0 +1,34 @@
+//! Helpers shared by the integration tests.
+
+// Each test crate uses a different subset of these helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Run the `mage` binary with `args` in `dir`.
+pub fn mage(dir: &Path, args: &[&str]) -> Output {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    Command::new("cargo")
+        .args(["run", "--quiet", "--manifest-path"])
+        .arg(manifest)
+        .arg("--")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("Failed to run mage")
+}
+
+/// A fresh, empty directory under the system temp directory.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Everything `output` wrote to stdout.
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
@@ -0,0 +1,36 @@
+use std::fs;
+use std::process::Command;
+use std::str;
+
+#[test]
+fn test_mage_run_dry_run_skips_side_effects() {
+    let dir = std::env::temp_dir().join("mage_dry_run_test");
+    fs::create_dir_all(&dir).unwrap();
+    let target = dir.join("untouched.txt");
+    let _ = fs::remove_file(&target);
+
+    let script = dir.join("dry_run.mage");
+    fs::write(
+        &script,
+        format!(
+            "cast write_file(\"{}\", \"hello\\n\")\nevoke \"echo should-not-run\"\n",
+            target.display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "-p", "mage-cli", "--", "run", "--dry-run"])
+        .arg(&script)
+        .output()
+        .expect("Failed to run mage run --dry-run");
+
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+
+    assert!(output.status.success(), "Dry-run command failed");
+    assert!(stdout.contains("[dry-run] would write"));
+    assert!(stdout.contains("+hello"), "Output did not include a diff");
+    assert!(stdout.contains("[dry-run] would evoke: echo should-not-run"));
+    assert!(!stdout.contains("\nshould-not-run"));
+    assert!(!target.exists(), "Dry-run wrote the file");
+}
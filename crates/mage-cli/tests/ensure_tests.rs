@@ -0,0 +1,249 @@
+mod common;
+
+use common::{mage, temp_dir};
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use std::str;
+
+fn run_script(dir: &Path, source: &str) -> Output {
+    fs::write(dir.join("ensure.mage"), source).unwrap();
+    mage(dir, &["run", "ensure.mage"])
+}
+
+#[test]
+fn test_ensure_file_is_idempotent() {
+    let dir = temp_dir("mage_ensure_file_test");
+    let target = dir.join("nested/app.conf");
+    let source = format!(
+        "conjure first = cast ensure_file(\"{0}\", \"key=value\\n\", \"600\")\n\
+         conjure second = cast ensure_file(\"{0}\", \"key=value\\n\", \"600\")\n\
+         incant \"${{first}} ${{second}}\"\n",
+        target.display()
+    );
+
+    let output = run_script(&dir, &source);
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+
+    assert!(output.status.success(), "ensure_file script failed");
+    assert!(
+        stdout.contains("changed ok"),
+        "Unexpected statuses: {}",
+        stdout
+    );
+    assert_eq!(fs::read_to_string(&target).unwrap(), "key=value\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o600);
+    }
+    assert!(stdout.contains("Summary: 1 ok, 1 changed, 0 failed"));
+}
+
+#[test]
+fn test_ensure_line_in_file_appends_once() {
+    let dir = temp_dir("mage_ensure_line_test");
+    let target = dir.join("profile");
+    fs::write(&target, "export PATH=/bin").unwrap();
+    let source = format!(
+        "cast ensure_line_in_file(\"{0}\", \"export EDITOR=vim\")\n\
+         cast ensure_line_in_file(\"{0}\", \"export EDITOR=vim\")\n",
+        target.display()
+    );
+
+    let output = run_script(&dir, &source);
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+
+    assert!(output.status.success(), "ensure_line_in_file script failed");
+    assert_eq!(
+        fs::read_to_string(&target).unwrap(),
+        "export PATH=/bin\nexport EDITOR=vim\n"
+    );
+    assert!(stdout.contains("Summary: 1 ok, 1 changed, 0 failed"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_ensure_symlink_requires_force_to_replace_files() {
+    let dir = temp_dir("mage_ensure_symlink_test");
+    let source_file = dir.join("bashrc");
+    fs::write(&source_file, "alias ll='ls -l'\n").unwrap();
+    let link = dir.join("link");
+    let occupied = dir.join("occupied");
+    fs::write(&occupied, "keep me\n").unwrap();
+    let source = format!(
+        "cast ensure_symlink(\"{0}\", \"{1}\")\n\
+         cast ensure_symlink(\"{0}\", \"{1}\")\n\
+         cast ensure_symlink(\"{0}\", \"{2}\")\n",
+        source_file.display(),
+        link.display(),
+        occupied.display()
+    );
+
+    let output = run_script(&dir, &source);
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+
+    assert_eq!(fs::read_link(&link).unwrap(), source_file);
+    assert_eq!(fs::read_to_string(&occupied).unwrap(), "keep me\n");
+    assert!(stderr.contains("is not a symlink"), "stderr: {}", stderr);
+    assert!(stdout.contains("Summary: 1 ok, 1 changed, 1 failed"));
+}
+
+#[test]
+fn test_ensure_absent_removes_directories() {
+    let dir = temp_dir("mage_ensure_absent_test");
+    let cache = dir.join("cache");
+    fs::create_dir_all(cache.join("deep")).unwrap();
+    fs::write(cache.join("deep/file"), "stale").unwrap();
+    let source = format!(
+        "conjure first = cast ensure_absent(\"{0}\")\n\
+         conjure second = cast ensure_absent(\"{0}\")\n\
+         incant \"${{first}} ${{second}}\"\n",
+        cache.display()
+    );
+
+    let output = run_script(&dir, &source);
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+
+    assert!(output.status.success(), "ensure_absent script failed");
+    assert!(
+        stdout.contains("changed ok"),
+        "Unexpected statuses: {}",
+        stdout
+    );
+    assert!(!cache.exists());
+}
+
+#[test]
+fn test_summary_is_only_printed_when_resources_were_ensured() {
+    let dir = temp_dir("mage_ensure_no_summary_test");
+
+    let output = run_script(&dir, "incant \"no resources here\"\n");
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert!(
+        !stdout.contains("Summary:"),
+        "Unexpected summary: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_ensure_line_replaces_last_match_and_keeps_backup() {
+    let dir = temp_dir("mage_ensure_line_regex_test");
+    let target = dir.join("gitconfig");
+    let original = "editor = vi\n[core]\neditor = nano\npager = less\n";
+    fs::write(&target, original).unwrap();
+    let source = format!(
+        "conjure first = cast ensure_line(\"{0}\", \"^editor\", \"editor = nvim\", true)\n\
+         conjure second = cast ensure_line(\"{0}\", \"^editor\", \"editor = nvim\", true)\n\
+         incant \"${{first}} ${{second}}\"\n",
+        target.display()
+    );
+
+    let output = run_script(&dir, &source);
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+
+    assert!(output.status.success(), "ensure_line script failed");
+    assert!(
+        stdout.contains("changed ok"),
+        "Unexpected statuses: {}",
+        stdout
+    );
+    assert_eq!(
+        fs::read_to_string(&target).unwrap(),
+        "editor = vi\n[core]\neditor = nvim\npager = less\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("gitconfig.bak")).unwrap(),
+        original
+    );
+}
+
+#[test]
+fn test_ensure_line_appends_when_nothing_matches() {
+    let dir = temp_dir("mage_ensure_line_append_test");
+    let target = dir.join("config");
+    fs::write(&target, "name = mage").unwrap();
+    let source = format!(
+        "cast ensure_line(\"{}\", \"^editor\", \"editor = nvim\")\n",
+        target.display()
+    );
+
+    let output = run_script(&dir, &source);
+
+    assert!(output.status.success(), "ensure_line script failed");
+    assert_eq!(
+        fs::read_to_string(&target).unwrap(),
+        "name = mage\neditor = nvim\n"
+    );
+    assert!(!dir.join("config.bak").exists(), "Backup written unasked");
+}
+
+#[test]
+fn test_ensure_block_writes_and_replaces_marked_block() {
+    let dir = temp_dir("mage_ensure_block_test");
+    let target = dir.join("bashrc");
+    fs::write(&target, "export A=1\n").unwrap();
+    let first = format!(
+        "cast ensure_block(\"{}\", \"aliases\", \"alias ll='ls -la'\")\n",
+        target.display()
+    );
+
+    let output = run_script(&dir, &first);
+    assert!(output.status.success(), "ensure_block script failed");
+    assert_eq!(
+        fs::read_to_string(&target).unwrap(),
+        "export A=1\n# BEGIN mage aliases\nalias ll='ls -la'\n# END mage aliases\n"
+    );
+
+    fs::write(
+        &target,
+        format!("{}export B=2\n", fs::read_to_string(&target).unwrap()),
+    )
+    .unwrap();
+    let second = format!(
+        "cast ensure_block(\"{}\", \"aliases\", \"alias g=git\\nalias l=ls\")\n",
+        target.display()
+    );
+
+    let output = run_script(&dir, &second);
+    assert!(output.status.success(), "ensure_block script failed");
+    assert_eq!(
+        fs::read_to_string(&target).unwrap(),
+        "export A=1\n# BEGIN mage aliases\nalias g=git\nalias l=ls\n# END mage aliases\nexport B=2\n"
+    );
+}
+
+#[test]
+fn test_ensure_block_absent_removes_only_the_block() {
+    let dir = temp_dir("mage_ensure_block_absent_test");
+    let target = dir.join("hosts");
+    let original =
+        "127.0.0.1 localhost\n# BEGIN mage dev\n10.0.0.1 dev\n# END mage dev\n::1 localhost\n";
+    fs::write(&target, original).unwrap();
+    let source = format!(
+        "conjure first = cast ensure_block_absent(\"{0}\", \"dev\", true)\n\
+         conjure second = cast ensure_block_absent(\"{0}\", \"dev\", true)\n\
+         incant \"${{first}} ${{second}}\"\n",
+        target.display()
+    );
+
+    let output = run_script(&dir, &source);
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+
+    assert!(output.status.success(), "ensure_block_absent script failed");
+    assert!(
+        stdout.contains("changed ok"),
+        "Unexpected statuses: {}",
+        stdout
+    );
+    assert_eq!(
+        fs::read_to_string(&target).unwrap(),
+        "127.0.0.1 localhost\n::1 localhost\n"
+    );
+    assert_eq!(fs::read_to_string(dir.join("hosts.bak")).unwrap(), original);
+}
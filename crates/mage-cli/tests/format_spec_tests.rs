@@ -0,0 +1,119 @@
+use mage_core::Value;
+use mage_core::format_spec::{format_string, format_value, is_valid_spec};
+
+fn text(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+#[test]
+fn test_spec_syntax() {
+    for spec in [
+        "", ">8", "*^9", "+", "#x", "08", "+#010b", ".2", "10.3f", ".3s", "e", "X",
+    ] {
+        assert!(is_valid_spec(spec), "Rejected '{}'", spec);
+    }
+    for spec in ["-/root", "8.", ".", "q", "10z", ">>>", "2x3"] {
+        assert!(!is_valid_spec(spec), "Accepted '{}'", spec);
+    }
+}
+
+#[test]
+fn test_alignment_and_fill() {
+    let cases = [
+        (Value::Integer(42), "5", "   42"),
+        (text("ab"), "5", "ab   "),
+        (text("ab"), ">5", "   ab"),
+        (text("ab"), "*^6", "**ab**"),
+        (text("ab"), "-^5", "-ab--"),
+        (text("long"), "2", "long"),
+        (Value::Integer(7), "03", "007"),
+        (Value::Integer(-7), "04", "-007"),
+        (Value::Integer(7), "+", "+7"),
+    ];
+    for (value, spec, expected) in cases {
+        assert_eq!(
+            format_value(&value, spec).unwrap(),
+            expected,
+            "spec '{}'",
+            spec
+        );
+    }
+}
+
+#[test]
+fn test_number_types_and_precision() {
+    let cases = [
+        (Value::Number(1.23456), ".2", "1.23"),
+        (Value::Integer(3), ".1", "3.0"),
+        (Value::Number(-2.5), "+.0f", "-2"),
+        (Value::Number(1234.5), ".2e", "1.23e3"),
+        (Value::Integer(255), "x", "ff"),
+        (Value::Integer(255), "#X", "0xFF"),
+        (Value::Integer(8), "#o", "0o10"),
+        (Value::Integer(5), "#010b", "0b00000101"),
+        (Value::Integer(-255), "x", "-ff"),
+        (Value::Integer(-10), "#06x", "-0x00a"),
+        (Value::Number(4.0), "d", "4"),
+        (text("mage-lang"), ".4", "mage"),
+    ];
+    for (value, spec, expected) in cases {
+        assert_eq!(
+            format_value(&value, spec).unwrap(),
+            expected,
+            "spec '{}'",
+            spec
+        );
+    }
+
+    let err = format_value(&Value::Number(2.5), "x").unwrap_err();
+    assert!(err.contains("requires an integer"), "{}", err);
+    let err = format_value(&text("a"), "f").unwrap_err();
+    assert!(err.contains("requires a number"), "{}", err);
+}
+
+#[test]
+fn test_width_and_precision_are_limited() {
+    let value = Value::Number(1.5);
+    assert_eq!(format_value(&value, "65535").unwrap().len(), 65535);
+    assert!(format_value(&value, ".65535").is_ok());
+
+    for (spec, what) in [
+        (">300000000", "width"),
+        (".65536", "precision"),
+        (".99999999999999999999999", "precision"),
+    ] {
+        assert!(is_valid_spec(spec), "'{}' is well-formed", spec);
+        let err = format_value(&value, spec).unwrap_err();
+        assert!(
+            err.contains(&format!(
+                "Format {} in '{}' must be at most 65535",
+                what, spec
+            )),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn test_format_string_placeholders() {
+    let args = [text("pkg"), Value::Integer(2), Value::Number(0.5)];
+    assert_eq!(
+        format_string("{:<5}|{:>3}|{:.2}", &args).unwrap(),
+        "pkg  |  2|0.50"
+    );
+    assert_eq!(format_string("{1}{0}{1}", &args).unwrap(), "2pkg2");
+    assert_eq!(format_string("{{{}}}", &args).unwrap(), "{pkg}");
+
+    for (template, message) in [
+        ("{3}", "refers to argument 3 but got 3 argument(s)"),
+        ("{", "Unclosed '{'"),
+        ("}", "Unmatched '}'"),
+        ("{x}", "Invalid format field '{x}'"),
+        ("{:q}", "Invalid format specifier 'q'"),
+        ("{:.70000}", "must be at most 65535"),
+    ] {
+        let err = format_string(template, &args).unwrap_err();
+        assert!(err.contains(message), "{}: {}", template, err);
+    }
+}
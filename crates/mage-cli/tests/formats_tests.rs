@@ -0,0 +1,116 @@
+use mage_core::formats::{self, Format};
+
+const DOCUMENT: &str = r#"{
+    "name": "mage",
+    "version": 3,
+    "ratio": 0.5,
+    "stable": true,
+    "tags": ["cli", "scripting"],
+    "deps": {"serde": {"version": "1", "optional": false}}
+}"#;
+
+fn json(text: &str) -> serde_json::Value {
+    serde_json::from_str(text).unwrap()
+}
+
+#[test]
+fn test_round_trip_through_every_format() {
+    let value = formats::parse(Format::Json, DOCUMENT).unwrap();
+    for format in [Format::Json, Format::Toml, Format::Yaml] {
+        let text = formats::serialize(format, &value, true).unwrap();
+        let reparsed = formats::parse(format, &text).unwrap();
+        let back = formats::serialize(Format::Json, &reparsed, false).unwrap();
+        assert_eq!(json(&back), json(DOCUMENT), "{:?} round trip", format);
+    }
+}
+
+#[test]
+fn test_integers_and_floats_keep_their_type() {
+    let value = formats::parse(Format::Yaml, "count: 2\nwhole: 2.0\nratio: 2.5\n").unwrap();
+
+    let toml = formats::serialize(Format::Toml, &value, true).unwrap();
+    assert_eq!(toml, "count = 2\nwhole = 2.0\nratio = 2.5\n");
+
+    let compact = formats::serialize(Format::Json, &value, false).unwrap();
+    assert_eq!(compact, r#"{"count":2,"whole":2.0,"ratio":2.5}"#);
+}
+
+#[test]
+fn test_null_reads_as_none_and_datetimes_as_strings() {
+    let value = formats::parse(Format::Yaml, "a: ~\nb: null\n1: one\n").unwrap();
+    let back = formats::serialize(Format::Json, &value, false).unwrap();
+    assert_eq!(json(&back), json(r#"{"a": null, "b": null, "1": "one"}"#));
+
+    let value = formats::parse(Format::Toml, "at = 1979-05-27T07:32:00Z\n").unwrap();
+    let back = formats::serialize(Format::Json, &value, false).unwrap();
+    assert_eq!(json(&back), json(r#"{"at": "1979-05-27T07:32:00Z"}"#));
+}
+
+#[test]
+fn test_toml_requires_a_top_level_map() {
+    let value = formats::parse(Format::Json, "[1, 2]").unwrap();
+    let err = formats::serialize(Format::Toml, &value, true).unwrap_err();
+    assert!(err.contains("top-level value must be a map"), "{}", err);
+}
+
+#[test]
+fn test_parse_errors_name_the_format() {
+    for (format, text, name) in [
+        (Format::Json, "{", "JSON"),
+        (Format::Toml, "key = ", "TOML"),
+        (Format::Yaml, "a: [", "YAML"),
+    ] {
+        let err = formats::parse(format, text).unwrap_err();
+        assert!(err.starts_with(&format!("Invalid {}", name)), "{}", err);
+    }
+}
+
+#[test]
+fn test_serialization_keeps_insertion_order() {
+    let value = formats::parse(
+        Format::Json,
+        r#"{"zsh": 1, "git": {"b": 2, "a": 3}, "nvim": 4}"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        formats::serialize(Format::Json, &value, false).unwrap(),
+        r#"{"zsh":1,"git":{"b":2,"a":3},"nvim":4}"#
+    );
+    assert_eq!(
+        formats::serialize(Format::Toml, &value, true).unwrap(),
+        "zsh = 1\nnvim = 4\n\n[git]\nb = 2\na = 3\n"
+    );
+    assert_eq!(
+        formats::serialize(Format::Yaml, &value, true).unwrap(),
+        "zsh: 1\ngit:\n  b: 2\n  a: 3\nnvim: 4\n"
+    );
+}
+
+#[test]
+fn test_toml_drops_none_keys() {
+    let value = formats::parse(
+        Format::Json,
+        r#"{"a": 1, "b": null, "c": {"d": null, "e": 2}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        formats::serialize(Format::Toml, &value, true).unwrap(),
+        "a = 1\n\n[c]\ne = 2\n"
+    );
+    assert_eq!(
+        formats::serialize(Format::Yaml, &value, true).unwrap(),
+        "a: 1\nb: null\nc:\n  d: null\n  e: 2\n"
+    );
+}
+
+#[test]
+fn test_toml_rejects_a_top_level_none() {
+    let value = formats::parse(Format::Json, "null").unwrap();
+    let err = formats::serialize(Format::Toml, &value, true).unwrap_err();
+    assert!(err.contains("Cannot convert none to TOML"), "{}", err);
+    assert_eq!(
+        formats::serialize(Format::Json, &value, false).unwrap(),
+        "null"
+    );
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::mage;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn test_undo_restores_journaled_run() {
+    let dir = std::env::temp_dir().join("mage_journal_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(dir.join("config.txt"), "original\n").unwrap();
+    fs::write(
+        dir.join("spell.mage"),
+        "cast write_file(\"config.txt\", \"changed\\n\")\ncast write_file(\"new.txt\", \"new\\n\")\n",
+    )
+    .unwrap();
+
+    let output = mage(&dir, &["run", "--journal", "spell.mage"]);
+    assert!(output.status.success(), "Journaled run failed");
+    assert_eq!(
+        fs::read_to_string(dir.join("config.txt")).unwrap(),
+        "changed\n"
+    );
+
+    let output = mage(&dir, &["undo"]);
+    assert!(output.status.success(), "Undo failed");
+    assert_eq!(
+        fs::read_to_string(dir.join("config.txt")).unwrap(),
+        "original\n"
+    );
+    assert!(!dir.join("new.txt").exists(), "Undo kept a created file");
+}
+
+#[test]
+fn test_undo_removes_created_directories() {
+    let dir = std::env::temp_dir().join("mage_journal_dirs_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(
+        dir.join("spell.mage"),
+        "cast write_file(\"a/b/new.txt\", \"new\\n\")\ncast ensure_directory(\"c/d\")\n",
+    )
+    .unwrap();
+
+    let output = mage(&dir, &["run", "--journal", "spell.mage"]);
+    assert!(output.status.success(), "Journaled run failed");
+    assert!(dir.join("a/b/new.txt").exists());
+    assert!(dir.join("c/d").is_dir());
+
+    let output = mage(&dir, &["undo"]);
+    assert!(output.status.success(), "Undo failed");
+    assert!(!dir.join("a").exists(), "Undo kept a created directory");
+    assert!(!dir.join("c").exists(), "Undo kept a created directory");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_undo_restores_file_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join("mage_journal_mode_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(dir.join("tool.sh"), "echo hi\n").unwrap();
+    fs::set_permissions(dir.join("tool.sh"), fs::Permissions::from_mode(0o644)).unwrap();
+    fs::write(
+        dir.join("spell.mage"),
+        "cast make_executable(\"tool.sh\")\n",
+    )
+    .unwrap();
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    let output = mage(&dir, &["run", "--journal", "spell.mage"]);
+    assert!(output.status.success(), "Journaled run failed");
+    assert_eq!(mode(&dir.join("tool.sh")), 0o755);
+
+    let output = mage(&dir, &["undo"]);
+    assert!(output.status.success(), "Undo failed");
+    assert_eq!(mode(&dir.join("tool.sh")), 0o644);
+}
+
+#[test]
+fn test_undo_removes_edit_backups() {
+    let dir = std::env::temp_dir().join("mage_journal_backup_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(dir.join("app.conf"), "editor = vi\n").unwrap();
+    fs::write(
+        dir.join("spell.mage"),
+        "cast ensure_line(\"app.conf\", \"^editor\", \"editor = nvim\", true)\n",
+    )
+    .unwrap();
+
+    let output = mage(&dir, &["run", "--journal", "spell.mage"]);
+    assert!(output.status.success(), "Journaled run failed");
+    assert!(dir.join("app.conf.bak").exists(), "No backup written");
+
+    let output = mage(&dir, &["undo"]);
+    assert!(output.status.success(), "Undo failed");
+    assert_eq!(
+        fs::read_to_string(dir.join("app.conf")).unwrap(),
+        "editor = vi\n"
+    );
+    assert!(!dir.join("app.conf.bak").exists(), "Undo kept the backup");
+}
+
+#[test]
+fn test_undo_rejects_unknown_run_ids() {
+    let dir = std::env::temp_dir().join("mage_journal_run_id_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join(".mage/journal")).unwrap();
+    fs::create_dir_all(dir.join("precious")).unwrap();
+    fs::write(dir.join("precious/data.txt"), "keep\n").unwrap();
+    // Looks like a journal, so only the run id check keeps it alive
+    fs::write(
+        dir.join("precious/journal.json"),
+        r#"{"run_id": "precious", "started_at": "", "entries": []}"#,
+    )
+    .unwrap();
+
+    let output = mage(&dir, &["undo", "../../precious"]);
+    assert!(!output.status.success(), "Undo accepted a path as run id");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No journal found for run"));
+    assert!(dir.join("precious/data.txt").exists(), "Undo deleted data");
+}
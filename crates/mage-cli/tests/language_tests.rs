@@ -0,0 +1,609 @@
+mod common;
+
+use common::{mage, stdout, temp_dir};
+use std::fs;
+use std::path::PathBuf;
+
+/// A fresh directory holding `spell.mage` with `source`.
+fn spell_dir(name: &str, source: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("spell.mage"), source).unwrap();
+    dir
+}
+
+#[test]
+fn test_list_and_map_literals_parse() {
+    for source in [
+        "conjure a = []",
+        "conjure a = [1,2,3]",
+        "conjure a = [1, 2, 3]",
+        "conjure a = [\n    \"x\",\n    \"y\",\n]",
+        "conjure a = [n + 1, name.upper(), [1, 2]]",
+        "conjure m = {}",
+        "conjure m = {x: 1, \"y-z\": [1, 2],}",
+        "conjure m = {\n    shell: \"zsh\",\n    paths: {bin: \"~/bin\"}\n}",
+    ] {
+        assert!(
+            mage_core::parse_ast(source).is_ok(),
+            "Failed to parse: {}",
+            source
+        );
+    }
+
+    for source in [
+        "conjure a = [1 2]",
+        "conjure a = [,]",
+        "conjure m = {,}",
+        "conjure m = {x 1}",
+    ] {
+        assert!(
+            mage_core::parse_ast(source).is_err(),
+            "Parsed invalid literal: {}",
+            source
+        );
+    }
+}
+
+#[test]
+fn test_list_and_map_literals_evaluate_items() {
+    let dir = spell_dir(
+        "mage_literal_test",
+        "conjure n = 5\n\
+         conjure a = [n, n + 1, \"a,b\", [1, 2]]\n\
+         incant a\n\
+         conjure m = {x: n, \"y-z\": [1,2],}\n\
+         incant m\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(output.status.success(), "Script failed");
+    let stdout = stdout(&output);
+    assert!(stdout.contains("[5, 6, a,b, [1, 2]]"), "{}", stdout);
+    assert!(stdout.contains("x: 5"), "{}", stdout);
+    assert!(stdout.contains("y-z: [1, 2]"), "{}", stdout);
+}
+
+#[test]
+fn test_template_includes_are_relative_to_their_template() {
+    let dir = spell_dir(
+        "mage_template_include_test",
+        "conjure out = cast render_template(\"templates/main.tmpl\", {name: \"ann\"})\nincant out\n",
+    );
+    fs::create_dir_all(dir.join("templates/parts/shared")).unwrap();
+    fs::write(
+        dir.join("templates/main.tmpl"),
+        "main {% include \"parts/part.tmpl\" %}",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("templates/parts/part.tmpl"),
+        "part {% include \"shared/leaf.tmpl\" %}",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("templates/parts/shared/leaf.tmpl"),
+        "leaf {{ name }}",
+    )
+    .unwrap();
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(
+        output.status.success(),
+        "Script failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout(&output).contains("main part leaf ann"));
+}
+
+#[test]
+fn test_template_default_only_replaces_undefined_values() {
+    let dir = spell_dir(
+        "mage_template_default_test",
+        "conjure vars = {zero: 0, off: false, empty: \"\", nil: none}\n\
+         conjure out = cast render_template(\"[{{ zero | default(\\\"x\\\") }}|{{ off | default(\\\"x\\\") }}|{{ empty | default(\\\"x\\\") }}|{{ missing | default(\\\"x\\\") }}|{{ nil | default(\\\"x\\\") }}]\", vars)\n\
+         incant out\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(
+        output.status.success(),
+        "Script failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout(&output).contains("[0|false||x|x]"),
+        "{}",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn test_maps_keep_insertion_order() {
+    let dir = spell_dir(
+        "mage_map_order_test",
+        "conjure tools = {zsh: \"shell\", git: {b: 2, a: 1}, nvim: \"editor\"}\n\
+         incant tools\n\
+         incant tools.keys()\n\
+         incant tools.sort_keys()\n\
+         incant tools.sort_keys(true)\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(output.status.success(), "Script failed");
+    let stdout = stdout(&output);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "{zsh: shell, git: {b: 2, a: 1}, nvim: editor}",
+            "[zsh, git, nvim]",
+            "{git: {b: 2, a: 1}, nvim: editor, zsh: shell}",
+            "{git: {a: 1, b: 2}, nvim: editor, zsh: shell}",
+        ]
+    );
+}
+
+#[test]
+fn test_strict_mode_leaves_shell_variables_to_the_shell() {
+    let dir = spell_dir(
+        "mage_strict_shell_test",
+        "conjure name = \"mage\"\n\
+         evoke \"echo home=$HOME name=$name\"\n\
+         evoke \"for f in a b; do echo item-$f; done\"\n\
+         conjure out = imbue \"echo $HOME\"\n\
+         incant \"imbued ${out}\"\n\
+         invoke { evoke \"echo ${nope}\" } seal (err) { incant \"caught: ${err}\" }\n",
+    );
+
+    let output = mage(&dir, &["run", "--strict", "spell.mage"]);
+    assert!(
+        output.status.success(),
+        "Strict run failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = stdout(&output);
+    let home = std::env::var("HOME").unwrap_or_default();
+    assert!(
+        stdout.contains(&format!("home={} name=mage", home)),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("item-a\nitem-b"), "{}", stdout);
+    assert!(stdout.contains(&format!("imbued {}", home)), "{}", stdout);
+    assert!(
+        stdout.contains("caught: Undefined variable 'nope'"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_strict_mode_rejects_undefined_variables() {
+    let dir = spell_dir("mage_strict_test", "incant \"value: $missing\"\n");
+
+    let output = mage(&dir, &["run", "--strict", "spell.mage"]);
+    assert!(!output.status.success(), "Strict run accepted $missing");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Undefined variable 'missing'"));
+}
+
+#[test]
+fn test_default_operator_only_handles_undefined_variables() {
+    let dir = spell_dir(
+        "mage_strict_default_test",
+        "conjure a = missing ? 7\n\
+         incant \"a=${a}\"\n\
+         enchant boom() {\n    summon \"boom\"\n}\n\
+         conjure c = cast boom() + missing ? 7\n\
+         incant \"c=${c}\"\n",
+    );
+
+    let output = mage(&dir, &["run", "--strict", "spell.mage"]);
+    let stdout = stdout(&output);
+    assert!(!output.status.success(), "Strict run swallowed an error");
+    assert!(stdout.contains("a=7"), "{}", stdout);
+    assert!(!stdout.contains("c=7"), "{}", stdout);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("boom"));
+}
+
+#[test]
+fn test_values_of_different_kinds_compare_unequal() {
+    let dir = spell_dir(
+        "mage_compare_kinds_test",
+        "conjure n = none\n\
+         scry n == \"none\" {\n    incant \"none equals string\"\n}\n\
+         scry 1 == \"1\" {\n    incant \"number equals string\"\n}\n\
+         scry true == \"true\" {\n    incant \"boolean equals string\"\n}\n\
+         scry n == none {\n    incant \"none equals none\"\n}\n\
+         scry n != \"none\" {\n    incant \"none differs from string\"\n}\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(output.status.success(), "Script failed");
+    let stdout = stdout(&output);
+    assert!(!stdout.contains("equals string"), "{}", stdout);
+    assert!(stdout.contains("none equals none"), "{}", stdout);
+    assert!(stdout.contains("none differs from string"), "{}", stdout);
+}
+
+#[test]
+fn test_division_by_zero_raises_for_integers_and_floats() {
+    let dir = spell_dir(
+        "mage_division_by_zero_test",
+        "invoke { incant 10 / 0 } seal (err) { incant \"int: ${err}\" }\n\
+         invoke { incant 10.0 / 0 } seal (err) { incant \"float: ${err}\" }\n\
+         invoke { incant 10.5 // 0.0 } seal (err) { incant \"floor: ${err}\" }\n\
+         invoke { incant 10.5 % 0 } seal (err) { incant \"mod: ${err}\" }\n\
+         incant 10.0 / 4\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(output.status.success(), "Script failed");
+    let stdout = stdout(&output);
+    for prefix in ["int", "float", "floor", "mod"] {
+        assert!(
+            stdout.contains(&format!("{}: Division by zero", prefix)),
+            "{}",
+            stdout
+        );
+    }
+    assert!(stdout.contains("2.5"), "{}", stdout);
+}
+
+#[test]
+fn test_triple_quoted_strings_are_dedented() {
+    let dir = spell_dir(
+        "mage_triple_quoted_test",
+        "conjure name = \"mage\"\n\
+         scry 1 == 1 {\n\
+         \x20   conjure text = \"\"\"\n\
+         \x20       [Unit]\n\
+         \x20         Description=${name}\\tdaemon\n\
+         \n\
+         \x20       End\n\
+         \x20       \"\"\"\n\
+         \x20   cast write_file(\"out.txt\", text)\n\
+         }\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(output.status.success(), "Script failed");
+    assert_eq!(
+        fs::read_to_string(dir.join("out.txt")).unwrap(),
+        "[Unit]\n  Description=mage\tdaemon\n\nEnd\n"
+    );
+}
+
+#[test]
+fn test_raw_strings_keep_backslashes_and_dollars() {
+    let dir = spell_dir(
+        "mage_raw_string_test",
+        "conjure name = \"mage\"\n\
+         incant r\"^v\\d+\\.\\d+$ ${name} \\n\"\n\
+         conjure block = r\"\"\"\n\
+         \x20   a\\n\n\
+         \x20     b $name\n\
+         \x20   \"\"\"\n\
+         cast write_file(\"raw.txt\", block)\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(output.status.success(), "Script failed");
+    assert!(
+        stdout(&output).contains("^v\\d+\\.\\d+$ ${name} \\n"),
+        "{}",
+        stdout(&output)
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("raw.txt")).unwrap(),
+        "a\\n\n  b $name\n"
+    );
+}
+
+#[test]
+fn test_evoke_feeds_stdin() {
+    let dir = spell_dir(
+        "mage_evoke_stdin_test",
+        "conjure name = \"mage\"\n\
+         evoke \"cat\" << \"\"\"\n\
+         \x20   hello ${name}\n\
+         \x20   second line\n\
+         \x20   \"\"\"\n\
+         conjure lines = \"a\\nb\\nc\\n\"\n\
+         evoke \"wc -l\" << lines\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(output.status.success(), "Script failed");
+    let stdout = stdout(&output);
+    assert!(stdout.contains("hello mage\nsecond line"), "{}", stdout);
+    assert!(stdout.contains('3'), "{}", stdout);
+}
+
+#[test]
+fn test_evoke_stdin_ignored_by_command() {
+    // Far more input than a pipe buffers, for a command that reads none of it
+    let dir = spell_dir(
+        "mage_evoke_unread_stdin_test",
+        "conjure big = \"x\"\n\
+         chant i from 1 to 20 {\n\
+         \x20   conjure big = big + big\n\
+         }\n\
+         evoke \"head -c 3\" << big\n\
+         evoke \"true\" << big\n\
+         incant \"after\"\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(
+        output.status.success(),
+        "Script failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = stdout(&output);
+    assert!(stdout.contains("xxx"), "{}", stdout);
+    assert!(!stdout.contains("xxxx"), "{}", stdout);
+    assert!(stdout.contains("after"), "{}", stdout);
+}
+
+#[test]
+fn test_string_methods_refuse_huge_results() {
+    let dir = spell_dir(
+        "mage_string_size_test",
+        "invoke { incant \"ab\".repeat(9223372036854775807) } seal (err) { incant \"repeat: ${err}\" }\n\
+         invoke { incant \"ab\".pad_left(9223372036854775807) } seal (err) { incant \"pad: ${err}\" }\n\
+         invoke { incant \"ab\".pad_right(9223372036854775807, \"é\") } seal (err) { incant \"pad: ${err}\" }\n\
+         incant \"ab\".repeat(3)\n\
+         incant \"ab\".pad_left(4, \"-\")\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(output.status.success(), "Script failed");
+    let stdout = stdout(&output);
+    assert!(
+        stdout.contains("repeat: repeat result would be larger"),
+        "{}",
+        stdout
+    );
+    assert_eq!(stdout.matches("pad: pad_").count(), 2, "{}", stdout);
+    assert!(stdout.contains("ababab"), "{}", stdout);
+    assert!(stdout.contains("--ab"), "{}", stdout);
+}
+
+#[test]
+fn test_split_is_literal_and_split_regex_uses_patterns() {
+    let dir = spell_dir(
+        "mage_split_test",
+        "incant \"1.4.2\".split(\".\")\n\
+         incant \"a  b\\tc\".split_regex(r\"\\s+\")\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(output.status.success(), "Script failed");
+    let stdout = stdout(&output);
+    assert!(stdout.contains("[1, 4, 2]"), "{}", stdout);
+    assert!(stdout.contains("[a, b, c]"), "{}", stdout);
+}
+
+/// Run `source` and return its stdout lines, failing on an unsuccessful run.
+fn run_lines(name: &str, source: &str) -> Vec<String> {
+    let dir = spell_dir(name, source);
+    let output = mage(&dir, &["run", "spell.mage"]);
+    assert!(
+        output.status.success(),
+        "Script failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout(&output).lines().map(str::to_string).collect()
+}
+
+#[test]
+fn test_list_methods() {
+    let lines = run_lines(
+        "mage_list_methods_test",
+        "conjure l = [3, 1, 2]\n\
+         incant l.push(4, 5)\n\
+         incant l.pop()\n\
+         incant l.insert(-1, 9)\n\
+         incant l.remove(0)\n\
+         incant l.sort()\n\
+         incant l.reverse()\n\
+         incant l.slice(1)\n\
+         incant l.concat([7], [8])\n\
+         incant l.zip([\"a\", \"b\"])\n\
+         incant l.join(\"-\")\n\
+         incant \"${l.first()} ${l.last()} ${l.len()}\"\n\
+         incant l\n",
+    );
+    assert_eq!(
+        lines,
+        [
+            "[3, 1, 2, 4, 5]",
+            "[3, 1]",
+            "[3, 1, 9, 2]",
+            "[1, 2]",
+            "[1, 2, 3]",
+            "[2, 1, 3]",
+            "[1, 2]",
+            "[3, 1, 2, 7, 8]",
+            "[[3, a], [1, b]]",
+            "3-1-2",
+            "3 2 3",
+            "[3, 1, 2]",
+        ]
+    );
+}
+
+#[test]
+fn test_list_methods_compare_values_by_kind() {
+    let lines = run_lines(
+        "mage_list_equality_test",
+        "conjure mixed = [1, \"1\", 1.0, 2, none, \"none\"]\n\
+         incant mixed.contains(\"2\")\n\
+         incant mixed.contains(2.0)\n\
+         incant mixed.index_of(\"1\")\n\
+         incant mixed.index_of(\"none\")\n\
+         incant mixed.index_of(none)\n\
+         incant mixed.index_of(true)\n\
+         conjure unique = mixed.unique()\n\
+         incant unique.len()\n\
+         incant unique.index_of(\"1\")\n",
+    );
+    assert_eq!(lines, ["false", "true", "1", "5", "4", "none", "5", "1"]);
+}
+
+#[test]
+fn test_map_methods() {
+    let lines = run_lines(
+        "mage_map_methods_test",
+        "conjure m = {a: 1, b: {c: 2, d: 3}}\n\
+         incant m.get(\"x\", 0)\n\
+         incant m.get(\"x\")\n\
+         incant m.set(\"e\", 5)\n\
+         incant m.remove(\"a\")\n\
+         incant m.merge({b: {d: 4}, f: 6})\n\
+         incant m.entries()\n\
+         incant m\n",
+    );
+    assert_eq!(
+        lines,
+        [
+            "0",
+            "none",
+            "{a: 1, b: {c: 2, d: 3}, e: 5}",
+            "{b: {c: 2, d: 3}}",
+            "{a: 1, b: {c: 2, d: 4}, f: 6}",
+            "[[a, 1], [b, {c: 2, d: 3}]]",
+            "{a: 1, b: {c: 2, d: 3}}",
+        ]
+    );
+}
+
+#[test]
+fn test_method_callbacks() {
+    let lines = run_lines(
+        "mage_method_callbacks_test",
+        "enchant double(x) { bestow x * 2 }\n\
+         enchant add(total, x) { bestow total + x }\n\
+         enchant negate(x) { bestow 0 - x }\n\
+         enchant odd(x) {\n    scry x % 2 == 1 {\n        bestow true\n    }\n    bestow false\n}\n\
+         enchant is_b(k, v) {\n    scry k == \"b\" {\n        bestow true\n    }\n    bestow false\n}\n\
+         enchant boom(x) { summon \"bad item ${x}\" }\n\
+         conjure l = [3, 1, 2]\n\
+         incant l.map(double)\n\
+         incant l.filter(odd)\n\
+         incant l.reduce(add, 10)\n\
+         incant l.sort_by(negate)\n\
+         incant \"${l.any(odd)} ${l.all(odd)}\"\n\
+         conjure m = {a: 1, b: 2}\n\
+         incant m.filter(is_b)\n\
+         conjure f = double\n\
+         incant cast f(21)\n\
+         invoke {\n    incant l.map(boom)\n} seal (err) {\n    incant \"caught: ${err}\"\n}\n",
+    );
+    assert_eq!(
+        lines,
+        [
+            "[6, 2, 4]",
+            "[3, 1]",
+            "16",
+            "[3, 2, 1]",
+            "true false",
+            "{b: 2}",
+            "42",
+            "caught: bad item 3",
+        ]
+    );
+}
+
+#[test]
+fn test_recite_bindings() {
+    let lines = run_lines(
+        "mage_recite_bindings_test",
+        "conjure ports = {http: 80, https: 443}\n\
+         recite name from ports { incant name }\n\
+         recite name, port from ports { incant \"${name}: ${port}\" }\n\
+         conjure hosts = [\"web\", \"db\"]\n\
+         recite i, host from hosts { incant \"${i}. ${host}\" }\n\
+         recite [host, ip] from hosts.zip([\"10.0.0.2\", \"10.0.0.3\"]) {\n    incant \"${host} -> ${ip}\"\n}\n\
+         recite [key, value] from ports.entries() { incant \"${key}=${value}\" }\n\
+         recite i, part from \"a,b\" { incant \"${i}:${part}\" }\n\
+         recite i from 2 { incant i }\n",
+    );
+    assert_eq!(
+        lines,
+        [
+            "http",
+            "https",
+            "http: 80",
+            "https: 443",
+            "0. web",
+            "1. db",
+            "web -> 10.0.0.2",
+            "db -> 10.0.0.3",
+            "http=80",
+            "https=443",
+            "0:a",
+            "1:b",
+            "0",
+            "1",
+        ]
+    );
+}
+
+#[test]
+fn test_recite_rejects_booleans_fractions_and_bad_unpacking() {
+    let lines = run_lines(
+        "mage_recite_errors_test",
+        "invoke {\n    recite x from true { incant x }\n} seal (err) {\n    incant \"bool: ${err}\"\n}\n\
+         invoke {\n    recite x from 2.5 { incant x }\n} seal (err) {\n    incant \"float: ${err}\"\n}\n\
+         invoke {\n    recite [a, b, c] from [[1, 2]] { incant a }\n} seal (err) {\n    incant \"unpack: ${err}\"\n}\n\
+         recite x from 2.0 { incant \"whole ${x}\" }\n",
+    );
+    assert_eq!(
+        lines,
+        [
+            "bool: Cannot recite over a boolean (true)",
+            "float: Cannot recite over 2.5: not a whole number",
+            "unpack: Cannot unpack 2 item(s) into [a, b, c]",
+            "whole 0",
+            "whole 1",
+        ]
+    );
+}
+
+#[test]
+fn test_divine_warns_about_unhandled_known_results() {
+    let dir = spell_dir(
+        "mage_divine_warning_test",
+        "divine cast platform() {\n\
+         \x20   \"linux\" => { incant \"linux\" }\n\
+         \x20   \"macos\" => { incant \"macos\" }\n\
+         \x20   \"windows\" => { incant \"windows\" }\n\
+         }\n\
+         divine cast get_primary_package_manager() {\n\
+         \x20   \"apt\" => { incant \"apt\" }\n\
+         \x20   lest { incant \"other\" }\n\
+         }\n\
+         conjure pm = cast get_primary_package_manager()\n\
+         divine pm {\n\
+         \x20   \"apt\" | \"yum\" | \"dnf\" | \"pacman\" | \"zypper\" | \"emerge\" => { incant \"linux\" }\n\
+         \x20   \"brew\" | \"port\" | \"winget\" | \"choco\" | \"scoop\" => { incant \"other\" }\n\
+         }\n",
+    );
+
+    let output = mage(&dir, &["run", "spell.mage"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("\"freebsd\""), "{}", stderr);
+    assert!(stderr.contains("from platform()"), "{}", stderr);
+    assert!(!stderr.contains("\"linux\""), "{}", stderr);
+    let package_manager_warnings: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.contains("get_primary_package_manager()"))
+        .collect();
+    assert_eq!(package_manager_warnings.len(), 1, "{}", stderr);
+    assert!(
+        package_manager_warnings[0].contains("does not handle \"none\" from"),
+        "{}",
+        stderr
+    );
+}
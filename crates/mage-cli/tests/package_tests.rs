@@ -0,0 +1,1156 @@
+mod common;
+
+use common::{mage, temp_dir};
+use mage_core::cache::PackageCache;
+use mage_core::package::{
+    IntegrityStatus, LockedPackage, PackageLock, PackageResolver, PackageSource, VendorStatus,
+};
+use mage_core::registry::Registry;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(["-c", "user.name=mage", "-c", "user.email=mage@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("Failed to run git");
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// A git package with a commit tagged with each of `tags`, and a `dev`
+/// branch one commit ahead of the last tag.
+fn tagged_repo(name: &str, tags: &[&str]) -> PathBuf {
+    let repo = temp_dir(name);
+    git(&repo, &["init", "--quiet", "--initial-branch", "main"]);
+    for tag in tags {
+        fs::write(
+            repo.join("mage.toml"),
+            format!(
+                "name = \"tool\"\nversion = \"{}\"\n",
+                tag.trim_start_matches('v')
+            ),
+        )
+        .unwrap();
+        git(&repo, &["add", "-A"]);
+        git(&repo, &["commit", "--quiet", "-m", tag]);
+        git(&repo, &["tag", tag]);
+    }
+    git(&repo, &["checkout", "--quiet", "-b", "dev"]);
+    dev_commit(&repo, "dev work");
+    git(&repo, &["checkout", "--quiet", "main"]);
+    repo
+}
+
+/// Add a commit to the `dev` branch and return its hash.
+fn dev_commit(repo: &Path, message: &str) -> String {
+    git(repo, &["checkout", "--quiet", "dev"]);
+    fs::write(repo.join("NOTES"), message).unwrap();
+    git(repo, &["add", "-A"]);
+    git(repo, &["commit", "--quiet", "-m", message]);
+    let commit = git(repo, &["rev-parse", "HEAD"]);
+    git(repo, &["checkout", "--quiet", "main"]);
+    commit
+}
+
+/// A project whose `mage.toml` has `dependencies` (TOML tables) and no
+/// registry or package cache.
+fn project(name: &str, dependencies: &str) -> (PathBuf, PackageResolver) {
+    let dir = temp_dir(name);
+    fs::write(
+        dir.join("mage.toml"),
+        format!("name = \"app\"\nversion = \"0.1.0\"\n\n{}", dependencies),
+    )
+    .unwrap();
+    let resolver = fresh_resolver(&dir);
+    (dir, resolver)
+}
+
+/// A resolver for the project in `dir`. Like a single mage command, each
+/// resolver fetches a git mirror at most once.
+fn fresh_resolver(dir: &Path) -> PackageResolver {
+    PackageResolver::new(dir)
+        .with_registry(None)
+        .with_cache(None)
+}
+
+fn git_dependency(repo: &Path, version: &str) -> String {
+    format!(
+        "[dependencies.tool]\nversion = \"{}\"\nsource = {{ Git = {{ url = \"{}\" }} }}\n",
+        version,
+        repo.display()
+    )
+}
+
+fn read_lock(dir: &Path) -> PackageLock {
+    toml::from_str(&fs::read_to_string(dir.join("mage.lock")).unwrap()).unwrap()
+}
+
+fn locked(dir: &Path, name: &str) -> LockedPackage {
+    read_lock(dir).packages[name].clone()
+}
+
+#[test]
+fn test_constraints_pick_the_newest_matching_tag() {
+    let repo = tagged_repo(
+        "mage_pkg_tags_repo",
+        &["v1.0.0", "v1.4.2", "2.0.0", "nightly"],
+    );
+
+    for (constraint, expected) in [
+        ("^1.2", "1.4.2"),
+        ("~1.0", "1.0.0"),
+        (">=1, <2", "1.4.2"),
+        ("=1.0.0", "1.0.0"),
+        ("*", "2.0.0"),
+        ("latest", "2.0.0"),
+    ] {
+        let (dir, resolver) = project("mage_pkg_tags", &git_dependency(&repo, constraint));
+        resolver.resolve_dependencies().unwrap();
+        let locked = locked(&dir, "tool");
+        assert_eq!(locked.version, expected, "constraint {}", constraint);
+
+        let tag = if expected == "2.0.0" {
+            expected.to_string()
+        } else {
+            format!("v{}", expected)
+        };
+        let commit = git(&repo, &["rev-parse", &format!("{}^{{commit}}", tag)]);
+        assert!(
+            matches!(&locked.source, PackageSource::Git { rev: Some(rev), .. } if *rev == commit),
+            "constraint {} locked {:?}",
+            constraint,
+            locked.source
+        );
+    }
+}
+
+#[test]
+fn test_unsatisfiable_constraints_report_every_requirement() {
+    let repo = tagged_repo("mage_pkg_conflict_repo", &["v1.0.0", "v1.4.2", "v2.0.0"]);
+    let dependencies = format!(
+        "{}\n[dev_dependencies.tool]\nversion = \">=2\"\nsource = {{ Git = {{ url = \"{}\" }} }}\n",
+        git_dependency(&repo, "^1.2"),
+        repo.display()
+    );
+    let (_, resolver) = project("mage_pkg_conflict", &dependencies);
+
+    let error = resolver.resolve_dependencies().unwrap_err();
+    assert!(error.contains("Version conflict for 'tool'"), "{}", error);
+    assert!(error.contains("app requires tool ^1.2"), "{}", error);
+    assert!(error.contains("app (dev) requires tool >=2"), "{}", error);
+    assert!(
+        error.contains("available: 1.0.0, 1.4.2, 2.0.0"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_git_versions_that_are_not_constraints_follow_a_branch() {
+    let repo = tagged_repo("mage_pkg_branch_repo", &["v1.0.0"]);
+    let first = git(&repo, &["rev-parse", "dev"]);
+    let (dir, resolver) = project("mage_pkg_branch", &git_dependency(&repo, "dev"));
+
+    resolver.resolve_dependencies().unwrap();
+    let locked_dev = locked(&dir, "tool");
+    assert_eq!(locked_dev.version, "dev");
+    assert!(
+        matches!(&locked_dev.source, PackageSource::Git { rev: Some(rev), .. } if *rev == first)
+    );
+
+    // Re-resolving keeps the locked commit; updating moves to the branch head
+    let second = dev_commit(&repo, "more dev work");
+    fresh_resolver(&dir).resolve_dependencies().unwrap();
+    assert!(
+        matches!(&locked(&dir, "tool").source, PackageSource::Git { rev: Some(rev), .. } if *rev == first)
+    );
+
+    let updates = fresh_resolver(&dir).update(&["tool".to_string()]).unwrap();
+    assert_eq!(updates.len(), 1);
+    assert!(
+        matches!(&locked(&dir, "tool").source, PackageSource::Git { rev: Some(rev), .. } if *rev == second)
+    );
+}
+
+#[test]
+fn test_other_sources_reject_versions_that_are_not_constraints() {
+    let library = temp_dir("mage_pkg_dev_path_lib");
+    fs::write(
+        library.join("mage.toml"),
+        "name = \"lib\"\nversion = \"1.0.0\"\n",
+    )
+    .unwrap();
+    let (_, resolver) = project(
+        "mage_pkg_dev_path",
+        &format!(
+            "[dependencies.lib]\nversion = \"dev\"\nsource = {{ Path = \"{}\" }}\n",
+            library.display()
+        ),
+    );
+
+    let error = resolver.resolve_dependencies().unwrap_err();
+    assert!(
+        error.contains("Invalid version constraint 'dev'"),
+        "{}",
+        error
+    );
+    assert!(
+        error.contains("only git dependencies may name a branch"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_sources_that_look_like_options_are_rejected() {
+    let marker = std::env::temp_dir().join("mage_pkg_option_url_marker");
+    let _ = fs::remove_file(&marker);
+    for (source, kind) in [
+        (
+            format!(
+                "{{ Git = {{ url = \"--upload-pack=touch {}\" }} }}",
+                marker.display()
+            ),
+            "git URL",
+        ),
+        (
+            "{ Git = { url = \"https://example.com/tool.git\", rev = \"--help\" } }".to_string(),
+            "git revision",
+        ),
+        ("{ Url = \"-o/tmp/x\" }".to_string(), "URL"),
+    ] {
+        let (_, resolver) = project(
+            "mage_pkg_option_url",
+            &format!(
+                "[dependencies.tool]\nversion = \"*\"\nsource = {}\n",
+                source
+            ),
+        );
+        let error = resolver.resolve_dependencies().unwrap_err();
+        assert!(
+            error.contains(&format!("Invalid {} '-", kind)) && error.contains("for tool in"),
+            "{}",
+            error
+        );
+    }
+    assert!(!marker.exists(), "git ran the injected command");
+
+    // So are sources declared by a dependency
+    let library = temp_dir("mage_pkg_option_child_lib");
+    fs::write(
+        library.join("mage.toml"),
+        "name = \"lib\"\nversion = \"1.0.0\"\n\n\
+         [dependencies.tool]\nversion = \"*\"\nsource = { Git = { url = \"--help\" } }\n",
+    )
+    .unwrap();
+    let (_, resolver) = project(
+        "mage_pkg_option_child",
+        &format!(
+            "[dependencies.lib]\nversion = \"*\"\nsource = {{ Path = \"{}\" }}\n",
+            library.display()
+        ),
+    );
+    let error = resolver.resolve_dependencies().unwrap_err();
+    assert!(error.contains("Invalid git URL '--help'"), "{}", error);
+    assert!(error.contains("required by app -> lib 1.0.0"), "{}", error);
+}
+
+#[test]
+fn test_package_names_cannot_escape_the_packages_directory() {
+    let (dir, resolver) = project(
+        "mage_pkg_bad_name",
+        "[dependencies.\"../..\"]\nversion = \"*\"\nsource = { Registry = \"apt\" }\n",
+    );
+    let error = resolver.install_dependencies(false, false).unwrap_err();
+    assert!(error.contains("Invalid package name '../..'"), "{}", error);
+    assert!(dir.join("mage.toml").exists());
+
+    // A tampered lock file is refused before anything is deleted
+    let (dir, resolver) = project("mage_pkg_bad_lock", "");
+    fs::write(
+        dir.join("mage.lock"),
+        "version = \"1\"\n\n\
+         [packages.\"../..\"]\nversion = \"1.0.0\"\nsource = { Path = \"lib\" }\n\
+         dependencies = []\nresolved_at = \"now\"\n\n\
+         [metadata]\ngenerated_at = \"now\"\ngenerator = \"mage\"\n\
+         platform = \"linux\"\nmage_version = \"0.1.0\"\n",
+    )
+    .unwrap();
+    let error = resolver.install_dependencies(false, true).unwrap_err();
+    assert!(error.contains("Invalid package name '../..'"), "{}", error);
+    assert!(dir.join("mage.toml").exists());
+
+    // So are names declared by a dependency
+    let library = temp_dir("mage_pkg_bad_child_lib");
+    fs::write(
+        library.join("mage.toml"),
+        "name = \"lib\"\nversion = \"1.0.0\"\n\n\
+         [dependencies.\"/tmp\"]\nversion = \"*\"\nsource = { Registry = \"apt\" }\n",
+    )
+    .unwrap();
+    let (_, resolver) = project(
+        "mage_pkg_bad_child",
+        &format!(
+            "[dependencies.lib]\nversion = \"*\"\nsource = {{ Path = \"{}\" }}\n",
+            library.display()
+        ),
+    );
+    let error = resolver.resolve_dependencies().unwrap_err();
+    assert!(error.contains("Invalid package name '/tmp'"), "{}", error);
+    assert!(error.contains("required by app -> lib 1.0.0"), "{}", error);
+}
+
+#[test]
+fn test_git_checksums_match_the_installed_clone() {
+    // `export-ignore` leaves files out of `git archive`, but not out of a clone
+    let repo = tagged_repo("mage_pkg_checksum_repo", &["v1.0.0"]);
+    fs::write(repo.join(".gitattributes"), "secret.txt export-ignore\n").unwrap();
+    fs::write(repo.join("secret.txt"), "kept by clones\n").unwrap();
+    fs::create_dir_all(repo.join("bin")).unwrap();
+    fs::write(repo.join("bin/tool"), "#!/bin/sh\n").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("bin/tool", repo.join("tool")).unwrap();
+    git(&repo, &["add", "-A"]);
+    git(&repo, &["commit", "--quiet", "-m", "v1.1.0"]);
+    git(&repo, &["tag", "v1.1.0"]);
+
+    let (dir, resolver) = project("mage_pkg_checksum", &git_dependency(&repo, "^1"));
+    resolver.resolve_dependencies().unwrap();
+    assert!(locked(&dir, "tool").checksum.is_some());
+    resolver.install_dependencies(false, false).unwrap();
+    let checks = resolver.verify_installed().unwrap();
+    assert_eq!(checks[0].status, IntegrityStatus::Verified);
+
+    fs::write(dir.join(".mage/packages/tool/secret.txt"), "tampered\n").unwrap();
+    let checks = resolver.verify_installed().unwrap();
+    assert!(
+        matches!(checks[0].status, IntegrityStatus::Mismatch { .. }),
+        "{:?}",
+        checks[0].status
+    );
+
+    // Vendoring exports the same files from the git mirror
+    let report = fresh_resolver(&dir).vendor().unwrap();
+    assert_eq!(report.packages[0].status, VendorStatus::Vendored);
+    let vendored = PackageCache::new(&dir.join("vendor"))
+        .tree(&locked(&dir, "tool").checksum.unwrap())
+        .unwrap();
+    assert!(vendored.join("secret.txt").exists());
+    #[cfg(unix)]
+    assert_eq!(
+        fs::read_link(vendored.join("tool")).unwrap(),
+        Path::new("bin/tool")
+    );
+}
+
+#[test]
+fn test_frozen_installs_reject_a_changed_revision() {
+    let repo = tagged_repo("mage_pkg_frozen_rev_repo", &["v1.0.0", "v1.4.2"]);
+    let pinned = |rev: &str| {
+        format!(
+            "[dependencies.tool]\nversion = \"*\"\nsource = {{ Git = {{ url = \"{}\", rev = \"{}\" }} }}\n",
+            repo.display(),
+            rev
+        )
+    };
+    let (dir, resolver) = project("mage_pkg_frozen_rev", &pinned("v1.0.0"));
+    resolver.install_dependencies(false, false).unwrap();
+    assert_eq!(
+        locked(&dir, "tool").requested_rev.as_deref(),
+        Some("v1.0.0")
+    );
+    fresh_resolver(&dir)
+        .install_dependencies(false, true)
+        .unwrap();
+
+    fs::write(
+        dir.join("mage.toml"),
+        format!(
+            "name = \"app\"\nversion = \"0.1.0\"\n\n{}",
+            pinned("v1.4.2")
+        ),
+    )
+    .unwrap();
+    let error = fresh_resolver(&dir)
+        .install_dependencies(false, true)
+        .unwrap_err();
+    assert!(
+        error.contains("'tool' is locked at revision v1.0.0, but mage.toml asks for v1.4.2"),
+        "{}",
+        error
+    );
+
+    fresh_resolver(&dir)
+        .install_dependencies(false, false)
+        .unwrap();
+    let locked = locked(&dir, "tool");
+    assert_eq!(locked.requested_rev.as_deref(), Some("v1.4.2"));
+    assert!(
+        matches!(&locked.source, PackageSource::Git { rev: Some(rev), .. }
+            if *rev == git(&repo, &["rev-parse", "v1.4.2"])),
+        "{:?}",
+        locked.source
+    );
+}
+
+#[test]
+fn test_lock_sources_that_look_like_options_are_rejected() {
+    let repo = tagged_repo("mage_pkg_bad_lock_source_repo", &["v1.0.0"]);
+    let (dir, resolver) = project("mage_pkg_bad_lock_source", &git_dependency(&repo, "^1"));
+    resolver.resolve_dependencies().unwrap();
+
+    let mut lock = read_lock(&dir);
+    let package = lock.packages.get_mut("tool").unwrap();
+    package.source = PackageSource::Git {
+        url: repo.display().to_string(),
+        rev: Some("--output=/tmp/mage_pkg_bad_lock_source".to_string()),
+    };
+    fs::write(dir.join("mage.lock"), toml::to_string(&lock).unwrap()).unwrap();
+
+    let error = fresh_resolver(&dir)
+        .install_dependencies(false, true)
+        .unwrap_err();
+    assert!(
+        error.contains("Invalid git revision '--output=") && error.contains("for tool in"),
+        "{}",
+        error
+    );
+    assert!(!dir.join(".mage/packages/tool").exists());
+}
+
+#[test]
+fn test_url_and_system_packages_lock_versions_not_constraints() {
+    let root = temp_dir("mage_pkg_url_fixture");
+    let package = root.join("tool");
+    fs::create_dir_all(&package).unwrap();
+    fs::write(
+        package.join("mage.toml"),
+        "name = \"tool\"\nversion = \"1.3.0\"\n",
+    )
+    .unwrap();
+    let archive = root.join("tool-1.3.0.tar.gz");
+    mage_core::archive::create_archive(&package, &archive).unwrap();
+    let script = root.join("install.sh");
+    fs::write(&script, "echo hi\n").unwrap();
+
+    let dependencies = format!(
+        "[dependencies.tool]\nversion = \"^1, >=1.2\"\nsource = {{ Url = \"{}\" }}\n\n\
+         [dependencies.script]\nversion = \"*\"\nsource = {{ Url = \"{}\" }}\n\n\
+         [dependencies.node]\nversion = \">=18\"\nsource = {{ Registry = \"apt\" }}\n",
+        archive.display(),
+        script.display()
+    );
+    let (dir, resolver) = project("mage_pkg_url", &dependencies);
+    resolver.resolve_dependencies().unwrap();
+    assert_eq!(locked(&dir, "tool").version, "1.3.0");
+    assert_eq!(locked(&dir, "script").version, "unversioned");
+    assert_eq!(locked(&dir, "node").version, "system");
+
+    let (_, resolver) = project(
+        "mage_pkg_url_conflict",
+        &format!(
+            "[dependencies.tool]\nversion = \"^2\"\nsource = {{ Url = \"{}\" }}\n",
+            archive.display()
+        ),
+    );
+    let error = resolver.resolve_dependencies().unwrap_err();
+    assert!(error.contains("available: 1.3.0"), "{}", error);
+}
+
+/// Pack a package named `name` at `version`, with extra `mage.toml` lines,
+/// into `<root>/<name>-<version>.tar.gz`.
+fn url_package(root: &Path, name: &str, version: &str, manifest: &str) -> PathBuf {
+    let package = root.join(name);
+    fs::create_dir_all(&package).unwrap();
+    fs::write(
+        package.join("mage.toml"),
+        format!(
+            "name = \"{}\"\nversion = \"{}\"\n\n{}",
+            name, version, manifest
+        ),
+    )
+    .unwrap();
+    fs::write(package.join("lib.mage"), format!("incant \"{}\"\n", name)).unwrap();
+    let archive = root.join(format!("{}-{}.tar.gz", name, version));
+    mage_core::archive::create_archive(&package, &archive).unwrap();
+    archive
+}
+
+#[test]
+fn test_url_packages_lock_their_own_dependencies() {
+    let root = temp_dir("mage_pkg_url_deps_fixture");
+    let helper = url_package(&root, "helper", "0.2.0", "");
+    let tool = url_package(
+        &root,
+        "tool",
+        "1.0.0",
+        &format!(
+            "[dependencies.helper]\nversion = \"^0.2\"\nsource = {{ Url = \"{}\" }}\n",
+            helper.display()
+        ),
+    );
+    let (dir, resolver) = project(
+        "mage_pkg_url_deps",
+        &format!(
+            "[dependencies.tool]\nversion = \"^1\"\nsource = {{ Url = \"{}\" }}\n",
+            tool.display()
+        ),
+    );
+
+    // The first resolve already sees helper, before anything is installed
+    resolver.resolve_dependencies().unwrap();
+    assert!(!dir.join(".mage/packages/tool").exists());
+    assert_eq!(
+        locked(&dir, "tool").dependencies,
+        vec!["helper".to_string()]
+    );
+    assert_eq!(locked(&dir, "helper").version, "0.2.0");
+
+    resolver.install_dependencies(false, true).unwrap();
+    assert!(dir.join(".mage/packages/tool/lib.mage").exists());
+    assert!(dir.join(".mage/packages/helper/lib.mage").exists());
+}
+
+fn sha256(path: &Path) -> String {
+    let output = Command::new("sha256sum").arg(path).output().unwrap();
+    let digest = String::from_utf8_lossy(&output.stdout);
+    format!("sha256:{}", digest.split_whitespace().next().unwrap())
+}
+
+fn url_project(name: &str, archive: &Path) -> (PathBuf, PackageResolver) {
+    project(
+        name,
+        &format!(
+            "[dependencies.tool]\nversion = \"*\"\nsource = {{ Url = \"{}\" }}\n",
+            archive.display()
+        ),
+    )
+}
+
+#[test]
+fn test_url_packages_lock_archive_and_tree_checksums() {
+    let root = temp_dir("mage_pkg_checksum_fixture");
+    let archive = url_package(&root, "tool", "1.0.0", "");
+    let (dir, resolver) = url_project("mage_pkg_checksum", &archive);
+
+    resolver.resolve_dependencies().unwrap();
+    let tool = locked(&dir, "tool");
+    assert_eq!(tool.checksum, Some(sha256(&archive)));
+    let tree = tool.tree_checksum.clone().expect("no tree checksum");
+    assert!(tree.starts_with("sha256:"));
+    assert_ne!(Some(&tree), tool.checksum.as_ref());
+
+    // The tree checksum is the unpacked files, so it survives repacking
+    fs::remove_file(&archive).unwrap();
+    url_package(&root, "tool", "1.0.0", "");
+    let (dir, resolver) = url_project("mage_pkg_checksum", &archive);
+    resolver.update(&[]).unwrap();
+    assert_eq!(locked(&dir, "tool").tree_checksum, Some(tree));
+}
+
+#[test]
+fn test_verify_checks_the_installed_files_of_url_packages() {
+    let root = temp_dir("mage_pkg_verify_fixture");
+    let archive = url_package(&root, "tool", "1.0.0", "");
+    let (dir, resolver) = url_project("mage_pkg_verify", &archive);
+    resolver.install_dependencies(false, false).unwrap();
+
+    let checks = fresh_resolver(&dir).verify_installed().unwrap();
+    assert_eq!(checks.len(), 1);
+    assert_eq!(checks[0].status, IntegrityStatus::Verified);
+    let output = mage(&dir, &["verify"]);
+    assert!(output.status.success(), "verify failed on a clean install");
+
+    fs::write(
+        dir.join(".mage/packages/tool/lib.mage"),
+        "evoke \"rm -rf ~\"\n",
+    )
+    .unwrap();
+    let checks = fresh_resolver(&dir).verify_installed().unwrap();
+    assert!(
+        matches!(&checks[0].status, IntegrityStatus::Mismatch { expected, .. }
+            if Some(expected) == locked(&dir, "tool").tree_checksum.as_ref()),
+        "{:?}",
+        checks
+    );
+    let output = mage(&dir, &["verify"]);
+    assert!(
+        !output.status.success(),
+        "verify accepted a tampered package"
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("❌ tool"));
+}
+
+#[test]
+fn test_changed_url_content_fails_install_until_updated() {
+    let root = temp_dir("mage_pkg_changed_fixture");
+    let archive = url_package(&root, "tool", "1.0.0", "");
+    let (dir, resolver) = url_project("mage_pkg_changed", &archive);
+    resolver.resolve_dependencies().unwrap();
+    let original = locked(&dir, "tool").checksum;
+
+    // Same URL, new content
+    fs::remove_file(&archive).unwrap();
+    url_package(&root, "tool", "1.0.0", "# rebuilt\n");
+
+    let error = fresh_resolver(&dir)
+        .install_dependencies(false, true)
+        .unwrap_err();
+    assert!(error.contains("Checksum mismatch for tool"), "{}", error);
+    assert!(!dir.join(".mage/packages/tool").exists());
+
+    // Re-resolving keeps the locked download; updating fetches the URL again
+    fresh_resolver(&dir).resolve_dependencies().unwrap();
+    assert_eq!(locked(&dir, "tool").checksum, original);
+
+    let updates = fresh_resolver(&dir).update(&["tool".to_string()]).unwrap();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(locked(&dir, "tool").checksum, Some(sha256(&archive)));
+    fresh_resolver(&dir)
+        .install_dependencies(false, true)
+        .unwrap();
+}
+
+#[test]
+fn test_pkg_new_creates_a_project() {
+    let root = temp_dir("mage_pkg_cli_new");
+
+    let output = mage(&root, &["pkg", "new", "spells"]);
+    assert!(output.status.success(), "{:?}", output);
+    let manifest = fs::read_to_string(root.join("spells/mage.toml")).unwrap();
+    assert!(manifest.contains("name = \"spells\""), "{}", manifest);
+
+    let output = mage(&root, &["pkg", "new", "spells"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+
+    let output = mage(&root, &["pkg", "new", "../escaped"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Invalid package name '../escaped'"),
+        "{:?}",
+        output
+    );
+    assert!(!root.parent().unwrap().join("escaped").exists());
+}
+
+#[test]
+fn test_pkg_add_tree_and_remove() {
+    let library = temp_dir("mage_pkg_cli_lib");
+    fs::write(
+        library.join("mage.toml"),
+        "name = \"lib\"\nversion = \"1.2.0\"\n",
+    )
+    .unwrap();
+    let (dir, _) = project("mage_pkg_cli_add", "");
+
+    let output = mage(
+        &dir,
+        &[
+            "pkg",
+            "add",
+            "lib",
+            "^1",
+            "--path",
+            &library.display().to_string(),
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let output = mage(&dir, &["pkg", "add", "node", "--dev"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(locked(&dir, "lib").version, "1.2.0");
+    assert_eq!(locked(&dir, "node").version, "system");
+
+    let output = mage(&dir, &["pkg", "tree"]);
+    assert!(output.status.success(), "{:?}", output);
+    let tree = String::from_utf8_lossy(&output.stdout);
+    assert!(tree.starts_with("app 0.1.0\n"), "{}", tree);
+    assert!(tree.contains("lib 1.2.0"), "{}", tree);
+    assert!(tree.contains("[dev-dependencies]"), "{}", tree);
+
+    let output = mage(&dir, &["pkg", "remove", "lib"]);
+    assert!(output.status.success(), "{:?}", output);
+    let manifest = fs::read_to_string(dir.join("mage.toml")).unwrap();
+    assert!(!manifest.contains("[dependencies.lib]"), "{}", manifest);
+}
+
+#[test]
+fn test_pkg_add_keeps_the_manifest_when_resolving_fails() {
+    let (dir, _) = project("mage_pkg_cli_add_fails", "");
+    let before = fs::read_to_string(dir.join("mage.toml")).unwrap();
+    let missing = dir.join("missing");
+
+    let output = mage(
+        &dir,
+        &[
+            "pkg",
+            "add",
+            "lib",
+            "--path",
+            &missing.display().to_string(),
+        ],
+    );
+    assert!(!output.status.success());
+    assert_eq!(fs::read_to_string(dir.join("mage.toml")).unwrap(), before);
+
+    let output = mage(&dir, &["pkg", "add", "tool", "--git=--upload-pack=true"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Invalid git URL '--upload-pack=true'"),
+        "{:?}",
+        output
+    );
+    assert_eq!(fs::read_to_string(dir.join("mage.toml")).unwrap(), before);
+}
+
+#[test]
+fn test_registries_refuse_package_names_that_are_paths() {
+    let registry_dir = temp_dir("mage_pkg_bad_registry");
+    let registry = Registry::new(&registry_dir.display().to_string());
+    for name in ["../evil", "a/b", "..", ""] {
+        assert!(registry.index(name).is_err(), "index accepted '{}'", name);
+    }
+
+    let (dir, _) = project("mage_pkg_bad_publish", "");
+    fs::write(
+        dir.join("mage.toml"),
+        "name = \"../evil\"\nversion = \"1.0.0\"\n",
+    )
+    .unwrap();
+    let error = registry.publish(&dir).unwrap_err();
+    assert!(
+        error.contains("Invalid package name '../evil'"),
+        "{}",
+        error
+    );
+    assert!(!registry_dir.join("packages").exists());
+
+    // Dependencies in an index are checked like those in a manifest
+    fs::create_dir_all(registry_dir.join("index")).unwrap();
+    fs::write(
+        registry_dir.join("index/tool.toml"),
+        "[[versions]]\nversion = \"1.0.0\"\narchive = \"tool.tar.gz\"\n\
+         checksum = \"sha256:00\"\npublished_at = \"now\"\n\n\
+         [versions.dependencies.helper]\nversion = \"*\"\n\
+         source = { Git = { url = \"--upload-pack=true\" } }\n",
+    )
+    .unwrap();
+    let error = registry.index("tool").unwrap_err();
+    assert!(
+        error.contains("Invalid git URL '--upload-pack=true'")
+            && error.contains("(required by tool 1.0.0"),
+        "{}",
+        error
+    );
+}
+
+/// A local registry with `tool` published at each of `versions`, each
+/// depending on `helper` 0.2.0 from the same registry.
+fn published_registry(name: &str, versions: &[&str]) -> (PathBuf, Registry) {
+    let root = temp_dir(&format!("{}_fixture", name));
+    let registry_dir = root.join("registry");
+    let registry = Registry::new(&registry_dir.display().to_string());
+    url_package(&root, "helper", "0.2.0", "");
+    registry.publish(&root.join("helper")).unwrap();
+    for version in versions {
+        url_package(
+            &root,
+            "tool",
+            version,
+            "[dependencies.helper]\nversion = \"^0.2\"\nsource = { Registry = \"mage\" }\n",
+        );
+        registry.publish(&root.join("tool")).unwrap();
+    }
+    (registry_dir, registry)
+}
+
+fn registry_project(name: &str, registry: &Registry, version: &str) -> (PathBuf, PackageResolver) {
+    let (dir, _) = project(
+        name,
+        &format!(
+            "[dependencies.tool]\nversion = \"{}\"\nsource = {{ Registry = \"mage\" }}\n",
+            version
+        ),
+    );
+    let resolver = fresh_resolver(&dir).with_registry(Some(registry.clone()));
+    (dir, resolver)
+}
+
+#[test]
+fn test_publish_writes_archives_and_a_sorted_index() {
+    let (registry_dir, registry) = published_registry("mage_pkg_publish", &["1.2.0", "1.0.0"]);
+
+    let index = registry.index("tool").unwrap().expect("tool has no index");
+    let versions: Vec<&str> = index.versions.iter().map(|e| e.version.as_str()).collect();
+    assert_eq!(versions, ["1.0.0", "1.2.0"]);
+    assert!(registry.index("unpublished").unwrap().is_none());
+
+    let entry = registry.entry("tool", "1.2.0").unwrap();
+    assert_eq!(entry.archive, "packages/tool/tool-1.2.0.tar.gz");
+    let archive = PathBuf::from(registry.archive_location(&entry));
+    assert_eq!(archive, registry_dir.join(&entry.archive));
+    assert_eq!(entry.checksum, sha256(&archive));
+    assert!(matches!(
+        &entry.dependencies["helper"].source,
+        PackageSource::Registry(manager) if manager == "mage"
+    ));
+    let error = registry.entry("tool", "9.9.9").unwrap_err();
+    assert!(
+        error.contains("tool 9.9.9 is not in the registry"),
+        "{}",
+        error
+    );
+
+    // The index file reads back the same after another publish rewrites it
+    let on_disk: mage_core::registry::RegistryIndex =
+        toml::from_str(&fs::read_to_string(registry_dir.join("index/tool.toml")).unwrap()).unwrap();
+    assert_eq!(on_disk.versions.len(), 2);
+    assert_eq!(on_disk.versions[1].checksum, entry.checksum);
+    assert_eq!(on_disk.versions[1].published_at, entry.published_at);
+}
+
+#[test]
+fn test_publish_rejects_duplicates_and_unpublishable_manifests() {
+    let (registry_dir, registry) = published_registry("mage_pkg_publish_dup", &["1.0.0"]);
+    let root = registry_dir.parent().unwrap();
+    let index_before = fs::read_to_string(registry_dir.join("index/tool.toml")).unwrap();
+    let archive = registry_dir.join("packages/tool/tool-1.0.0.tar.gz");
+    let archive_before = fs::read(&archive).unwrap();
+
+    url_package(root, "tool", "1.0.0", "# changed\n");
+    let error = registry.publish(&root.join("tool")).unwrap_err();
+    assert!(
+        error.contains("tool 1.0.0 is already published to"),
+        "{}",
+        error
+    );
+    assert_eq!(
+        fs::read_to_string(registry_dir.join("index/tool.toml")).unwrap(),
+        index_before
+    );
+    assert_eq!(fs::read(&archive).unwrap(), archive_before);
+
+    url_package(root, "tool", "next", "");
+    let error = registry.publish(&root.join("tool")).unwrap_err();
+    assert!(error.contains("is not a semantic version"), "{}", error);
+
+    url_package(
+        root,
+        "tool",
+        "1.1.0",
+        "[dependencies.local]\nversion = \"*\"\nsource = { Path = \"../local\" }\n",
+    );
+    let error = registry.publish(&root.join("tool")).unwrap_err();
+    assert!(
+        error.contains("dependency 'local' uses the local path"),
+        "{}",
+        error
+    );
+    assert_eq!(registry.index("tool").unwrap().unwrap().versions.len(), 1);
+}
+
+#[test]
+fn test_packages_resolve_and_install_from_a_local_registry() {
+    let (_, registry) =
+        published_registry("mage_pkg_registry_install", &["1.0.0", "1.3.0", "2.0.0"]);
+    let (dir, resolver) = registry_project("mage_pkg_registry_install", &registry, "^1");
+
+    resolver.install_dependencies(false, false).unwrap();
+    let tool = locked(&dir, "tool");
+    assert_eq!(tool.version, "1.3.0");
+    assert_eq!(
+        tool.checksum,
+        Some(registry.entry("tool", "1.3.0").unwrap().checksum)
+    );
+    assert!(tool.tree_checksum.is_some());
+    assert_eq!(tool.dependencies, vec!["helper".to_string()]);
+    assert_eq!(locked(&dir, "helper").version, "0.2.0");
+    assert!(dir.join(".mage/packages/tool/lib.mage").exists());
+    assert!(dir.join(".mage/packages/helper/lib.mage").exists());
+
+    let checks = fresh_resolver(&dir)
+        .with_registry(Some(registry.clone()))
+        .verify_installed()
+        .unwrap();
+    assert_eq!(checks.len(), 2);
+    assert!(
+        checks
+            .iter()
+            .all(|check| check.status == IntegrityStatus::Verified),
+        "{:?}",
+        checks
+    );
+
+    // A frozen install from the lock alone reproduces the same files
+    fs::remove_dir_all(dir.join(".mage/packages")).unwrap();
+    fresh_resolver(&dir)
+        .with_registry(Some(registry.clone()))
+        .install_dependencies(false, true)
+        .unwrap();
+    assert!(dir.join(".mage/packages/tool/lib.mage").exists());
+
+    let (_, resolver) = registry_project("mage_pkg_registry_install", &registry, "^3");
+    let error = resolver.resolve_dependencies().unwrap_err();
+    assert!(error.contains("tool"), "{}", error);
+}
+
+#[test]
+fn test_publish_command_uses_the_given_registry() {
+    let root = temp_dir("mage_pkg_publish_cli");
+    let registry_dir = root.join("registry");
+    url_package(&root, "tool", "0.3.0", "");
+    let registry_arg = registry_dir.display().to_string();
+
+    let output = mage(
+        &root.join("tool"),
+        &["publish", "--registry", &registry_arg],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("packages/tool/tool-0.3.0.tar.gz"));
+    assert!(
+        registry_dir
+            .join("packages/tool/tool-0.3.0.tar.gz")
+            .exists()
+    );
+
+    let output = mage(
+        &root.join("tool"),
+        &["publish", "--registry", &registry_arg],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already published"));
+}
+
+/// The tree checksum of `dir`, as `mage.lock` records it for a path package.
+fn tree_checksum(dir: &Path) -> String {
+    let (project_dir, resolver) = project(
+        "mage_pkg_tree_checksum",
+        &format!(
+            "[dependencies.tool]\nversion = \"*\"\nsource = {{ Path = \"{}\" }}\n",
+            dir.display()
+        ),
+    );
+    resolver.resolve_dependencies().unwrap();
+    locked(&project_dir, "tool").checksum.unwrap()
+}
+
+#[test]
+fn test_package_cache_stores_and_verifies_entries() {
+    let root = temp_dir("mage_pkg_cache_entries");
+    let archive = url_package(&root, "tool", "1.0.0", "");
+    let file_checksum = sha256(&archive);
+    let tree = root.join("tool");
+    fs::create_dir_all(tree.join(".git")).unwrap();
+    fs::write(tree.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+    let tree_checksum = tree_checksum(&tree);
+
+    let cache = PackageCache::new(&root.join("cache"));
+    assert!(
+        cache.checksums().unwrap().is_empty(),
+        "missing root is empty"
+    );
+    assert!(!cache.contains(&file_checksum));
+    assert!(cache.file(&file_checksum).is_none());
+
+    let stored = cache.store_file(&file_checksum, &archive).unwrap();
+    assert_eq!(fs::read(&stored).unwrap(), fs::read(&archive).unwrap());
+    assert_eq!(cache.file(&file_checksum), Some(stored.clone()));
+    assert!(cache.tree(&file_checksum).is_none(), "a file is not a tree");
+
+    let stored_tree = cache.store_tree(&tree_checksum, &tree).unwrap();
+    assert!(stored_tree.join("lib.mage").exists());
+    assert!(!stored_tree.join(".git").exists());
+    assert_eq!(cache.tree(&tree_checksum), Some(stored_tree.clone()));
+
+    let mut expected = vec![file_checksum.clone(), tree_checksum.clone()];
+    expected.sort();
+    fs::write(cache.root().join("README"), "not an entry").unwrap();
+    assert_eq!(cache.checksums().unwrap(), expected);
+
+    // Damaged entries are still present but never handed out
+    fs::write(&stored, "truncated").unwrap();
+    fs::write(stored_tree.join("lib.mage"), "evoke \"rm -rf ~\"\n").unwrap();
+    assert!(cache.contains(&file_checksum));
+    assert!(cache.file(&file_checksum).is_none());
+    assert!(cache.tree(&tree_checksum).is_none());
+
+    cache.remove(&file_checksum);
+    cache.remove(&tree_checksum);
+    assert!(!cache.contains(&file_checksum));
+    assert!(!cache.contains(&tree_checksum));
+    assert!(cache.checksums().unwrap().is_empty());
+}
+
+#[test]
+fn test_package_cache_refuses_checksums_that_are_not_digests() {
+    let root = temp_dir("mage_pkg_cache_bad");
+    let archive = url_package(&root, "tool", "1.0.0", "");
+    let cache = PackageCache::new(&root.join("cache"));
+
+    for checksum in [
+        "",
+        "sha256",
+        "sha256:",
+        ":abc",
+        "sha256:../../evil",
+        "../x:abc",
+    ] {
+        let error = cache.store_file(checksum, &archive).unwrap_err();
+        assert!(
+            error.contains("Cannot cache a package with checksum"),
+            "{}",
+            error
+        );
+        assert!(!cache.contains(checksum));
+        assert!(cache.file(checksum).is_none());
+    }
+    assert!(!root.join("evil").exists());
+    assert!(cache.checksums().unwrap().is_empty());
+}
+
+#[test]
+fn test_offline_installs_come_from_the_package_cache() {
+    let root = temp_dir("mage_pkg_offline_cache_fixture");
+    let archive = url_package(&root, "tool", "1.0.0", "");
+    let (dir, _) = url_project("mage_pkg_offline_cache", &archive);
+    let cache = PackageCache::new(&root.join("cache"));
+    let with_cache = |offline: bool| {
+        fresh_resolver(&dir)
+            .with_cache(Some(cache.clone()))
+            .with_offline(offline)
+    };
+
+    with_cache(false)
+        .install_dependencies(false, false)
+        .unwrap();
+    let checksum = locked(&dir, "tool").checksum.unwrap();
+    assert!(cache.file(&checksum).is_some());
+
+    // The URL is gone; only the cache has the package now
+    fs::remove_file(&archive).unwrap();
+    fs::remove_dir_all(dir.join(".mage")).unwrap();
+    with_cache(true).install_dependencies(false, true).unwrap();
+    assert!(dir.join(".mage/packages/tool/lib.mage").exists());
+
+    // A damaged entry is dropped instead of installed
+    fs::remove_dir_all(dir.join(".mage")).unwrap();
+    fs::write(cache.file(&checksum).unwrap(), "truncated").unwrap();
+    let error = with_cache(true)
+        .install_dependencies(false, true)
+        .unwrap_err();
+    assert!(
+        error.contains("Cannot install tool offline: it is not in vendor/ or the package cache"),
+        "{}",
+        error
+    );
+    assert!(!cache.contains(&checksum));
+    assert!(!dir.join(".mage/packages/tool").exists());
+
+    let error = fresh_resolver(&dir)
+        .with_offline(true)
+        .install_dependencies(false, true)
+        .unwrap_err();
+    assert!(
+        error.contains("Cannot install tool offline: it is not in vendor/"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_vendored_packages_install_offline() {
+    let repo = tagged_repo("mage_pkg_vendor_repo", &["v1.0.0"]);
+    let root = temp_dir("mage_pkg_vendor_fixture");
+    let archive = url_package(&root, "helper", "0.2.0", "");
+    let local = root.join("local");
+    fs::create_dir_all(&local).unwrap();
+    fs::write(
+        local.join("mage.toml"),
+        "name = \"local\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+    let (dir, resolver) = project(
+        "mage_pkg_vendor",
+        &format!(
+            "{}[dependencies.helper]\nversion = \"*\"\nsource = {{ Url = \"{}\" }}\n\
+             [dependencies.local]\nversion = \"*\"\nsource = {{ Path = \"{}\" }}\n",
+            git_dependency(&repo, "^1"),
+            archive.display(),
+            local.display()
+        ),
+    );
+
+    let report = resolver.vendor().unwrap();
+    let statuses: Vec<(&str, &VendorStatus)> = report
+        .packages
+        .iter()
+        .map(|p| (p.name.as_str(), &p.status))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            ("helper", &VendorStatus::Vendored),
+            ("local", &VendorStatus::Skipped("local path")),
+            ("tool", &VendorStatus::Vendored),
+        ]
+    );
+    let vendor = PackageCache::new(&dir.join("vendor"));
+    assert!(
+        vendor
+            .file(&locked(&dir, "helper").checksum.unwrap())
+            .is_some()
+    );
+    assert!(
+        vendor
+            .tree(&locked(&dir, "tool").checksum.unwrap())
+            .is_some()
+    );
+
+    let report = fresh_resolver(&dir).with_offline(true).vendor().unwrap();
+    assert!(
+        report
+            .packages
+            .iter()
+            .all(|p| p.status != VendorStatus::Vendored)
+    );
+
+    // Neither the git repository nor the URL is needed any more
+    fs::remove_dir_all(&repo).unwrap();
+    fs::remove_file(&archive).unwrap();
+    let _ = fs::remove_dir_all(dir.join(".mage"));
+    fresh_resolver(&dir)
+        .with_offline(true)
+        .install_dependencies(false, true)
+        .unwrap();
+    assert!(dir.join(".mage/packages/tool/mage.toml").exists());
+    assert!(dir.join(".mage/packages/helper/lib.mage").exists());
+    let checks = fresh_resolver(&dir).verify_installed().unwrap();
+    assert!(
+        checks
+            .iter()
+            .all(|check| check.status == IntegrityStatus::Verified),
+        "{:?}",
+        checks
+    );
+
+    // Entries mage.lock no longer has are removed
+    let stale = format!("sha256:{}", "0".repeat(64));
+    vendor.store_file(&stale, &local.join("mage.toml")).unwrap();
+    let report = fresh_resolver(&dir).with_offline(true).vendor().unwrap();
+    assert_eq!(report.removed, 1);
+    assert!(!vendor.contains(&stale));
+    assert_eq!(vendor.checksums().unwrap().len(), 2);
+}
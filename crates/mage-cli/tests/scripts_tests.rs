@@ -0,0 +1,169 @@
+mod common;
+
+use common::{mage, stdout, temp_dir};
+use mage_core::scripts::{mage_invocation, script_steps, shell_quote, split_words};
+use std::fs;
+use std::path::PathBuf;
+
+fn words(command: &str) -> Option<Vec<String>> {
+    split_words(command)
+}
+
+fn owned(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| word.to_string()).collect()
+}
+
+/// A project whose `mage.toml` has the `scripts` table (TOML lines).
+fn project(name: &str, scripts: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(
+        dir.join("mage.toml"),
+        format!(
+            "name = \"app\"\nversion = \"0.1.0\"\n\n[scripts]\n{}",
+            scripts
+        ),
+    )
+    .unwrap();
+    dir
+}
+
+#[test]
+fn test_split_words_honours_quotes_and_escapes() {
+    let cases = [
+        ("mage build.mage", vec!["mage", "build.mage"]),
+        ("  spaced   out\t", vec!["spaced", "out"]),
+        ("'a b' \"c d\"", vec!["a b", "c d"]),
+        ("it\\'s \"say \\\"hi\\\"\"", vec!["it's", "say \"hi\""]),
+        ("'\\n stays'", vec!["\\n stays"]),
+        ("pre'fix'\"ed\"", vec!["prefixed"]),
+        ("'' empty", vec!["", "empty"]),
+        ("", vec![]),
+    ];
+    for (command, expected) in cases {
+        assert_eq!(words(command), Some(owned(&expected)), "{}", command);
+    }
+}
+
+#[test]
+fn test_split_words_rejects_other_shell_syntax() {
+    for command in [
+        "make | tee log",
+        "build && test",
+        "a; b",
+        "run > out",
+        "run < in",
+        "echo $HOME",
+        "echo \"$HOME\"",
+        "echo `date`",
+        "echo \"`date`\"",
+        "(cd x)",
+        "'unbalanced",
+        "\"unbalanced",
+        "trailing\\",
+    ] {
+        assert_eq!(words(command), None, "{}", command);
+    }
+    // Quoted, these are plain text
+    assert_eq!(
+        words("echo '$HOME | x'"),
+        Some(owned(&["echo", "$HOME | x"]))
+    );
+}
+
+#[test]
+fn test_mage_invocation_finds_the_script_file() {
+    let cases = [
+        (
+            "mage scripts/build.mage",
+            Some(("scripts/build.mage", vec![])),
+        ),
+        (
+            "mage run build.mage --release 'two words'",
+            Some(("build.mage", vec!["--release", "two words"])),
+        ),
+        ("mage build", None),
+        ("mage", None),
+        ("npm run build.mage", None),
+        ("mage build.mage | tee log", None),
+    ];
+    for (command, expected) in cases {
+        let expected = expected.map(|(file, args)| (file.to_string(), owned(&args)));
+        assert_eq!(mage_invocation(command), expected, "{}", command);
+    }
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn test_shell_quote_keeps_arguments_whole() {
+    let cases = [
+        ("plain", "plain"),
+        ("--flag=a/b.c", "--flag=a/b.c"),
+        ("", "''"),
+        ("two words", "'two words'"),
+        ("it's", "'it'\\''s'"),
+        ("$HOME;rm", "'$HOME;rm'"),
+    ];
+    for (arg, expected) in cases {
+        assert_eq!(shell_quote(arg), expected, "{}", arg);
+    }
+}
+
+#[test]
+fn test_hooks_run_around_the_script() {
+    let dir = project(
+        "mage_scripts_steps",
+        "build = \"mage build.mage\"\nprebuild = \"echo pre\"\npostbuild = \"echo post\"\n\
+         pretest = \"echo only a hook\"\n",
+    );
+
+    let steps = script_steps(&dir, "build").unwrap();
+    let names: Vec<&str> = steps.iter().map(|step| step.name.as_str()).collect();
+    assert_eq!(names, ["prebuild", "build", "postbuild"]);
+    let takes_args: Vec<bool> = steps.iter().map(|step| step.takes_args).collect();
+    assert_eq!(takes_args, [false, true, false]);
+
+    let error = script_steps(&dir, "test").unwrap_err();
+    assert!(error.contains("Script 'test' is not defined"), "{}", error);
+    assert!(error.contains("available: build, postbuild, prebuild, pretest"));
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn test_run_script_passes_arguments_to_the_script_only() {
+    let dir = project(
+        "mage_scripts_run",
+        "build = \"mage build.mage --fast\"\nprebuild = \"echo pre\"\n\
+         postbuild = \"echo post\"\nshell = \"printf '<%s>'\"\n",
+    );
+    fs::write(
+        dir.join("build.mage"),
+        "recite arg from args {\n    incant \"arg: ${arg}\"\n}\n",
+    )
+    .unwrap();
+
+    let output = mage(&dir, &["run-script", "build", "two words", "--verbose"]);
+    assert!(output.status.success(), "{:?}", output);
+    let lines: Vec<String> = stdout(&output)
+        .lines()
+        .filter(|line| !line.starts_with("▶️"))
+        .map(str::to_string)
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "pre",
+            "arg: --fast",
+            "arg: two words",
+            "arg: --verbose",
+            "post"
+        ]
+    );
+
+    let output = mage(&dir, &["shell", "it's", "$HOME"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout(&output).ends_with("<it's><$HOME>"),
+        "{}",
+        stdout(&output)
+    );
+}
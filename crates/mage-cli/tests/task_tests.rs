@@ -0,0 +1,310 @@
+mod common;
+
+use common::temp_dir;
+use mage_core::RunOptions;
+use mage_core::tasks::{TaskFile, TaskStatus, expand_glob};
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+fn load(source: &str) -> TaskFile {
+    TaskFile::from_source(
+        source.to_string(),
+        Path::new(""),
+        None,
+        &RunOptions::default(),
+    )
+    .unwrap()
+}
+
+fn names(targets: &[&str], file: &TaskFile) -> Result<Vec<String>, String> {
+    let targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+    file.plan(&targets)
+        .map(|plan| plan.iter().map(|t| t.name.clone()).collect())
+}
+
+fn touch(path: &Path, age: u64) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    let file = File::create(path).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(age))
+        .unwrap();
+}
+
+/// Paths under `dir` matching `pattern`, relative to `dir` and sorted.
+fn glob(dir: &Path, pattern: &str) -> Vec<String> {
+    let mut found: Vec<String> = expand_glob(dir, pattern)
+        .unwrap()
+        .iter()
+        .map(|p| p.strip_prefix(dir).unwrap().display().to_string())
+        .collect();
+    found.sort();
+    found
+}
+
+#[test]
+fn test_plan_runs_needed_tasks_first_once() {
+    let file = load(
+        r#"
+ritual build needs ["compile", "assets"] { }
+ritual compile needs "generate" { }
+ritual assets needs "generate" { }
+ritual generate { }
+ritual test needs ["build", "compile"] { }
+"#,
+    );
+
+    assert_eq!(
+        names(&["test"], &file).unwrap(),
+        ["generate", "compile", "assets", "build", "test"]
+    );
+    assert_eq!(
+        names(&["assets", "compile"], &file).unwrap(),
+        ["generate", "assets", "compile"]
+    );
+}
+
+#[test]
+fn test_plan_reports_cycles_and_unknown_tasks() {
+    let file = load(
+        r#"
+ritual a needs "b" { }
+ritual b needs "c" { }
+ritual c needs "a" { }
+ritual lonely needs "missing" { }
+ritual itself needs "itself" { }
+"#,
+    );
+
+    let err = names(&["a"], &file).unwrap_err();
+    assert!(
+        err.contains("Task cycle detected: a -> b -> c -> a"),
+        "{}",
+        err
+    );
+    let err = names(&["itself"], &file).unwrap_err();
+    assert!(err.contains("itself -> itself"), "{}", err);
+
+    let err = names(&["lonely"], &file).unwrap_err();
+    assert!(
+        err.contains("Task 'lonely' needs unknown task 'missing'"),
+        "{}",
+        err
+    );
+    let err = names(&["nope"], &file).unwrap_err();
+    assert!(err.contains("Task 'nope' is not defined"), "{}", err);
+}
+
+#[test]
+fn test_is_up_to_date_compares_inputs_with_outputs() {
+    let dir = temp_dir("mage_task_fresh");
+    let input = dir.join("src/main.c");
+    let output = dir.join("build/app");
+    let file = load(&format!(
+        r#"
+ritual always reads "{src}" {{ }}
+ritual compile reads "{src}" makes "{out}" {{ }}
+"#,
+        src = dir.join("src").display(),
+        out = output.display(),
+    ));
+    let always = file.task("always").unwrap();
+    let compile = file.task("compile").unwrap();
+
+    touch(&input, 100);
+    assert!(!always.is_up_to_date(Path::new("")).unwrap());
+    assert!(
+        !compile.is_up_to_date(Path::new("")).unwrap(),
+        "output is missing"
+    );
+
+    touch(&output, 50);
+    assert!(compile.is_up_to_date(Path::new("")).unwrap());
+
+    touch(&input, 0);
+    assert!(
+        !compile.is_up_to_date(Path::new("")).unwrap(),
+        "input is newer"
+    );
+
+    // A new file under a directory input counts as well.
+    touch(&output, 0);
+    assert!(compile.is_up_to_date(Path::new("")).unwrap());
+    touch(&dir.join("src/extra.c"), 0);
+    File::options()
+        .write(true)
+        .open(&output)
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(10))
+        .unwrap();
+    assert!(!compile.is_up_to_date(Path::new("")).unwrap());
+}
+
+#[test]
+fn test_inputs_that_match_nothing_are_never_up_to_date() {
+    let dir = temp_dir("mage_task_no_inputs");
+    touch(&dir.join("src/main.c"), 100);
+    touch(&dir.join("build/app"), 0);
+    let file = load(
+        r#"
+ritual compile reads ["src/*.c", "gen/*.c"] makes "build/app" { }
+ritual typo reads "src/*.cc" makes "build/app" { }
+ritual fine reads "src/*.c" makes "build/app" { }
+"#,
+    );
+
+    assert!(!file.task("compile").unwrap().is_up_to_date(&dir).unwrap());
+    assert!(!file.task("typo").unwrap().is_up_to_date(&dir).unwrap());
+    assert!(file.task("fine").unwrap().is_up_to_date(&dir).unwrap());
+}
+
+#[test]
+fn test_relative_paths_are_relative_to_the_task_file() {
+    let dir = temp_dir("mage_task_relative");
+    touch(&dir.join("src/main.c"), 100);
+    touch(&dir.join("build/app"), 50);
+    let path = dir.join("tasks.mage");
+    fs::write(
+        &path,
+        "ritual compile reads \"src/**/*.c\" makes \"build/app\" { }\n",
+    )
+    .unwrap();
+
+    // The tests run from the crate directory, not from `dir`
+    let file = TaskFile::load(&path, None, &RunOptions::default()).unwrap();
+    let outcomes = file
+        .run(&["compile".to_string()], 1, &RunOptions::default())
+        .unwrap();
+    assert_eq!(outcomes[0].status, TaskStatus::UpToDate);
+
+    touch(&dir.join("src/main.c"), 0);
+    let outcomes = file
+        .run(&["compile".to_string()], 1, &RunOptions::default())
+        .unwrap();
+    assert_eq!(outcomes[0].status, TaskStatus::Ran);
+}
+
+#[test]
+fn test_expand_glob_matches_segments_and_depths() {
+    let dir = temp_dir("mage_task_glob");
+    for file in [
+        "a.rs",
+        "ab.rs",
+        "abcabc.rs",
+        "notes.txt",
+        "src/lib.rs",
+        "src/deep/mod.rs",
+        "src/deep/x.rs",
+    ] {
+        touch(&dir.join(file), 0);
+    }
+
+    assert_eq!(glob(&dir, "*.rs"), ["a.rs", "ab.rs", "abcabc.rs"]);
+    assert_eq!(glob(&dir, "?.rs"), ["a.rs"]);
+    assert_eq!(glob(&dir, "a*c.rs"), ["abcabc.rs"]);
+    assert_eq!(glob(&dir, "a*b*c.rs"), ["abcabc.rs"]);
+    assert_eq!(glob(&dir, "*.txt"), ["notes.txt"]);
+    assert_eq!(
+        glob(&dir, "**/*.rs"),
+        [
+            "a.rs",
+            "ab.rs",
+            "abcabc.rs",
+            "src/deep/mod.rs",
+            "src/deep/x.rs",
+            "src/lib.rs"
+        ]
+    );
+    assert_eq!(glob(&dir, "src/*/?.rs"), ["src/deep/x.rs"]);
+    assert_eq!(glob(&dir, "src/**/x.rs"), ["src/deep/x.rs"]);
+    assert_eq!(glob(&dir, "src/**").len(), 3);
+    assert_eq!(glob(&dir, "src/deep"), ["src/deep/mod.rs", "src/deep/x.rs"]);
+    assert_eq!(glob(&dir, "notes.txt"), ["notes.txt"]);
+    assert!(glob(&dir, "missing.txt").is_empty());
+    assert!(glob(&dir, "*.md").is_empty());
+    assert!(glob(&dir, "missing/*.rs").is_empty());
+}
+
+#[test]
+fn test_run_skips_up_to_date_tasks() {
+    let dir = temp_dir("mage_task_run_fresh");
+    let input = dir.join("input.txt");
+    let output = dir.join("output.txt");
+    touch(&input, 100);
+    touch(&output, 50);
+    let file = load(&format!(
+        r#"
+ritual build reads "{}" makes "{}" {{ }}
+ritual test needs "build" {{ }}
+"#,
+        input.display(),
+        output.display(),
+    ));
+
+    let outcomes = file
+        .run(&["test".to_string()], 1, &RunOptions::default())
+        .unwrap();
+    let statuses: Vec<_> = outcomes
+        .iter()
+        .map(|o| (o.name.as_str(), o.status))
+        .collect();
+    assert_eq!(
+        statuses,
+        [("build", TaskStatus::UpToDate), ("test", TaskStatus::Ran)]
+    );
+}
+
+#[test]
+fn test_run_stops_starting_tasks_after_a_failure() {
+    let dir = temp_dir("mage_task_run_fail");
+    let marker = |name: &str| dir.join(name);
+    let file = load(&format!(
+        r#"
+ritual broken {{
+    summon "compiler exploded"
+}}
+ritual dependent needs "broken" {{
+    evoke "touch {dependent}"
+}}
+ritual later {{
+    evoke "touch {later}"
+}}
+ritual slow {{
+    evoke "sleep 0.5 && touch {slow}"
+}}
+"#,
+        dependent = marker("dependent").display(),
+        later = marker("later").display(),
+        slow = marker("slow").display(),
+    ));
+
+    // One job at a time: nothing starts after `broken` fails.
+    let err = file
+        .run(
+            &["dependent".to_string(), "later".to_string()],
+            1,
+            &RunOptions::default(),
+        )
+        .unwrap_err();
+    assert!(err.contains("Task 'broken' failed"), "{}", err);
+    assert!(err.contains("compiler exploded"), "{}", err);
+    assert!(!marker("dependent").exists());
+    assert!(!marker("later").exists());
+
+    // A task already running when another fails still finishes.
+    let err = file
+        .run(
+            &[
+                "slow".to_string(),
+                "broken".to_string(),
+                "dependent".to_string(),
+            ],
+            2,
+            &RunOptions::default(),
+        )
+        .unwrap_err();
+    assert!(err.contains("Task 'broken' failed"), "{}", err);
+    assert!(marker("slow").exists());
+    assert!(!marker("dependent").exists());
+}
@@ -0,0 +1,481 @@
+//! Reading and writing `.tar`, `.tar.gz`, `.tar.xz`, `.tar.zst` and `.zip` archives.
+//!
+//! Extraction detects the format from the archive's leading bytes rather than
+//! its name, so a download saved without an extension still unpacks. Entries
+//! that would land outside the destination (absolute paths, `..` components,
+//! links pointing out of it, or paths through a link) make extraction fail
+//! instead of being skipped.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarXz,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detect the format of the archive at `path` from its magic bytes.
+    /// Returns `None` for files that are not a supported archive.
+    pub fn detect(path: &Path) -> Result<Option<Self>, String> {
+        let mut header = Vec::with_capacity(262);
+        File::open(path)
+            .and_then(|file| file.take(262).read_to_end(&mut header))
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok(Self::from_magic(&header))
+    }
+
+    fn from_magic(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::TarXz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::TarZst)
+        } else if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if header.get(257..262) == Some(b"ustar") {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// Pick the format for a new archive from its file name.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        [
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar.xz", Self::TarXz),
+            (".txz", Self::TarXz),
+            (".tar.zst", Self::TarZst),
+            (".tzst", Self::TarZst),
+            (".zip", Self::Zip),
+            (".tar", Self::Tar),
+        ]
+        .into_iter()
+        .find(|(extension, _)| name.ends_with(extension))
+        .map(|(_, format)| format)
+    }
+}
+
+/// Extract `archive` into `dest`, dropping the first `strip_components`
+/// directories of every entry like `tar --strip-components`.
+/// Returns the extracted files relative to `dest`.
+pub fn extract_archive(
+    archive: &Path,
+    dest: &Path,
+    strip_components: usize,
+) -> Result<Vec<PathBuf>, String> {
+    let format = ArchiveFormat::detect(archive)?
+        .ok_or_else(|| format!("{} is not a supported archive", archive.display()))?;
+    fs::create_dir_all(dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+
+    let mut extracted = Vec::new();
+    match format {
+        ArchiveFormat::Zip => extract_zip(archive, dest, strip_components, &mut extracted)?,
+        _ => extract_tar(
+            decompress(archive, format)?,
+            dest,
+            strip_components,
+            &mut extracted,
+        )?,
+    }
+    Ok(extracted)
+}
+
+/// The entry names of `archive`, in archive order.
+pub fn archive_entries(archive: &Path) -> Result<Vec<String>, String> {
+    let format = ArchiveFormat::detect(archive)?
+        .ok_or_else(|| format!("{} is not a supported archive", archive.display()))?;
+    let fail = |e: &dyn std::fmt::Display| format!("Failed to read {}: {}", archive.display(), e);
+
+    if format == ArchiveFormat::Zip {
+        let file = File::open(archive).map_err(|e| fail(&e))?;
+        let zip = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| fail(&e))?;
+        return Ok(zip.file_names().map(str::to_string).collect());
+    }
+
+    let mut tar = tar::Archive::new(decompress(archive, format)?);
+    let mut names = Vec::new();
+    for entry in tar.entries().map_err(|e| fail(&e))? {
+        let entry = entry.map_err(|e| fail(&e))?;
+        names.push(
+            entry
+                .path()
+                .map_err(|e| fail(&e))?
+                .to_string_lossy()
+                .to_string(),
+        );
+    }
+    Ok(names)
+}
+
+/// Pack `source` (a file or directory) into `archive`, choosing the format
+/// from the archive's extension. Entries are stored under the source's own
+/// name, so extracting with one stripped component yields its contents.
+/// Returns the number of files written.
+pub fn create_archive(source: &Path, archive: &Path) -> Result<usize, String> {
+    let format = ArchiveFormat::from_extension(archive).ok_or_else(|| {
+        format!(
+            "Cannot tell the archive format of {}; use .tar.gz, .tar.xz, .tar.zst, .zip or .tar",
+            archive.display()
+        )
+    })?;
+    let root_name = source
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.file_name().map(|name| name.to_os_string()))
+        .ok_or_else(|| format!("Cannot archive {}: path not found", source.display()))?;
+
+    let mut entries = Vec::new();
+    for entry in walkdir::WalkDir::new(source).sort_by_file_name() {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+        let relative = entry.path().strip_prefix(source).unwrap_or(entry.path());
+        let name: String = Path::new(&root_name)
+            .join(relative)
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        entries.push((entry.path().to_path_buf(), name, entry.file_type()));
+    }
+
+    let file = File::create(archive)
+        .map_err(|e| format!("Failed to create {}: {}", archive.display(), e))?;
+    let fail = |e: io::Error| format!("Failed to write {}: {}", archive.display(), e);
+    match format {
+        ArchiveFormat::Zip => write_zip(file, &entries)
+            .map_err(|e| format!("Failed to write {}: {}", archive.display(), e))?,
+        ArchiveFormat::Tar => {
+            write_tar(file, &entries).map_err(fail)?;
+        }
+        ArchiveFormat::TarGz => {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            write_tar(encoder, &entries)
+                .and_then(|encoder| encoder.finish())
+                .map_err(fail)?;
+        }
+        ArchiveFormat::TarXz => {
+            write_tar(xz2::write::XzEncoder::new(file, 6), &entries)
+                .and_then(|encoder| encoder.finish())
+                .map_err(fail)?;
+        }
+        ArchiveFormat::TarZst => {
+            let encoder = zstd::Encoder::new(file, 0).map_err(fail)?;
+            write_tar(encoder, &entries)
+                .and_then(|encoder| encoder.finish())
+                .map_err(fail)?;
+        }
+    }
+
+    Ok(entries
+        .iter()
+        .filter(|(_, _, file_type)| !file_type.is_dir())
+        .count())
+}
+
+fn decompress(archive: &Path, format: ArchiveFormat) -> Result<Box<dyn Read>, String> {
+    let file = BufReader::new(
+        File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?,
+    );
+    Ok(match format {
+        ArchiveFormat::TarGz => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        ArchiveFormat::TarXz => Box::new(xz2::read::XzDecoder::new(file)),
+        ArchiveFormat::TarZst => Box::new(
+            zstd::Decoder::with_buffer(file)
+                .map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?,
+        ),
+        ArchiveFormat::Tar | ArchiveFormat::Zip => Box::new(file),
+    })
+}
+
+fn extract_tar(
+    reader: Box<dyn Read>,
+    dest: &Path,
+    strip: usize,
+    extracted: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let fail = |e: io::Error| format!("Failed to extract archive: {}", e);
+    let mut archive = tar::Archive::new(reader);
+    let mut links = Vec::new();
+    for entry in archive.entries().map_err(fail)? {
+        let mut entry = entry.map_err(fail)?;
+        let name = entry.path().map_err(fail)?.to_string_lossy().to_string();
+        let Some(relative) = entry_path(&name, strip)? else {
+            continue;
+        };
+        check_parents(&name, dest, &relative)?;
+        let target = dest.join(&relative);
+
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => {
+                fs::create_dir_all(&target).map_err(fail)?;
+                continue;
+            }
+            tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::GNUSparse => {
+                create_parent(&target)?;
+                remove_symlink(&target);
+                entry.unpack(&target).map_err(fail)?;
+            }
+            tar::EntryType::Symlink => {
+                let link = entry
+                    .link_name()
+                    .map_err(fail)?
+                    .unwrap_or_default()
+                    .into_owned();
+                check_link(&name, &relative, &link)?;
+                create_parent(&target)?;
+                let _ = fs::remove_file(&target);
+                entry.unpack(&target).map_err(fail)?;
+                links.push((name, relative.clone(), link));
+            }
+            tar::EntryType::Link => {
+                let link = entry.link_name().map_err(fail)?.unwrap_or_default();
+                let Some(source) = entry_path(&link.to_string_lossy(), strip)? else {
+                    return Err(format!(
+                        "Refusing to extract '{}': it links to '{}', which was stripped",
+                        name,
+                        link.display()
+                    ));
+                };
+                check_parents(&name, dest, &source)?;
+                if is_symlink(&dest.join(&source)) {
+                    return Err(format!(
+                        "Refusing to extract '{}': it links to the symlink '{}'",
+                        name,
+                        link.display()
+                    ));
+                }
+                create_parent(&target)?;
+                remove_symlink(&target);
+                fs::copy(dest.join(source), &target).map_err(fail)?;
+            }
+            // Devices, FIFOs and metadata entries have nothing to write
+            _ => continue,
+        }
+        extracted.push(relative);
+    }
+    check_link_chains(dest, &links)
+}
+
+fn extract_zip(
+    archive: &Path,
+    dest: &Path,
+    strip: usize,
+    extracted: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let fail =
+        |e: &dyn std::fmt::Display| format!("Failed to extract {}: {}", archive.display(), e);
+    let file = File::open(archive).map_err(|e| fail(&e))?;
+    let mut zip = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| fail(&e))?;
+
+    let mut links = Vec::new();
+    for index in 0..zip.len() {
+        let mut file = zip.by_index(index).map_err(|e| fail(&e))?;
+        let name = file.name().to_string();
+        let Some(relative) = entry_path(&name, strip)? else {
+            continue;
+        };
+        check_parents(&name, dest, &relative)?;
+        let target = dest.join(&relative);
+        let mode = file.unix_mode();
+
+        if file.is_dir() {
+            fs::create_dir_all(&target).map_err(|e| fail(&e))?;
+            continue;
+        }
+
+        create_parent(&target)?;
+        if mode.is_some_and(|mode| mode & 0o170000 == 0o120000) {
+            let mut link = String::new();
+            file.read_to_string(&mut link).map_err(|e| fail(&e))?;
+            check_link(&name, &relative, Path::new(&link))?;
+            let _ = fs::remove_file(&target);
+            make_symlink(Path::new(&link), &target).map_err(|e| fail(&e))?;
+            links.push((name, relative.clone(), PathBuf::from(link)));
+        } else {
+            // Replace a link at the target rather than write through it
+            remove_symlink(&target);
+            let mut out = File::create(&target).map_err(|e| fail(&e))?;
+            io::copy(&mut file, &mut out).map_err(|e| fail(&e))?;
+            #[cfg(unix)]
+            if let Some(mode) = mode {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&target, fs::Permissions::from_mode(mode & 0o777))
+                    .map_err(|e| fail(&e))?;
+            }
+        }
+        extracted.push(relative);
+    }
+    check_link_chains(dest, &links)
+}
+
+/// The path an entry extracts to, relative to the destination, or `None`
+/// when stripping removes it entirely.
+fn entry_path(name: &str, strip: usize) -> Result<Option<PathBuf>, String> {
+    let mut parts = Vec::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => parts.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(format!(
+                    "Refusing to extract '{}': path escapes the destination",
+                    name
+                ));
+            }
+        }
+    }
+    Ok((parts.len() > strip).then(|| parts[strip..].iter().collect()))
+}
+
+/// Reject a symlink at `relative` whose target resolves outside the destination.
+fn check_link(name: &str, relative: &Path, link: &Path) -> Result<(), String> {
+    let mut depth = relative.components().count().saturating_sub(1);
+    for component in link.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => {
+                return Err(format!(
+                    "Refusing to extract '{}': it links to '{}', outside the destination",
+                    name,
+                    link.display()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reject an entry at `relative` below a symlink. Writing there would follow
+/// the link, and links made earlier in the same archive can chain their way
+/// out of the destination even when each one looks harmless on its own.
+fn check_parents(name: &str, dest: &Path, relative: &Path) -> Result<(), String> {
+    let mut path = dest.to_path_buf();
+    let mut parts = relative.components().peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            break;
+        }
+        path.push(part);
+        if is_symlink(&path) {
+            return Err(format!(
+                "Refusing to extract '{}': {} is a symlink",
+                name,
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Reject extracted symlinks whose target passes through another symlink,
+/// where a `..` no longer means what [`check_link`] assumed. The offending
+/// link is removed.
+fn check_link_chains(dest: &Path, links: &[(String, PathBuf, PathBuf)]) -> Result<(), String> {
+    for (name, relative, link) in links {
+        let mut path = dest.join(relative);
+        path.pop();
+        let mut parts = link.components().peekable();
+        while let Some(part) = parts.next() {
+            match part {
+                Component::ParentDir => {
+                    path.pop();
+                }
+                Component::Normal(part) => {
+                    path.push(part);
+                    if parts.peek().is_some() && is_symlink(&path) {
+                        let _ = fs::remove_file(dest.join(relative));
+                        return Err(format!(
+                            "Refusing to extract '{}': it links to '{}' through the symlink {}",
+                            name,
+                            link.display(),
+                            path.display()
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink())
+}
+
+fn remove_symlink(path: &Path) {
+    if is_symlink(path) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn create_parent(path: &Path) -> Result<(), String> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn make_symlink(link: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(link, target)
+}
+
+#[cfg(windows)]
+fn make_symlink(link: &Path, target: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(link, target)
+}
+
+type ArchiveEntry = (PathBuf, String, fs::FileType);
+
+fn write_tar<W: Write>(writer: W, entries: &[ArchiveEntry]) -> io::Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+    for (path, name, file_type) in entries {
+        if file_type.is_dir() {
+            builder.append_dir(name, path)?;
+        } else {
+            builder.append_path_with_name(path, name)?;
+        }
+    }
+    builder.into_inner()
+}
+
+fn write_zip(file: File, entries: &[ArchiveEntry]) -> zip::result::ZipResult<()> {
+    use zip::write::SimpleFileOptions;
+
+    let mut zip = zip::ZipWriter::new(file);
+    for (path, name, file_type) in entries {
+        let mut options = SimpleFileOptions::default();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::symlink_metadata(path)?.permissions().mode();
+            options = options.unix_permissions(mode & 0o777);
+        }
+
+        if file_type.is_dir() {
+            zip.add_directory(name.as_str(), options)?;
+        } else if file_type.is_symlink() {
+            let link = fs::read_link(path)?;
+            zip.add_symlink(name.as_str(), link.to_string_lossy(), options)?;
+        } else {
+            zip.start_file(name.as_str(), options)?;
+            io::copy(&mut File::open(path)?, &mut zip)?;
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
@@ -1,4 +1,7 @@
+use crate::formats::{self, Format};
+use crate::interpreter::ExprValue;
 use crate::output::OutputCollector;
+use crate::{ensure, file_edit, format_spec, template};
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
@@ -54,7 +57,7 @@ pub fn call_builtin(
             if args.len() != 1 {
                 return Err("ensure_directory() requires exactly 1 argument: path".to_string());
             }
-            ensure_directory(&args[0])
+            ensure_directory(&args[0], output)
         }
         "copy_file" => {
             if args.len() != 2 {
@@ -62,37 +65,37 @@ pub fn call_builtin(
                     "copy_file() requires exactly 2 arguments: source, destination".to_string(),
                 );
             }
-            copy_file(&args[0], &args[1])
+            copy_file(&args[0], &args[1], output)
         }
         "write_file" => {
             if args.len() != 2 {
                 return Err("write_file() requires exactly 2 arguments: path, content".to_string());
             }
-            write_file(&args[0], &args[1])
+            write_file(&args[0], &args[1], output)
         }
         "remove_file" => {
             if args.len() != 1 {
                 return Err("remove_file() requires exactly 1 argument: path".to_string());
             }
-            remove_file(&args[0])
+            remove_file(&args[0], output)
         }
         "remove_directory" => {
             if args.len() != 1 {
                 return Err("remove_directory() requires exactly 1 argument: path".to_string());
             }
-            remove_directory(&args[0])
+            remove_directory(&args[0], output)
         }
         "symlink" => {
             if args.len() != 2 {
                 return Err("symlink() requires exactly 2 arguments: source, target".to_string());
             }
-            create_symlink(&args[0], &args[1])
+            create_symlink(&args[0], &args[1], output)
         }
         "make_executable" => {
             if args.len() != 1 {
                 return Err("make_executable() requires exactly 1 argument: path".to_string());
             }
-            make_executable(&args[0])
+            make_executable(&args[0], output)
         }
         "is_executable" => {
             if args.len() != 1 {
@@ -101,6 +104,76 @@ pub fn call_builtin(
             Ok(BuiltinValue::Boolean(is_executable(&args[0])))
         }
 
+        // Idempotent Resources
+        "ensure_file" => {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(
+                    "ensure_file() requires 2-3 arguments: path, content, [mode]".to_string(),
+                );
+            }
+            ensure::ensure_file(&args[0], &args[1], args.get(2).map(|s| s.as_str()), output)
+        }
+        "ensure_symlink" => {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(
+                    "ensure_symlink() requires 2-3 arguments: source, target, [force]".to_string(),
+                );
+            }
+            let force = args.get(2).is_some_and(|f| f == "true");
+            ensure::ensure_symlink(&args[0], &args[1], force, output)
+        }
+        "ensure_line_in_file" => {
+            if args.len() != 2 {
+                return Err(
+                    "ensure_line_in_file() requires exactly 2 arguments: path, line".to_string(),
+                );
+            }
+            ensure::ensure_line_in_file(&args[0], &args[1], output)
+        }
+        "ensure_absent" => {
+            if args.len() != 1 {
+                return Err("ensure_absent() requires exactly 1 argument: path".to_string());
+            }
+            ensure::ensure_absent(&args[0], output)
+        }
+        "ensure_package" => {
+            if args.len() != 1 {
+                return Err(
+                    "ensure_package() requires exactly 1 argument: package_name".to_string()
+                );
+            }
+            ensure::ensure_package(&args[0], output)
+        }
+        "ensure_line" => {
+            if args.len() < 3 || args.len() > 4 {
+                return Err(
+                    "ensure_line() requires 3-4 arguments: path, pattern, line, [backup]"
+                        .to_string(),
+                );
+            }
+            let backup = args.get(3).is_some_and(|b| b == "true");
+            file_edit::ensure_line(&args[0], &args[1], &args[2], backup, output)
+        }
+        "ensure_block" => {
+            if args.len() < 3 || args.len() > 4 {
+                return Err(
+                    "ensure_block() requires 3-4 arguments: path, id, content, [backup]"
+                        .to_string(),
+                );
+            }
+            let backup = args.get(3).is_some_and(|b| b == "true");
+            file_edit::ensure_block(&args[0], &args[1], &args[2], backup, output)
+        }
+        "ensure_block_absent" => {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(
+                    "ensure_block_absent() requires 2-3 arguments: path, id, [backup]".to_string(),
+                );
+            }
+            let backup = args.get(2).is_some_and(|b| b == "true");
+            file_edit::ensure_block_absent(&args[0], &args[1], backup, output)
+        }
+
         // Package Management
         "detect_package_managers" => Ok(BuiltinValue::Array(detect_package_managers())),
         "get_primary_package_manager" => Ok(BuiltinValue::String(get_primary_package_manager())),
@@ -156,8 +229,12 @@ pub fn call_builtin(
             package_remove(&args[0], output)
         }
         "package_install" => {
-            let dev = args.first().map(|s| s == "--dev").unwrap_or(false);
-            package_install_deps(dev, output)
+            let dev = args.iter().any(|arg| arg == "--dev");
+            let frozen = args
+                .iter()
+                .any(|arg| arg == "--frozen" || arg == "--locked");
+            let offline = args.iter().any(|arg| arg == "--offline");
+            package_install_deps(dev, frozen, offline, output)
         }
         "package_list" => Ok(BuiltinValue::String(package_list())),
         "package_info" => {
@@ -172,7 +249,33 @@ pub fn call_builtin(
             if args.len() != 2 {
                 return Err("download() requires exactly 2 arguments: url, path".to_string());
             }
-            download_file(&args[0], &args[1])
+            download_file(&args[0], &args[1], output)
+        }
+        "extract_archive" => {
+            if args.len() != 2 && args.len() != 3 {
+                return Err(
+                    "extract_archive() requires 2-3 arguments: archive, destination, [strip_components]"
+                        .to_string(),
+                );
+            }
+            let strip = match args.get(2) {
+                Some(strip) => strip.trim().parse::<usize>().map_err(|_| {
+                    format!(
+                        "extract_archive() strip_components must be a non-negative integer, got '{}'",
+                        strip
+                    )
+                })?,
+                None => 0,
+            };
+            extract_archive(&args[0], &args[1], strip, output)
+        }
+        "create_archive" => {
+            if args.len() != 2 {
+                return Err(
+                    "create_archive() requires exactly 2 arguments: source, archive".to_string(),
+                );
+            }
+            create_archive(&args[0], &args[1], output)
         }
         "search_package" => {
             if args.len() != 1 {
@@ -232,7 +335,7 @@ pub fn call_builtin(
             if args.len() < 2 {
                 return Err("from_package() requires at least 2 arguments: package_name, command, [args...]".to_string());
             }
-            from_package(&args[0], &args[1], &args[2..])
+            from_package(&args[0], &args[1], &args[2..], output)
         }
 
         _ => Err(format!("Unknown builtin function: {}", name)),
@@ -256,6 +359,14 @@ pub fn is_builtin(name: &str) -> bool {
             | "write_file"
             | "remove_file"
             | "remove_directory"
+            | "ensure_file"
+            | "ensure_symlink"
+            | "ensure_line_in_file"
+            | "ensure_absent"
+            | "ensure_package"
+            | "ensure_line"
+            | "ensure_block"
+            | "ensure_block_absent"
             | "detect_package_managers"
             | "get_primary_package_manager"
             | "package_manager_available"
@@ -270,12 +381,209 @@ pub fn is_builtin(name: &str) -> bool {
             | "package_list"
             | "package_info"
             | "download"
+            | "extract_archive"
+            | "create_archive"
             | "env_var"
             | "from_package"
     )
 }
 
+/// Builtins that take and return structured values (lists and maps) rather than strings.
+pub fn call_value_builtin(
+    name: &str,
+    args: Vec<ExprValue>,
+    output: &mut OutputCollector,
+) -> Result<ExprValue, String> {
+    match name {
+        "render_template" => {
+            if args.len() != 2 {
+                return Err(
+                    "render_template() requires exactly 2 arguments: template, variables"
+                        .to_string(),
+                );
+            }
+            template::render_template(&args[0].to_display_string(), &args[1]).map(ExprValue::String)
+        }
+
+        // Optional values
+        "is_none" => {
+            if args.len() != 1 {
+                return Err("is_none() requires exactly 1 argument: value".to_string());
+            }
+            Ok(ExprValue::Boolean(matches!(args[0], ExprValue::None)))
+        }
+        "or_else" => {
+            if args.len() != 2 {
+                return Err("or_else() requires exactly 2 arguments: value, default".to_string());
+            }
+            let mut args = args.into_iter();
+            let value = args.next().unwrap();
+            let default = args.next().unwrap();
+            Ok(match value {
+                ExprValue::None => default,
+                value => value,
+            })
+        }
+
+        // Numbers
+        "int" => {
+            if args.len() != 1 {
+                return Err("int() requires exactly 1 argument: value".to_string());
+            }
+            to_int(&args[0]).map(ExprValue::Integer)
+        }
+        "float" => {
+            if args.len() != 1 {
+                return Err("float() requires exactly 1 argument: value".to_string());
+            }
+            to_float(&args[0]).map(ExprValue::Number)
+        }
+
+        // Strings
+        "format" => {
+            let Some((template, values)) = args.split_first() else {
+                return Err(
+                    "format() requires at least 1 argument: template, [values...]".to_string(),
+                );
+            };
+            format_spec::format_string(&template.to_display_string(), values).map(ExprValue::String)
+        }
+
+        // Structured data
+        "parse_json" | "parse_toml" | "parse_yaml" => {
+            if args.len() != 1 {
+                return Err(format!("{}() requires exactly 1 argument: text", name));
+            }
+            formats::parse(data_format(name)?, &args[0].to_display_string())
+        }
+        "to_json" | "to_toml" | "to_yaml" => {
+            if args.is_empty() || args.len() > 2 {
+                return Err(format!(
+                    "{}() requires 1-2 arguments: value, [pretty]",
+                    name
+                ));
+            }
+            let pretty = args
+                .get(1)
+                .is_none_or(|p| !matches!(p, ExprValue::Boolean(false)));
+            formats::serialize(data_format(name)?, &args[0], pretty).map(ExprValue::String)
+        }
+        "read_json" | "read_toml" | "read_yaml" => {
+            if args.len() != 1 {
+                return Err(format!("{}() requires exactly 1 argument: path", name));
+            }
+            formats::read_file(data_format(name)?, &args[0].to_display_string())
+        }
+        "write_json" | "write_toml" | "write_yaml" => {
+            if args.len() != 2 {
+                return Err(format!(
+                    "{}() requires exactly 2 arguments: path, value",
+                    name
+                ));
+            }
+            let path = args[0].to_display_string();
+            formats::write_file(data_format(name)?, &path, &args[1], output)?;
+            Ok(ExprValue::Boolean(true))
+        }
+
+        _ => Err(format!("Unknown builtin function: {}", name)),
+    }
+}
+
+/// Convert to an integer, truncating floats toward zero.
+fn to_int(value: &ExprValue) -> Result<i64, String> {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    match value {
+        ExprValue::Integer(i) => Ok(*i),
+        ExprValue::Number(n) if n.is_finite() && n.trunc().abs() < LIMIT => Ok(n.trunc() as i64),
+        ExprValue::Number(n) => Err(format!("int() cannot convert {} to an integer", n)),
+        ExprValue::Boolean(b) => Ok(*b as i64),
+        ExprValue::String(s) => {
+            let s = s.trim();
+            s.parse::<i64>().or_else(|_| {
+                s.parse::<f64>()
+                    .map_err(|_| format!("int() cannot parse '{}'", s))
+                    .and_then(|n| to_int(&ExprValue::Number(n)))
+            })
+        }
+        other => Err(format!("int() cannot convert {} to an integer", other)),
+    }
+}
+
+fn to_float(value: &ExprValue) -> Result<f64, String> {
+    match value {
+        ExprValue::Integer(i) => Ok(*i as f64),
+        ExprValue::Number(n) => Ok(*n),
+        ExprValue::Boolean(b) => Ok(*b as i64 as f64),
+        ExprValue::String(s) => s
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("float() cannot parse '{}'", s.trim())),
+        other => Err(format!("float() cannot convert {} to a number", other)),
+    }
+}
+
+/// The data format named by the suffix of a builtin such as `read_json`.
+fn data_format(name: &str) -> Result<Format, String> {
+    name.rsplit('_')
+        .next()
+        .and_then(Format::from_suffix)
+        .ok_or_else(|| format!("Unknown data format in {}()", name))
+}
+
+pub fn is_value_builtin(name: &str) -> bool {
+    matches!(
+        name,
+        "render_template"
+            | "is_none"
+            | "or_else"
+            | "int"
+            | "float"
+            | "format"
+            | "parse_json"
+            | "parse_toml"
+            | "parse_yaml"
+            | "to_json"
+            | "to_toml"
+            | "to_yaml"
+            | "read_json"
+            | "read_toml"
+            | "read_yaml"
+            | "write_json"
+            | "write_toml"
+            | "write_yaml"
+    )
+}
+
 // System Information Functions
+/// Builtins that return one of a fixed set of strings, used to warn about
+/// `divine` statements that do not handle every value.
+pub const KNOWN_RESULTS: &[(&str, &[&str])] = &[
+    // Every value `std::env::consts::OS` is documented to take.
+    (
+        "platform",
+        &[
+            "linux",
+            "macos",
+            "ios",
+            "freebsd",
+            "dragonfly",
+            "netbsd",
+            "openbsd",
+            "solaris",
+            "android",
+            "windows",
+        ],
+    ),
+    (
+        "get_primary_package_manager",
+        &[
+            "apt", "yum", "dnf", "pacman", "zypper", "emerge", "brew", "port", "winget", "choco",
+            "scoop", "none",
+        ],
+    ),
+];
+
 fn detect_platform() -> String {
     std::env::consts::OS.to_string()
 }
@@ -299,21 +607,60 @@ fn get_current_directory() -> String {
 }
 
 // File System Functions
-fn ensure_directory(path: &str) -> Result<BuiltinValue, String> {
+fn ensure_directory(path: &str, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        if !Path::new(path).is_dir() {
+            output.dry_run_note(&format!("would create directory {}", path));
+        }
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
+    if !Path::new(path).is_dir() {
+        output.journal_snapshot(path)?;
+    }
     match fs::create_dir_all(path) {
         Ok(()) => Ok(BuiltinValue::Boolean(true)),
         Err(e) => Err(format!("Failed to create directory '{}': {}", path, e)),
     }
 }
 
-fn copy_file(source: &str, dest: &str) -> Result<BuiltinValue, String> {
+fn copy_file(
+    source: &str,
+    dest: &str,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        if !Path::new(source).is_file() {
+            return Err(format!(
+                "Failed to copy '{}' to '{}': source not found",
+                source, dest
+            ));
+        }
+        output.dry_run_note(&format!("would copy {} to {}", source, dest));
+        if let Ok(new) = fs::read_to_string(source) {
+            report_file_diff(dest, &new, output);
+        }
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
+    output.journal_snapshot(dest)?;
     match fs::copy(source, dest) {
         Ok(_) => Ok(BuiltinValue::Boolean(true)),
         Err(e) => Err(format!("Failed to copy '{}' to '{}': {}", source, dest, e)),
     }
 }
 
-fn create_symlink(source: &str, target: &str) -> Result<BuiltinValue, String> {
+fn create_symlink(
+    source: &str,
+    target: &str,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would symlink {} -> {}", target, source));
+        return Ok(BuiltinValue::Boolean(true));
+    }
+    output.journal_snapshot(target)?;
+
     #[cfg(target_family = "windows")]
     {
         use std::os::windows::fs;
@@ -339,7 +686,19 @@ fn create_symlink(source: &str, target: &str) -> Result<BuiltinValue, String> {
     }
 }
 
-fn write_file(path: &str, content: &str) -> Result<BuiltinValue, String> {
+pub(crate) fn write_file(
+    path: &str,
+    content: &str,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would write {}", path));
+        report_file_diff(path, content, output);
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
+    output.journal_snapshot(path)?;
+
     // Create parent directory if it doesn't exist
     if let Some(parent) = std::path::Path::new(path).parent()
         && !parent.exists()
@@ -353,8 +712,32 @@ fn write_file(path: &str, content: &str) -> Result<BuiltinValue, String> {
         .map_err(|e| format!("Failed to write file '{}': {}", path, e))
 }
 
-fn remove_file(path: &str) -> Result<BuiltinValue, String> {
+/// Print a unified diff between the current contents of `path` and `new_content`.
+pub(crate) fn report_file_diff(path: &str, new_content: &str, output: &mut OutputCollector) {
+    let old_content = fs::read_to_string(path).unwrap_or_default();
+    if old_content == new_content {
+        output.dry_run_note(&format!("{} is already up to date", path));
+        return;
+    }
+
+    let diff = similar::TextDiff::from_lines(old_content.as_str(), new_content);
+    let rendered = diff
+        .unified_diff()
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string();
+    output.print(&rendered);
+    if !rendered.ends_with('\n') {
+        output.println("");
+    }
+}
+
+fn remove_file(path: &str, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
     if std::path::Path::new(path).exists() {
+        if output.is_dry_run() {
+            output.dry_run_note(&format!("would remove file {}", path));
+            return Ok(BuiltinValue::Boolean(true));
+        }
+        output.journal_snapshot(path)?;
         std::fs::remove_file(path)
             .map(|_| BuiltinValue::Boolean(true))
             .map_err(|e| format!("Failed to remove file '{}': {}", path, e))
@@ -363,8 +746,13 @@ fn remove_file(path: &str) -> Result<BuiltinValue, String> {
     }
 }
 
-fn remove_directory(path: &str) -> Result<BuiltinValue, String> {
+fn remove_directory(path: &str, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
     if std::path::Path::new(path).exists() {
+        if output.is_dry_run() {
+            output.dry_run_note(&format!("would remove directory {}", path));
+            return Ok(BuiltinValue::Boolean(true));
+        }
+        output.journal_snapshot(path)?;
         std::fs::remove_dir_all(path)
             .map(|_| BuiltinValue::Boolean(true))
             .map_err(|e| format!("Failed to remove directory '{}': {}", path, e))
@@ -373,7 +761,12 @@ fn remove_directory(path: &str) -> Result<BuiltinValue, String> {
     }
 }
 
-fn make_executable(path: &str) -> Result<BuiltinValue, String> {
+fn make_executable(path: &str, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would make {} executable", path));
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
     #[cfg(target_family = "windows")]
     {
         // On Windows, executability is determined by file extension
@@ -392,6 +785,7 @@ fn make_executable(path: &str) -> Result<BuiltinValue, String> {
         if !path.exists() {
             return Err(format!("File '{}' does not exist", path.display()));
         }
+        output.journal_snapshot(&path.to_string_lossy())?;
 
         let metadata = match fs::metadata(path) {
             Ok(m) => m,
@@ -488,12 +882,20 @@ fn package_manager_available(manager: &str) -> bool {
     which::which(manager).is_ok()
 }
 
-fn install_package(package: &str, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
+pub(crate) fn install_package(
+    package: &str,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
     let pm = get_primary_package_manager();
     if pm == "none" {
         return Err("No package manager available".to_string());
     }
 
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would install {} using {}", package, pm));
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
     // Search for multiple packages and let user choose
     let package_name = match select_package_interactively(package, &pm, output) {
         Some(selected) => selected,
@@ -537,7 +939,7 @@ fn install_package(package: &str, output: &mut OutputCollector) -> Result<Builti
     }
 }
 
-fn package_installed(package: &str) -> bool {
+pub(crate) fn package_installed(package: &str) -> bool {
     let pm = get_primary_package_manager();
     let package_name = map_package_name(package, &pm);
 
@@ -968,23 +1370,32 @@ fn map_package_name(package: &str, manager: &str) -> String {
 }
 
 // Network Functions
-fn download_file(url: &str, path: &str) -> Result<BuiltinValue, String> {
+fn download_file(
+    url: &str,
+    path: &str,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would download {} to {}", url, path));
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
     // This is a simplified implementation
     // In a real implementation, you'd use a proper HTTP client like reqwest
     let curl_cmd = format!("curl -L '{}' -o '{}'", url, path);
 
-    let output = if cfg!(target_os = "windows") {
+    let cmd_output = if cfg!(target_os = "windows") {
         Command::new("cmd").args(["/C", &curl_cmd]).output()
     } else {
         Command::new("sh").args(["-c", &curl_cmd]).output()
     };
 
-    match output {
-        Ok(output) => {
-            if output.status.success() {
+    match cmd_output {
+        Ok(cmd_output) => {
+            if cmd_output.status.success() {
                 Ok(BuiltinValue::Boolean(true))
             } else {
-                let stderr = String::from_utf8_lossy(&output.stderr);
+                let stderr = String::from_utf8_lossy(&cmd_output.stderr);
                 Err(format!("Failed to download '{}': {}", url, stderr))
             }
         }
@@ -992,6 +1403,43 @@ fn download_file(url: &str, path: &str) -> Result<BuiltinValue, String> {
     }
 }
 
+fn extract_archive(
+    archive: &str,
+    dest: &str,
+    strip_components: usize,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would extract {} into {}", archive, dest));
+        return Ok(BuiltinValue::Array(Vec::new()));
+    }
+
+    output.journal_snapshot(dest)?;
+    let extracted =
+        crate::archive::extract_archive(Path::new(archive), Path::new(dest), strip_components)?;
+    Ok(BuiltinValue::Array(
+        extracted
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect(),
+    ))
+}
+
+fn create_archive(
+    source: &str,
+    archive: &str,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would create {} from {}", archive, source));
+        return Ok(BuiltinValue::Number(0.0));
+    }
+
+    output.journal_snapshot(archive)?;
+    let count = crate::archive::create_archive(Path::new(source), Path::new(archive))?;
+    Ok(BuiltinValue::Number(count as f64))
+}
+
 // Environment Functions
 fn get_env_var(name: &str, default: Option<&str>) -> String {
     std::env::var(name).unwrap_or_else(|_| default.unwrap_or("").to_string())
@@ -999,6 +1447,11 @@ fn get_env_var(name: &str, default: Option<&str>) -> String {
 
 // Package Project Management Functions
 fn package_init(name: &str, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would initialize mage project {}", name));
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
     use std::env;
     let current_dir =
         env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
@@ -1021,6 +1474,11 @@ fn package_add(
     is_dev: bool,
     output: &mut OutputCollector,
 ) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would add {} @ {} to mage.toml", package, version));
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
     use std::env;
     let current_dir =
         env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
@@ -1039,6 +1497,11 @@ fn package_add(
 }
 
 fn package_remove(package: &str, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would remove {} from mage.toml", package));
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
     use std::env;
     let current_dir =
         env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
@@ -1051,13 +1514,23 @@ fn package_remove(package: &str, output: &mut OutputCollector) -> Result<Builtin
     Ok(BuiltinValue::Boolean(true))
 }
 
-fn package_install_deps(dev: bool, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
+fn package_install_deps(
+    dev: bool,
+    frozen: bool,
+    offline: bool,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    if output.is_dry_run() {
+        output.dry_run_note("would install project dependencies");
+        return Ok(BuiltinValue::Boolean(true));
+    }
+
     use std::env;
     let current_dir =
         env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
 
-    let resolver = crate::package::PackageResolver::new(&current_dir);
-    resolver.install_dependencies(dev)?;
+    let resolver = crate::package::PackageResolver::new(&current_dir).with_offline(offline);
+    resolver.install_dependencies(dev, frozen)?;
 
     output.println("Dependencies installed successfully");
 
@@ -1107,25 +1580,35 @@ fn package_info(package: &str) -> Result<BuiltinValue, String> {
 }
 
 // External Package Integration
-fn from_package(package: &str, command: &str, args: &[String]) -> Result<BuiltinValue, String> {
+fn from_package(
+    package: &str,
+    command: &str,
+    args: &[String],
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
     // Build the command to execute
     let mut cmd_args = vec![command.to_string()];
     cmd_args.extend_from_slice(args);
 
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would run {} {}", package, cmd_args.join(" ")));
+        return Ok(BuiltinValue::String(String::new()));
+    }
+
     // Try to find the package executable in common locations
     let executable = find_package_executable(package)?;
 
     // Execute the command
-    let output = Command::new(&executable)
+    let cmd_output = Command::new(&executable)
         .args(&cmd_args)
         .output()
         .map_err(|e| format!("Failed to execute {} {}: {}", package, command, e))?;
 
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
+    if cmd_output.status.success() {
+        let stdout = String::from_utf8_lossy(&cmd_output.stdout);
         Ok(BuiltinValue::String(stdout.trim().to_string()))
     } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = String::from_utf8_lossy(&cmd_output.stderr);
         Err(format!("{} {} failed: {}", package, command, stderr))
     }
 }
@@ -0,0 +1,161 @@
+//! Content-addressed package stores.
+//!
+//! Packages are stored under the checksum `mage.lock` records for them, so an
+//! entry never goes stale and one copy serves every project:
+//!
+//! ```text
+//! sha256-<digest>    # a downloaded archive, or a git package's tree
+//! ```
+//!
+//! The global cache lives in `~/.cache/mage/packages` (or `MAGE_CACHE_DIR`),
+//! and `mage vendor` writes the same layout to a project's `vendor/`
+//! directory. Entries are checked against their checksum whenever they are
+//! used, so a damaged entry is ignored rather than installed.
+
+use crate::package::{copy_tree, hash_file, hash_tree};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Overrides the location of the global package cache.
+pub const MAGE_CACHE_DIR: &str = "MAGE_CACHE_DIR";
+
+#[derive(Debug, Clone)]
+pub struct PackageCache {
+    root: PathBuf,
+}
+
+impl PackageCache {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// The per-user cache: `MAGE_CACHE_DIR`, or `mage/packages` in the
+    /// platform cache directory.
+    pub fn global() -> Option<Self> {
+        std::env::var_os(MAGE_CACHE_DIR)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| dirs::cache_dir().map(|dir| dir.join("mage").join("packages")))
+            .map(|root| Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where the entry for `checksum` lives. `None` for anything that is not
+    /// a plain `algorithm:digest` checksum, so a lock file cannot point
+    /// outside the cache.
+    fn entry(&self, checksum: &str) -> Option<PathBuf> {
+        let (algorithm, digest) = checksum.split_once(':')?;
+        let valid =
+            |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric());
+        (valid(algorithm) && valid(digest))
+            .then(|| self.root.join(format!("{}-{}", algorithm, digest)))
+    }
+
+    /// Whether there is an entry for `checksum`, without verifying it.
+    pub fn contains(&self, checksum: &str) -> bool {
+        self.entry(checksum).is_some_and(|path| path.exists())
+    }
+
+    /// The cached file for `checksum`, if there is one whose contents still
+    /// hash to it.
+    pub fn file(&self, checksum: &str) -> Option<PathBuf> {
+        self.entry(checksum)
+            .filter(|path| path.is_file())
+            .filter(|path| hash_file(path).is_ok_and(|actual| actual == checksum))
+    }
+
+    /// The cached directory for `checksum`, if there is one whose contents
+    /// still hash to it.
+    pub fn tree(&self, checksum: &str) -> Option<PathBuf> {
+        self.entry(checksum)
+            .filter(|path| path.is_dir())
+            .filter(|path| hash_tree(path).is_ok_and(|actual| actual == checksum))
+    }
+
+    /// Copy the file at `source` into the cache as `checksum`.
+    pub fn store_file(&self, checksum: &str, source: &Path) -> Result<PathBuf, String> {
+        self.store(checksum, |staging| {
+            fs::copy(source, staging)
+                .map(|_| ())
+                .map_err(|e| format!("Failed to copy {}: {}", source.display(), e))
+        })
+    }
+
+    /// Copy the directory at `source`, without `.git` and `.mage`, into the
+    /// cache as `checksum`.
+    pub fn store_tree(&self, checksum: &str, source: &Path) -> Result<PathBuf, String> {
+        self.store(checksum, |staging| copy_tree(source, staging))
+    }
+
+    /// Write an entry into a staging path next to it and move it into place,
+    /// so an interrupted copy never leaves a partial entry behind.
+    fn store(
+        &self,
+        checksum: &str,
+        write: impl FnOnce(&Path) -> Result<(), String>,
+    ) -> Result<PathBuf, String> {
+        let path = self
+            .entry(checksum)
+            .ok_or_else(|| format!("Cannot cache a package with checksum '{}'", checksum))?;
+        fs::create_dir_all(&self.root)
+            .map_err(|e| format!("Failed to create {}: {}", self.root.display(), e))?;
+
+        let staging = path.with_extension(format!("tmp-{}", std::process::id()));
+        remove_entry(&staging);
+        if let Err(e) = write(&staging) {
+            remove_entry(&staging);
+            return Err(e);
+        }
+        remove_entry(&path);
+        fs::rename(&staging, &path).map_err(|e| {
+            remove_entry(&staging);
+            format!(
+                "Failed to store {} in {}: {}",
+                checksum,
+                self.root.display(),
+                e
+            )
+        })?;
+        Ok(path)
+    }
+
+    /// The checksums of every entry in the cache.
+    pub fn checksums(&self) -> Result<Vec<String>, String> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.root.display(), e)),
+        };
+        let mut checksums: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let (algorithm, digest) = name.split_once('-')?;
+                let checksum = format!("{}:{}", algorithm, digest);
+                self.entry(&checksum).is_some().then_some(checksum)
+            })
+            .collect();
+        checksums.sort();
+        Ok(checksums)
+    }
+
+    /// Delete the entry for `checksum`, if there is one.
+    pub fn remove(&self, checksum: &str) {
+        if let Some(path) = self.entry(checksum) {
+            remove_entry(&path);
+        }
+    }
+}
+
+fn remove_entry(path: &Path) {
+    let _ = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(_) => Ok(()),
+    };
+}
@@ -0,0 +1,323 @@
+//! Idempotent resource primitives.
+//!
+//! Each `ensure_*` builtin compares the desired state of a resource with its
+//! actual state and only acts when they differ. The outcome is reported as
+//! `ok` (nothing to do), `changed` (the resource was brought into the desired
+//! state) or `failed`, and tallied in the run's [`EnsureSummary`].
+
+use crate::builtins::{self, BuiltinValue};
+use crate::output::OutputCollector;
+use std::fs;
+use std::path::Path;
+
+/// Outcome of a single `ensure_*` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnsureStatus {
+    Ok,
+    Changed,
+    Failed,
+}
+
+impl EnsureStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnsureStatus::Ok => "ok",
+            EnsureStatus::Changed => "changed",
+            EnsureStatus::Failed => "failed",
+        }
+    }
+}
+
+impl std::fmt::Display for EnsureStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Running tally of `ensure_*` outcomes for a script run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnsureSummary {
+    pub ok: usize,
+    pub changed: usize,
+    pub failed: usize,
+}
+
+impl EnsureSummary {
+    pub fn record(&mut self, status: EnsureStatus) {
+        match status {
+            EnsureStatus::Ok => self.ok += 1,
+            EnsureStatus::Changed => self.changed += 1,
+            EnsureStatus::Failed => self.failed += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.ok + self.changed + self.failed
+    }
+}
+
+impl std::fmt::Display for EnsureSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Summary: {} ok, {} changed, {} failed",
+            self.ok, self.changed, self.failed
+        )
+    }
+}
+
+/// Record the outcome of a resource and convert it into a builtin return value.
+pub(crate) fn finish(
+    status: EnsureStatus,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    output.record_resource(status);
+    Ok(BuiltinValue::String(status.to_string()))
+}
+
+/// Report a failed resource on stderr and record it.
+pub(crate) fn fail(message: String, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
+    output.eprintln(&message);
+    finish(EnsureStatus::Failed, output)
+}
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    let digits = mode.trim().trim_start_matches("0o");
+    u32::from_str_radix(digits, 8).map_err(|_| format!("Invalid file mode '{}'", mode))
+}
+
+#[cfg(not(target_family = "windows"))]
+fn mode_matches(path: &Path, mode: u32) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|m| m.permissions().mode() & 0o7777 == mode)
+        .unwrap_or(false)
+}
+
+#[cfg(target_family = "windows")]
+fn mode_matches(_path: &Path, _mode: u32) -> bool {
+    // Unix permission bits have no meaning on Windows
+    true
+}
+
+#[cfg(not(target_family = "windows"))]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(target_family = "windows")]
+fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+fn make_symlink(source: &str, target: &str) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(source, target)
+}
+
+#[cfg(target_family = "windows")]
+fn make_symlink(source: &str, target: &str) -> std::io::Result<()> {
+    if Path::new(source).is_dir() {
+        std::os::windows::fs::symlink_dir(source, target)
+    } else {
+        std::os::windows::fs::symlink_file(source, target)
+    }
+}
+
+/// Remove whatever is at `path` (file, symlink or directory tree).
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Ensure `path` exists with exactly `content` and, optionally, the given octal mode.
+pub fn ensure_file(
+    path: &str,
+    content: &str,
+    mode: Option<&str>,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    let mode = match mode.map(parse_mode).transpose() {
+        Ok(mode) => mode,
+        Err(e) => return fail(e, output),
+    };
+    let file = Path::new(path);
+
+    let content_ok = fs::read(file)
+        .map(|c| c == content.as_bytes())
+        .unwrap_or(false);
+    let mode_ok = mode.is_none_or(|m| mode_matches(file, m));
+    if content_ok && mode_ok {
+        return finish(EnsureStatus::Ok, output);
+    }
+
+    if output.is_dry_run() {
+        if !content_ok {
+            output.dry_run_note(&format!("would write {}", path));
+            builtins::report_file_diff(path, content, output);
+        }
+        if let Some(m) = mode
+            && !mode_ok
+        {
+            output.dry_run_note(&format!("would set mode of {} to {:o}", path, m));
+        }
+        return finish(EnsureStatus::Changed, output);
+    }
+
+    if let Err(e) = output.journal_snapshot(path) {
+        return fail(e, output);
+    }
+    if !content_ok {
+        if let Some(parent) = file.parent()
+            && !parent.as_os_str().is_empty()
+            && let Err(e) = fs::create_dir_all(parent)
+        {
+            return fail(
+                format!("Failed to create parent of '{}': {}", path, e),
+                output,
+            );
+        }
+        if let Err(e) = fs::write(file, content) {
+            return fail(format!("Failed to write file '{}': {}", path, e), output);
+        }
+    }
+    if let Some(m) = mode
+        && !mode_matches(file, m)
+        && let Err(e) = set_mode(file, m)
+    {
+        return fail(format!("Failed to set mode of '{}': {}", path, e), output);
+    }
+
+    finish(EnsureStatus::Changed, output)
+}
+
+/// Ensure `target` is a symlink pointing at `source`.
+///
+/// An existing symlink pointing elsewhere is replaced. Any other existing file
+/// or directory is only replaced when `force` is set.
+pub fn ensure_symlink(
+    source: &str,
+    target: &str,
+    force: bool,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    let target_path = Path::new(target);
+
+    let existing = fs::symlink_metadata(target_path).ok();
+    if let Some(metadata) = &existing {
+        if metadata.file_type().is_symlink() {
+            if fs::read_link(target_path).is_ok_and(|link| link == Path::new(source)) {
+                return finish(EnsureStatus::Ok, output);
+            }
+        } else if !force {
+            return fail(
+                format!(
+                    "'{}' exists and is not a symlink (pass force = true to replace it)",
+                    target
+                ),
+                output,
+            );
+        }
+    }
+
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would symlink {} -> {}", target, source));
+        return finish(EnsureStatus::Changed, output);
+    }
+
+    if let Err(e) = output.journal_snapshot(target) {
+        return fail(e, output);
+    }
+    if existing.is_some()
+        && let Err(e) = remove_path(target_path)
+    {
+        return fail(format!("Failed to replace '{}': {}", target, e), output);
+    }
+    if let Err(e) = make_symlink(source, target) {
+        return fail(
+            format!(
+                "Failed to create symlink from '{}' to '{}': {}",
+                source, target, e
+            ),
+            output,
+        );
+    }
+
+    finish(EnsureStatus::Changed, output)
+}
+
+/// Ensure `path` contains `line` as a whole line, appending it when missing.
+pub fn ensure_line_in_file(
+    path: &str,
+    line: &str,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    let current = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return fail(format!("Failed to read file '{}': {}", path, e), output),
+    };
+
+    if current.lines().any(|l| l == line) {
+        return finish(EnsureStatus::Ok, output);
+    }
+
+    let mut updated = current.clone();
+    if !updated.is_empty() && !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated.push_str(line);
+    updated.push('\n');
+
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would add a line to {}", path));
+        builtins::report_file_diff(path, &updated, output);
+        return finish(EnsureStatus::Changed, output);
+    }
+
+    if let Err(e) = output.journal_snapshot(path) {
+        return fail(e, output);
+    }
+    match fs::write(path, updated) {
+        Ok(()) => finish(EnsureStatus::Changed, output),
+        Err(e) => fail(format!("Failed to write file '{}': {}", path, e), output),
+    }
+}
+
+/// Ensure nothing exists at `path`.
+pub fn ensure_absent(path: &str, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
+    let target = Path::new(path);
+    if fs::symlink_metadata(target).is_err() {
+        return finish(EnsureStatus::Ok, output);
+    }
+
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would remove {}", path));
+        return finish(EnsureStatus::Changed, output);
+    }
+
+    if let Err(e) = output.journal_snapshot(path) {
+        return fail(e, output);
+    }
+    match remove_path(target) {
+        Ok(()) => finish(EnsureStatus::Changed, output),
+        Err(e) => fail(format!("Failed to remove '{}': {}", path, e), output),
+    }
+}
+
+/// Ensure a system package is installed, installing it when missing.
+pub fn ensure_package(package: &str, output: &mut OutputCollector) -> Result<BuiltinValue, String> {
+    if builtins::package_installed(package) {
+        return finish(EnsureStatus::Ok, output);
+    }
+
+    match builtins::install_package(package, output) {
+        Ok(_) => finish(EnsureStatus::Changed, output),
+        Err(e) => fail(e, output),
+    }
+}
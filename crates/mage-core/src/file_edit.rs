@@ -0,0 +1,212 @@
+//! In-place editing of configuration files.
+//!
+//! These builtins change a single line or a marked block of a file and leave
+//! every other byte untouched. Like the other `ensure_*` resources they report
+//! `ok`, `changed` or `failed`.
+
+use crate::builtins::{self, BuiltinValue};
+use crate::ensure::{EnsureStatus, fail, finish};
+use crate::output::OutputCollector;
+use regex::Regex;
+use std::fs;
+
+fn begin_marker(id: &str) -> String {
+    format!("# BEGIN mage {}", id)
+}
+
+fn end_marker(id: &str) -> String {
+    format!("# END mage {}", id)
+}
+
+/// Strip the line terminator from a line produced by `split_inclusive('\n')`.
+fn line_text(line: &str) -> &str {
+    line.trim_end_matches('\n').trim_end_matches('\r')
+}
+
+fn read_existing(path: &str) -> Result<Option<String>, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read file '{}': {}", path, e)),
+    }
+}
+
+/// Append `text` on a new line at the end of `content`.
+fn append(content: &mut String, text: &str) {
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(text);
+}
+
+/// Write `updated` to `path` if it differs from `current`, optionally keeping a
+/// `<path>.bak` copy of the previous contents.
+fn apply(
+    path: &str,
+    current: Option<&str>,
+    updated: &str,
+    backup: bool,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    if current == Some(updated) {
+        return finish(EnsureStatus::Ok, output);
+    }
+
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would edit {}", path));
+        builtins::report_file_diff(path, updated, output);
+        return finish(EnsureStatus::Changed, output);
+    }
+
+    if backup && current.is_some() {
+        let backup_path = format!("{}.bak", path);
+        if let Err(e) = output.journal_snapshot(&backup_path) {
+            return fail(e, output);
+        }
+        if let Err(e) = fs::copy(path, &backup_path) {
+            return fail(format!("Failed to back up '{}': {}", path, e), output);
+        }
+    }
+
+    if let Err(e) = output.journal_snapshot(path) {
+        return fail(e, output);
+    }
+    match fs::write(path, updated) {
+        Ok(()) => finish(EnsureStatus::Changed, output),
+        Err(e) => fail(format!("Failed to write file '{}': {}", path, e), output),
+    }
+}
+
+/// Ensure a line matching `pattern` reads exactly `line`.
+///
+/// The last matching line is replaced. When nothing matches, `line` is appended
+/// unless it is already present verbatim.
+pub fn ensure_line(
+    path: &str,
+    pattern: &str,
+    line: &str,
+    backup: bool,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    let regex = match Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(e) => return fail(format!("Invalid pattern '{}': {}", pattern, e), output),
+    };
+    let current = match read_existing(path) {
+        Ok(current) => current,
+        Err(e) => return fail(e, output),
+    };
+    let content = current.clone().unwrap_or_default();
+
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let updated = match lines.iter().rposition(|l| regex.is_match(line_text(l))) {
+        Some(index) => {
+            let old = lines[index];
+            let ending = &old[line_text(old).len()..];
+            let mut updated = String::with_capacity(content.len() + line.len());
+            for l in &lines[..index] {
+                updated.push_str(l);
+            }
+            updated.push_str(line);
+            updated.push_str(ending);
+            for l in &lines[index + 1..] {
+                updated.push_str(l);
+            }
+            updated
+        }
+        None if lines.iter().any(|l| line_text(l) == line) => content.clone(),
+        None => {
+            let mut updated = content.clone();
+            append(&mut updated, line);
+            updated.push('\n');
+            updated
+        }
+    };
+
+    apply(path, current.as_deref(), &updated, backup, output)
+}
+
+/// Locate the byte range of the block `id`, including both marker lines.
+fn find_block(content: &str, id: &str) -> Option<(usize, usize)> {
+    let begin = begin_marker(id);
+    let end = end_marker(id);
+
+    let mut offset = 0;
+    let mut start = None;
+    for line in content.split_inclusive('\n') {
+        let text = line_text(line).trim();
+        if start.is_none() && text == begin {
+            start = Some(offset);
+        } else if let Some(start) = start
+            && text == end
+        {
+            return Some((start, offset + line.len()));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Ensure the block delimited by `# BEGIN mage <id>` / `# END mage <id>`
+/// contains exactly `body`, appending the block when it does not exist yet.
+pub fn ensure_block(
+    path: &str,
+    id: &str,
+    body: &str,
+    backup: bool,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    let current = match read_existing(path) {
+        Ok(current) => current,
+        Err(e) => return fail(e, output),
+    };
+    let content = current.clone().unwrap_or_default();
+
+    let mut block = begin_marker(id);
+    block.push('\n');
+    block.push_str(body);
+    if !body.is_empty() && !body.ends_with('\n') {
+        block.push('\n');
+    }
+    block.push_str(&end_marker(id));
+    block.push('\n');
+
+    let updated = match find_block(&content, id) {
+        Some((start, end)) => {
+            let mut replaced = block;
+            // Keep the original terminator if the end marker was the last line
+            if end == content.len() && !content.ends_with('\n') {
+                replaced.pop();
+            }
+            format!("{}{}{}", &content[..start], replaced, &content[end..])
+        }
+        None => {
+            let mut updated = content.clone();
+            append(&mut updated, &block);
+            updated
+        }
+    };
+
+    apply(path, current.as_deref(), &updated, backup, output)
+}
+
+/// Remove the block delimited by `# BEGIN mage <id>` / `# END mage <id>`.
+pub fn ensure_block_absent(
+    path: &str,
+    id: &str,
+    backup: bool,
+    output: &mut OutputCollector,
+) -> Result<BuiltinValue, String> {
+    let current = match read_existing(path) {
+        Ok(Some(current)) => current,
+        Ok(None) => return finish(EnsureStatus::Ok, output),
+        Err(e) => return fail(e, output),
+    };
+
+    let Some((start, end)) = find_block(&current, id) else {
+        return finish(EnsureStatus::Ok, output);
+    };
+    let updated = format!("{}{}", &current[..start], &current[end..]);
+
+    apply(path, Some(&current), &updated, backup, output)
+}
@@ -0,0 +1,281 @@
+//! Format specifiers shared by `${expr:spec}` interpolation and `format()`.
+//!
+//! A spec has the form `[[fill]align][+][#][0][width][.precision][type]`:
+//!
+//! - `align` is `<` (left), `>` (right) or `^` (centre); `fill` defaults to a space
+//! - `+` always prints the sign of numbers
+//! - `#` adds a `0x`, `0o` or `0b` prefix to hex, octal and binary output
+//! - `0` pads numbers with zeros after the sign
+//! - `precision` is the number of decimals for floats, or the maximum length of strings
+//! - `type` is one of `s`, `d`, `f`, `e`, `x`, `X`, `o` or `b`
+//!
+//! Numbers are right-aligned by default and everything else is left-aligned.
+//! Width and precision are limited to 65535, like Rust's own formatter.
+
+use crate::interpreter::ExprValue;
+
+/// The largest width or precision a spec may ask for.
+const MAX_COUNT: usize = u16::MAX as usize;
+
+#[derive(Debug, Default)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    sign: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+/// Digits of a width or precision; values too large for `usize` saturate and
+/// are rejected by `parse_spec`.
+fn count(digits: &[char]) -> usize {
+    digits
+        .iter()
+        .collect::<String>()
+        .parse()
+        .unwrap_or(usize::MAX)
+}
+
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let parsed = parse_syntax(spec)?;
+    for (what, value) in [
+        ("width", Some(parsed.width)),
+        ("precision", parsed.precision),
+    ] {
+        if value.is_some_and(|n| n > MAX_COUNT) {
+            return Err(format!(
+                "Format {} in '{}' must be at most {}",
+                what, spec, MAX_COUNT
+            ));
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_syntax(spec: &str) -> Result<Spec, String> {
+    let invalid = || format!("Invalid format specifier '{}'", spec);
+    let chars: Vec<char> = spec.chars().collect();
+    let mut parsed = Spec::default();
+    let mut i = 0;
+
+    let is_align = |c: char| matches!(c, '<' | '>' | '^');
+    if chars.len() >= 2 && is_align(chars[1]) {
+        parsed.fill = Some(chars[0]);
+        parsed.align = Some(chars[1]);
+        i = 2;
+    } else if chars.first().is_some_and(|c| is_align(*c)) {
+        parsed.align = Some(chars[0]);
+        i = 1;
+    }
+
+    if chars.get(i) == Some(&'+') {
+        parsed.sign = true;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'#') {
+        parsed.alternate = true;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'0') {
+        parsed.zero = true;
+        i += 1;
+    }
+
+    let start = i;
+    while chars.get(i).is_some_and(|c| c.is_ascii_digit()) {
+        i += 1;
+    }
+    if i > start {
+        parsed.width = count(&chars[start..i]);
+    }
+
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        let start = i;
+        while chars.get(i).is_some_and(|c| c.is_ascii_digit()) {
+            i += 1;
+        }
+        if i == start {
+            return Err(invalid());
+        }
+        parsed.precision = Some(count(&chars[start..i]));
+    }
+
+    match chars.get(i) {
+        Some(c) if matches!(c, 's' | 'd' | 'f' | 'e' | 'x' | 'X' | 'o' | 'b') => {
+            parsed.kind = Some(*c);
+            i += 1;
+        }
+        _ => {}
+    }
+
+    if i != chars.len() {
+        return Err(invalid());
+    }
+    Ok(parsed)
+}
+
+/// Returns true if `spec` is a well-formed format specifier. An out-of-range
+/// width or precision is well-formed; `format_value` reports it.
+pub fn is_valid_spec(spec: &str) -> bool {
+    parse_syntax(spec).is_ok()
+}
+
+/// Format a single value according to `spec`.
+pub fn format_value(value: &ExprValue, spec: &str) -> Result<String, String> {
+    let spec = parse_spec(spec)?;
+    let is_number = matches!(value, ExprValue::Integer(_) | ExprValue::Number(_));
+
+    let body = match spec.kind {
+        Some(kind @ ('x' | 'X' | 'o' | 'b' | 'd')) => {
+            let n = integer_of(value).ok_or_else(|| {
+                format!("Format type '{}' requires an integer, got {}", kind, value)
+            })?;
+            let magnitude = n.unsigned_abs();
+            let (prefix, digits) = match kind {
+                'x' => ("0x", format!("{:x}", magnitude)),
+                'X' => ("0x", format!("{:X}", magnitude)),
+                'o' => ("0o", format!("{:o}", magnitude)),
+                'b' => ("0b", format!("{:b}", magnitude)),
+                _ => ("", magnitude.to_string()),
+            };
+            let prefix = if spec.alternate { prefix } else { "" };
+            signed(n < 0, &spec, prefix, digits)
+        }
+        Some(kind @ ('f' | 'e')) if !is_number => {
+            return Err(format!(
+                "Format type '{}' requires a number, got {}",
+                kind, value
+            ));
+        }
+        None if is_number => match value {
+            ExprValue::Integer(n) if spec.precision.is_none() => {
+                signed(*n < 0, &spec, "", n.unsigned_abs().to_string())
+            }
+            _ => format_float(value, &spec),
+        },
+        Some('f' | 'e') => format_float(value, &spec),
+        _ => {
+            let text = value.to_display_string();
+            match spec.precision {
+                Some(max) => text.chars().take(max).collect(),
+                None => text,
+            }
+        }
+    };
+
+    Ok(pad(body, &spec, is_number))
+}
+
+fn format_float(value: &ExprValue, spec: &Spec) -> String {
+    let n = match value {
+        ExprValue::Integer(i) => *i as f64,
+        ExprValue::Number(n) => *n,
+        _ => 0.0,
+    };
+    let digits = match (spec.kind, spec.precision) {
+        (Some('e'), Some(p)) => format!("{:.*e}", p, n.abs()),
+        (Some('e'), None) => format!("{:e}", n.abs()),
+        (_, Some(p)) => format!("{:.*}", p, n.abs()),
+        (_, None) => n.abs().to_string(),
+    };
+    signed(n.is_sign_negative() && n != 0.0, spec, "", digits)
+}
+
+fn integer_of(value: &ExprValue) -> Option<i64> {
+    match value {
+        ExprValue::Integer(i) => Some(*i),
+        ExprValue::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Some(*n as i64),
+        _ => None,
+    }
+}
+
+/// Prefix the sign and radix prefix, zero-padding between them and the digits
+/// when requested.
+fn signed(negative: bool, spec: &Spec, prefix: &str, digits: String) -> String {
+    let sign = if negative {
+        "-"
+    } else if spec.sign {
+        "+"
+    } else {
+        ""
+    };
+    if spec.zero && spec.align.is_none() {
+        let len = sign.len() + prefix.len() + digits.chars().count();
+        let zeros = "0".repeat(spec.width.saturating_sub(len));
+        format!("{}{}{}{}", sign, prefix, zeros, digits)
+    } else {
+        format!("{}{}{}", sign, prefix, digits)
+    }
+}
+
+fn pad(body: String, spec: &Spec, is_number: bool) -> String {
+    let len = body.chars().count();
+    if len >= spec.width {
+        return body;
+    }
+    let fill = spec.fill.unwrap_or(' ');
+    let gap = spec.width - len;
+    let align = spec.align.unwrap_or(if is_number { '>' } else { '<' });
+    let repeat = |n: usize| fill.to_string().repeat(n);
+    match align {
+        '>' => format!("{}{}", repeat(gap), body),
+        '^' => format!("{}{}{}", repeat(gap / 2), body, repeat(gap - gap / 2)),
+        _ => format!("{}{}", body, repeat(gap)),
+    }
+}
+
+/// Format `template`, replacing `{}`, `{0}` or `{:spec}`/`{1:spec}` with `args`.
+/// `{{` and `}}` produce literal braces.
+pub fn format_string(template: &str, args: &[ExprValue]) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = template.chars().peekable();
+    let mut next_arg = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => field.push(c),
+                        None => return Err("Unclosed '{' in format string".to_string()),
+                    }
+                }
+                let (index, spec) = field.split_once(':').unwrap_or((field.as_str(), ""));
+                let index = if index.trim().is_empty() {
+                    next_arg += 1;
+                    next_arg - 1
+                } else {
+                    index
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid format field '{{{}}}'", field))?
+                };
+                let value = args.get(index).ok_or_else(|| {
+                    format!(
+                        "Format string refers to argument {} but got {} argument(s)",
+                        index,
+                        args.len()
+                    )
+                })?;
+                result.push_str(&format_value(value, spec)?);
+            }
+            '}' => return Err("Unmatched '}' in format string".to_string()),
+            c => result.push(c),
+        }
+    }
+    Ok(result)
+}
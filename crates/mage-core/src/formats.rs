@@ -0,0 +1,239 @@
+//! Conversion between mage values and JSON, TOML and YAML.
+//!
+//! | Data                    | Mage value                                   |
+//! |-------------------------|----------------------------------------------|
+//! | string                  | `String`                                     |
+//! | integer                 | `Integer`                                    |
+//! | float                   | `Number`                                     |
+//! | boolean                 | `Boolean`                                    |
+//! | array / sequence        | `List`                                       |
+//! | object / table / map    | `Map` (non-string YAML keys are stringified) |
+//! | null / `~`              | `none` (omitted from TOML tables)            |
+//! | TOML datetime           | `String` in RFC 3339 form                    |
+
+use crate::builtins;
+use crate::interpreter::ExprValue;
+use crate::output::OutputCollector;
+use indexmap::IndexMap;
+use std::fs;
+
+/// Serialization formats understood by the `parse_*`/`to_*` builtins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Look up the format from a builtin name suffix such as `json` in `read_json`.
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            "yaml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::Toml => "TOML",
+            Format::Yaml => "YAML",
+        }
+    }
+}
+
+/// Parse `text` in the given format.
+pub fn parse(format: Format, text: &str) -> Result<ExprValue, String> {
+    let err = |e: &dyn std::fmt::Display| format!("Invalid {}: {}", format.name(), e);
+    match format {
+        Format::Json => serde_json::from_str::<serde_json::Value>(text)
+            .map(from_json)
+            .map_err(|e| err(&e)),
+        Format::Toml => text
+            .parse::<toml::Table>()
+            .map(|t| from_toml(toml::Value::Table(t)))
+            .map_err(|e| err(&e)),
+        Format::Yaml => serde_yaml::from_str::<serde_yaml::Value>(text)
+            .map(from_yaml)
+            .map_err(|e| err(&e)),
+    }
+}
+
+/// Serialize `value` in the given format. `pretty` only affects JSON.
+pub fn serialize(format: Format, value: &ExprValue, pretty: bool) -> Result<String, String> {
+    let err = |e: &dyn std::fmt::Display| format!("Cannot convert to {}: {}", format.name(), e);
+    match format {
+        Format::Json => {
+            let json = to_json(value)?;
+            if pretty {
+                serde_json::to_string_pretty(&json).map_err(|e| err(&e))
+            } else {
+                serde_json::to_string(&json).map_err(|e| err(&e))
+            }
+        }
+        Format::Toml => match to_toml(value)? {
+            toml::Value::Table(table) => toml::to_string(&table).map_err(|e| err(&e)),
+            _ => Err("Cannot convert to TOML: the top-level value must be a map".to_string()),
+        },
+        Format::Yaml => serde_yaml::to_string(&to_yaml(value)?).map_err(|e| err(&e)),
+    }
+}
+
+/// Read and parse a structured file.
+pub fn read_file(format: Format, path: &str) -> Result<ExprValue, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read file '{}': {}", path, e))?;
+    parse(format, &text).map_err(|e| format!("{} in '{}'", e, path))
+}
+
+/// Serialize `value` and write it to `path`, honouring dry-run and the undo journal.
+pub fn write_file(
+    format: Format,
+    path: &str,
+    value: &ExprValue,
+    output: &mut OutputCollector,
+) -> Result<(), String> {
+    let mut text = serialize(format, value, true)?;
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    builtins::write_file(path, &text, output).map(|_| ())
+}
+
+fn from_json(value: serde_json::Value) -> ExprValue {
+    match value {
+        serde_json::Value::Null => ExprValue::None,
+        serde_json::Value::Bool(b) => ExprValue::Boolean(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => ExprValue::Integer(i),
+            None => ExprValue::Number(n.as_f64().unwrap_or(0.0)),
+        },
+        serde_json::Value::String(s) => ExprValue::String(s),
+        serde_json::Value::Array(items) => {
+            ExprValue::List(items.into_iter().map(from_json).collect())
+        }
+        serde_json::Value::Object(map) => ExprValue::Map(
+            map.into_iter()
+                .map(|(k, v)| (k, from_json(v)))
+                .collect::<IndexMap<_, _>>(),
+        ),
+    }
+}
+
+fn to_json(value: &ExprValue) -> Result<serde_json::Value, String> {
+    Ok(match value {
+        ExprValue::String(s) => serde_json::Value::String(s.clone()),
+        ExprValue::Integer(i) => serde_json::Value::from(*i),
+        ExprValue::Number(n) => serde_json::Number::from_f64(*n)
+            .map(serde_json::Value::Number)
+            .ok_or_else(|| format!("Cannot convert {} to JSON", n))?,
+        ExprValue::Boolean(b) => serde_json::Value::Bool(*b),
+        ExprValue::None => serde_json::Value::Null,
+        ExprValue::Function(name) => {
+            return Err(format!("Cannot convert function '{}' to JSON", name));
+        }
+        ExprValue::List(items) => {
+            serde_json::Value::Array(items.iter().map(to_json).collect::<Result<_, _>>()?)
+        }
+        ExprValue::Map(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), to_json(v)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
+
+fn from_toml(value: toml::Value) -> ExprValue {
+    match value {
+        toml::Value::String(s) => ExprValue::String(s),
+        toml::Value::Integer(i) => ExprValue::Integer(i),
+        toml::Value::Float(f) => ExprValue::Number(f),
+        toml::Value::Boolean(b) => ExprValue::Boolean(b),
+        toml::Value::Datetime(d) => ExprValue::String(d.to_string()),
+        toml::Value::Array(items) => ExprValue::List(items.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => ExprValue::Map(
+            table
+                .into_iter()
+                .map(|(k, v)| (k, from_toml(v)))
+                .collect::<IndexMap<_, _>>(),
+        ),
+    }
+}
+
+fn to_toml(value: &ExprValue) -> Result<toml::Value, String> {
+    Ok(match value {
+        ExprValue::String(s) => toml::Value::String(s.clone()),
+        ExprValue::Integer(i) => toml::Value::Integer(*i),
+        ExprValue::Number(n) => toml::Value::Float(*n),
+        ExprValue::Boolean(b) => toml::Value::Boolean(*b),
+        ExprValue::None => return Err("Cannot convert none to TOML".to_string()),
+        ExprValue::Function(name) => {
+            return Err(format!("Cannot convert function '{}' to TOML", name));
+        }
+        ExprValue::List(items) => {
+            toml::Value::Array(items.iter().map(to_toml).collect::<Result<_, _>>()?)
+        }
+        // TOML has no null, so keys set to none are left out
+        ExprValue::Map(map) => toml::Value::Table(
+            map.iter()
+                .filter(|(_, v)| !matches!(v, ExprValue::None))
+                .map(|(k, v)| Ok((k.clone(), to_toml(v)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
+
+fn yaml_key(key: serde_yaml::Value) -> String {
+    match key {
+        serde_yaml::Value::String(s) => s,
+        other => match from_yaml(other) {
+            ExprValue::String(s) => s,
+            value => value.to_string(),
+        },
+    }
+}
+
+fn from_yaml(value: serde_yaml::Value) -> ExprValue {
+    match value {
+        serde_yaml::Value::Null => ExprValue::None,
+        serde_yaml::Value::Bool(b) => ExprValue::Boolean(b),
+        serde_yaml::Value::Number(n) => match n.as_i64() {
+            Some(i) => ExprValue::Integer(i),
+            None => ExprValue::Number(n.as_f64().unwrap_or(0.0)),
+        },
+        serde_yaml::Value::String(s) => ExprValue::String(s),
+        serde_yaml::Value::Sequence(items) => {
+            ExprValue::List(items.into_iter().map(from_yaml).collect())
+        }
+        serde_yaml::Value::Mapping(map) => ExprValue::Map(
+            map.into_iter()
+                .map(|(k, v)| (yaml_key(k), from_yaml(v)))
+                .collect::<IndexMap<_, _>>(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => from_yaml(tagged.value),
+    }
+}
+
+fn to_yaml(value: &ExprValue) -> Result<serde_yaml::Value, String> {
+    Ok(match value {
+        ExprValue::String(s) => serde_yaml::Value::String(s.clone()),
+        ExprValue::Integer(i) => serde_yaml::Value::from(*i),
+        ExprValue::Number(n) => serde_yaml::Value::from(*n),
+        ExprValue::Boolean(b) => serde_yaml::Value::Bool(*b),
+        ExprValue::None => serde_yaml::Value::Null,
+        ExprValue::Function(name) => {
+            return Err(format!("Cannot convert function '{}' to YAML", name));
+        }
+        ExprValue::List(items) => {
+            serde_yaml::Value::Sequence(items.iter().map(to_yaml).collect::<Result<_, _>>()?)
+        }
+        ExprValue::Map(map) => serde_yaml::Value::Mapping(
+            map.iter()
+                .map(|(k, v)| Ok((serde_yaml::Value::String(k.clone()), to_yaml(v)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
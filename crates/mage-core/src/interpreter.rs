@@ -1,5 +1,9 @@
 use crate::output::{InterpreterError, OutputCollector};
-use crate::{Rule, builtins};
+use crate::parser::MageParser;
+use crate::tasks::Task;
+use crate::{Rule, builtins, format_spec};
+use indexmap::IndexMap;
+use pest::Parser;
 use pest::iterators::Pairs;
 use std::collections::HashMap;
 
@@ -22,10 +26,14 @@ pub enum FunctionResult {
 #[derive(Debug, Clone)]
 pub enum ExprValue {
     String(String),
+    Integer(i64),
     Number(f64),
     Boolean(bool),
     List(Vec<ExprValue>),
-    Map(HashMap<String, ExprValue>),
+    Map(IndexMap<String, ExprValue>),
+    /// A reference to a function defined with `enchant`, used as a callback.
+    Function(String),
+    None,
 }
 
 #[derive(Debug, Clone)]
@@ -41,6 +49,7 @@ impl std::fmt::Display for ExprValue {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ExprValue::String(s) => write!(f, "{}", s),
+            ExprValue::Integer(i) => write!(f, "{}", i),
             ExprValue::Number(n) => write!(f, "{}", n),
             ExprValue::Boolean(b) => write!(f, "{}", b),
             ExprValue::List(l) => write!(
@@ -59,6 +68,8 @@ impl std::fmt::Display for ExprValue {
                     .collect::<Vec<String>>()
                     .join(", ")
             ),
+            ExprValue::Function(name) => write!(f, "<function {}>", name),
+            ExprValue::None => write!(f, "none"),
         }
     }
 }
@@ -70,6 +81,21 @@ impl ExprValue {
             other => other.to_string(),
         }
     }
+
+    /// Truthiness used by callbacks such as `filter`: false, none, zero and
+    /// empty strings, lists and maps are false.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            ExprValue::String(s) => !s.is_empty(),
+            ExprValue::Integer(i) => *i != 0,
+            ExprValue::Number(n) => *n != 0.0,
+            ExprValue::Boolean(b) => *b,
+            ExprValue::List(l) => !l.is_empty(),
+            ExprValue::Map(m) => !m.is_empty(),
+            ExprValue::Function(_) => true,
+            ExprValue::None => false,
+        }
+    }
 }
 
 pub fn interpret<'i>(
@@ -81,6 +107,7 @@ pub fn interpret<'i>(
 ) -> Result<(), InterpreterError> {
     for pair in pairs {
         if pair.as_rule() == Rule::program {
+            check_divine_exhaustive(pair.clone(), output);
             for incantation in pair.into_inner() {
                 if let Signal::Error(msg) = match_incantation_with_shell(
                     incantation,
@@ -111,7 +138,7 @@ fn match_incantation_with_shell<'i>(
             match_incantation_with_shell(inner, scope, functions, shell_override, output)
         }
         Rule::evoke => {
-            handle_evoke(stmt, scope, shell_override, output);
+            handle_evoke(stmt, scope, functions, shell_override, output);
             Signal::None
         }
         _ => match_incantation(stmt, scope, functions, output),
@@ -123,6 +150,24 @@ fn match_incantation<'i>(
     scope: &mut HashMap<String, ExprValue>,
     functions: &mut HashMap<String, FunctionDef<'i>>,
     output: &mut OutputCollector,
+) -> Signal {
+    let signal = dispatch_incantation(stmt, scope, functions, output);
+    match output.take_error() {
+        Some(msg) if !matches!(signal, Signal::Error(_)) => Signal::Error(msg),
+        _ => signal,
+    }
+}
+
+/// Turn an error raised while evaluating an expression into an error signal.
+fn raised_error(output: &mut OutputCollector) -> Option<Signal> {
+    output.take_error().map(Signal::Error)
+}
+
+fn dispatch_incantation<'i>(
+    stmt: pest::iterators::Pair<'i, Rule>,
+    scope: &mut HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef<'i>>,
+    output: &mut OutputCollector,
 ) -> Signal {
     match stmt.as_rule() {
         Rule::incantation => {
@@ -143,7 +188,7 @@ fn match_incantation<'i>(
         }
         Rule::summon => handle_summon(stmt, scope, functions, output),
         Rule::evoke => {
-            handle_evoke(stmt, scope, None, output);
+            handle_evoke(stmt, scope, functions, None, output);
             Signal::None
         }
         Rule::scry_chain => handle_scry_chain(stmt, scope, functions, output),
@@ -152,10 +197,15 @@ fn match_incantation<'i>(
         Rule::chant_block => handle_chant_block(stmt, scope, functions, output),
         Rule::recite_block => handle_recite_block(stmt, scope, functions, output),
         Rule::loop_block => handle_loop_block(stmt, scope, functions, output),
+        Rule::divine_block => handle_divine_block(stmt, scope, functions, output),
         Rule::enchant => {
             handle_enchant(stmt, functions);
             Signal::None
         }
+        Rule::ritual => {
+            handle_ritual(stmt, scope, functions, output);
+            Signal::None
+        }
         Rule::cast => {
             handle_cast(stmt, scope, functions, output);
             Signal::None
@@ -181,7 +231,9 @@ fn handle_conjure(
     let expression_pair = inner.next().unwrap();
 
     let value = evaluate_expression(expression_pair, scope, functions, output);
-    scope.insert(ident, value);
+    if !output.has_error() {
+        scope.insert(ident, value);
+    }
 }
 
 // ─── Output ──────────────────────────────────────────────────────────
@@ -195,12 +247,9 @@ fn handle_incant(
     let expression_pair = pair.into_inner().next().unwrap();
     let result = evaluate_expression(expression_pair, scope, functions, output);
 
-    let text = match result {
-        ExprValue::String(s) => interpolate(&s, scope),
-        other => other.to_display_string(),
-    };
-
-    output.println(&text);
+    if !output.has_error() {
+        output.println(&result.to_display_string());
+    }
 }
 
 // ─── Error / Exit ────────────────────────────────────────────────────
@@ -286,14 +335,14 @@ fn handle_seal_block<'i>(
 // ─── Shell Commands ──────────────────────────────────────────────────
 
 #[cfg(target_family = "windows")]
-fn shell_command(command: &str, _shell_override: Option<&str>) -> std::process::Command {
+pub(crate) fn shell_command(command: &str, _shell_override: Option<&str>) -> std::process::Command {
     let mut cmd = std::process::Command::new("cmd");
     cmd.arg("/C").arg(command);
     cmd
 }
 
 #[cfg(not(target_family = "windows"))]
-fn shell_command(command: &str, shell_override: Option<&str>) -> std::process::Command {
+pub(crate) fn shell_command(command: &str, shell_override: Option<&str>) -> std::process::Command {
     if let Some(shell) = shell_override {
         let mut cmd = std::process::Command::new(shell);
         cmd.arg("-c").arg(command);
@@ -324,17 +373,45 @@ fn shell_command(command: &str, shell_override: Option<&str>) -> std::process::C
 fn handle_evoke(
     pair: pest::iterators::Pair<Rule>,
     scope: &mut HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
     shell_override: Option<&str>,
     output: &mut OutputCollector,
 ) {
-    let raw = pair.into_inner().next().unwrap().as_str().trim_matches('"');
-    let command = interpolate(raw, scope);
-    let cmd_output = shell_command(&command, shell_override).output();
+    let mut inner = pair.into_inner();
+    let command = evaluate_command_literal(inner.next().unwrap(), scope, functions, output);
+    let stdin = inner
+        .next()
+        .filter(|p| p.as_rule() == Rule::evoke_stdin)
+        .map(|p| {
+            let expr = p.into_inner().next().unwrap();
+            evaluate_expression(expr, scope, functions, output).to_display_string()
+        });
+    if output.has_error() {
+        return;
+    }
+
+    if output.is_dry_run() {
+        match &stdin {
+            Some(input) => output.dry_run_note(&format!(
+                "would evoke: {} (with {} line(s) on stdin)",
+                command,
+                input.lines().count()
+            )),
+            None => output.dry_run_note(&format!("would evoke: {}", command)),
+        }
+        scope.insert("_exit".to_string(), ExprValue::Integer(0));
+        return;
+    }
+
+    let cmd_output = match stdin {
+        Some(input) => run_with_stdin(shell_command(&command, shell_override), input),
+        None => shell_command(&command, shell_override).output(),
+    };
 
     match cmd_output {
         Ok(cmd_output) => {
             let code = cmd_output.status.code().unwrap_or(1);
-            scope.insert("_exit".to_string(), ExprValue::Number(code as f64));
+            scope.insert("_exit".to_string(), ExprValue::Integer(code as i64));
 
             if !cmd_output.stdout.is_empty() {
                 output.print(&String::from_utf8_lossy(&cmd_output.stdout));
@@ -352,6 +429,31 @@ fn handle_evoke(
     }
 }
 
+/// Run `cmd` with `input` written to its stdin, capturing stdout and stderr.
+fn run_with_stdin(
+    mut cmd: std::process::Command,
+    input: String,
+) -> std::io::Result<std::process::Output> {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Write from a separate thread so a command that fills its stdout pipe
+    // before reading all of stdin cannot deadlock us.
+    let mut child_stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || {
+        // A command that exits without reading its input is not an error
+        let _ = child_stdin.write_all(input.as_bytes());
+    });
+    let result = child.wait_with_output();
+    let _ = writer.join();
+    result
+}
+
 fn handle_scry_chain<'i>(
     pair: pest::iterators::Pair<'i, Rule>,
     scope: &mut HashMap<String, ExprValue>,
@@ -364,7 +466,11 @@ fn handle_scry_chain<'i>(
 
     let statements: Vec<_> = scry_block.into_inner().collect();
 
-    if eval_condition(scry_cond, scope, functions, output) {
+    let matched = eval_condition(scry_cond, scope, functions, output);
+    if let Some(signal) = raised_error(output) {
+        return signal;
+    }
+    if matched {
         for stmt in statements {
             let signal = match_incantation(stmt, scope, functions, output);
             if !matches!(signal, Signal::None) {
@@ -380,7 +486,11 @@ fn handle_scry_chain<'i>(
             let morph_cond = morph_inner.next().unwrap();
             let morph_block = morph_inner.next().unwrap();
 
-            if eval_condition(morph_cond, scope, functions, output) {
+            let matched = eval_condition(morph_cond, scope, functions, output);
+            if let Some(signal) = raised_error(output) {
+                return signal;
+            }
+            if matched {
                 for stmt in morph_block.into_inner() {
                     let signal = match_incantation(stmt, scope, functions, output);
                     if !matches!(signal, Signal::None) {
@@ -390,18 +500,332 @@ fn handle_scry_chain<'i>(
                 return Signal::None;
             }
         } else if morph.as_rule() == Rule::lest_block {
-            for stmt in morph.into_inner() {
-                let signal = match_incantation(stmt, scope, functions, output);
-                if !matches!(signal, Signal::None) {
+            return run_block(morph.into_inner().next().unwrap(), scope, functions, output);
+        }
+    }
+    Signal::None
+}
+
+/// Run the statements of a block, stopping at the first signal.
+fn run_block<'i>(
+    block: pest::iterators::Pair<'i, Rule>,
+    scope: &mut HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef<'i>>,
+    output: &mut OutputCollector,
+) -> Signal {
+    for stmt in block.into_inner() {
+        let signal = match_incantation(stmt, scope, functions, output);
+        if !matches!(signal, Signal::None) {
+            return signal;
+        }
+    }
+    Signal::None
+}
+
+// ─── Pattern Matching ────────────────────────────────────────────────
+
+fn handle_divine_block<'i>(
+    pair: pest::iterators::Pair<'i, Rule>,
+    scope: &mut HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef<'i>>,
+    output: &mut OutputCollector,
+) -> Signal {
+    let mut inner = pair.into_inner();
+    let subject = evaluate_expression(inner.next().unwrap(), scope, functions, output);
+    if let Some(signal) = raised_error(output) {
+        return signal;
+    }
+
+    for arm in inner {
+        if arm.as_rule() == Rule::lest_block {
+            return run_block(arm.into_inner().next().unwrap(), scope, functions, output);
+        }
+
+        let mut parts: Vec<_> = arm.into_inner().collect();
+        let block = parts.pop().unwrap();
+        let guard = match parts.last() {
+            Some(last) if last.as_rule() == Rule::guard => parts.pop(),
+            _ => None,
+        };
+
+        for pattern in parts {
+            let mut bindings = Vec::new();
+            let matched = match_pattern(pattern, &subject, &mut bindings, scope, functions, output);
+            if let Some(signal) = raised_error(output) {
+                return signal;
+            }
+            if !matched {
+                continue;
+            }
+
+            let previous: Vec<_> = bindings
+                .into_iter()
+                .map(|(name, value)| {
+                    let old = scope.insert(name.clone(), value);
+                    (name, old)
+                })
+                .collect();
+            if let Some(guard) = &guard {
+                let condition = guard.clone().into_inner().next().unwrap();
+                let passed = eval_condition(condition, scope, functions, output);
+                if let Some(signal) = raised_error(output) {
                     return signal;
                 }
+                if !passed {
+                    // Undo the bindings of an arm whose guard failed
+                    for (name, old) in previous.into_iter().rev() {
+                        match old {
+                            Some(value) => scope.insert(name, value),
+                            None => scope.remove(&name),
+                        };
+                    }
+                    continue;
+                }
             }
-            return Signal::None;
+            return run_block(block, scope, functions, output);
         }
     }
     Signal::None
 }
 
+/// Match `value` against a pattern, collecting the names it binds.
+fn match_pattern(
+    pattern: pest::iterators::Pair<Rule>,
+    value: &ExprValue,
+    bindings: &mut Vec<(String, ExprValue)>,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) -> bool {
+    let inner = pattern.into_inner().next().unwrap();
+    match inner.as_rule() {
+        Rule::wildcard => true,
+        Rule::IDENT => {
+            bindings.push((inner.as_str().to_string(), value.clone()));
+            true
+        }
+        Rule::literal_pattern => {
+            let literal = pattern_literal(inner, scope, functions, output);
+            same_kind(value, &literal) && values_equal(value, &literal)
+        }
+        Rule::range_pattern => {
+            let mut parts = inner.into_inner();
+            let low = pattern_literal(parts.next().unwrap(), scope, functions, output);
+            let inclusive = parts.next().unwrap().as_str() == "..=";
+            let high = pattern_literal(parts.next().unwrap(), scope, functions, output);
+            if !same_kind(value, &low) || !same_kind(value, &high) {
+                return false;
+            }
+            let above_low = compare_values(value, &low) != std::cmp::Ordering::Less;
+            let below_high = match compare_values(value, &high) {
+                std::cmp::Ordering::Less => true,
+                std::cmp::Ordering::Equal => inclusive,
+                std::cmp::Ordering::Greater => false,
+            };
+            above_low && below_high
+        }
+        Rule::list_pattern => {
+            let ExprValue::List(items) = value else {
+                return false;
+            };
+            let mut fixed = Vec::new();
+            let mut rest = None;
+            for part in inner.into_inner() {
+                match part.as_rule() {
+                    Rule::rest_pattern => rest = Some(part),
+                    _ => fixed.push(part),
+                }
+            }
+            let count = fixed.len();
+            if items.len() < count || (rest.is_none() && items.len() != count) {
+                return false;
+            }
+            for (part, item) in fixed.into_iter().zip(items) {
+                if !match_pattern(part, item, bindings, scope, functions, output) {
+                    return false;
+                }
+            }
+            if let Some(name) = rest.and_then(|r| r.into_inner().next()) {
+                bindings.push((
+                    name.as_str().to_string(),
+                    ExprValue::List(items[count..].to_vec()),
+                ));
+            }
+            true
+        }
+        Rule::map_pattern => {
+            let ExprValue::Map(map) = value else {
+                return false;
+            };
+            for entry in inner.into_inner() {
+                let mut parts = entry.into_inner();
+                let key_pair = parts.next().unwrap();
+                let is_name = key_pair.as_rule() == Rule::IDENT;
+                let key = match key_pair.as_rule() {
+                    Rule::string => process_escape_sequences(key_pair.as_str().trim_matches('"')),
+                    _ => key_pair.as_str().to_string(),
+                };
+                let Some(field) = map.get(&key) else {
+                    return false;
+                };
+                let matched = match parts.next() {
+                    Some(part) => match_pattern(part, field, bindings, scope, functions, output),
+                    None => {
+                        // `{os}` binds the field to a variable of the same name
+                        if is_name {
+                            bindings.push((key, field.clone()));
+                        }
+                        true
+                    }
+                };
+                if !matched {
+                    return false;
+                }
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+fn pattern_literal(
+    pair: pest::iterators::Pair<Rule>,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) -> ExprValue {
+    let inner = pair.into_inner().next().unwrap();
+    match inner.as_rule() {
+        Rule::number => parse_number(inner.as_str(), output),
+        Rule::boolean => ExprValue::Boolean(inner.as_str() == "true"),
+        Rule::none => ExprValue::None,
+        _ => ExprValue::String(evaluate_string_literal(inner, scope, functions, output)),
+    }
+}
+
+/// Patterns only match values of the same kind, so `"1"` does not match `1`.
+fn same_kind(left: &ExprValue, right: &ExprValue) -> bool {
+    let numeric = |v: &ExprValue| matches!(v, ExprValue::Integer(_) | ExprValue::Number(_));
+    (numeric(left) && numeric(right))
+        || std::mem::discriminant(left) == std::mem::discriminant(right)
+}
+
+/// Warn about `divine` statements that can miss a value of a known set, such
+/// as the platforms `platform()` returns. A subject counts as coming from such
+/// a builtin when it calls it directly, names a variable conjured from it, or
+/// when every string pattern belongs to its set.
+fn check_divine_exhaustive(pair: pest::iterators::Pair<Rule>, output: &mut OutputCollector) {
+    let mut sources = HashMap::new();
+    check_divine_in(pair, &mut sources, output);
+}
+
+fn check_divine_in(
+    pair: pest::iterators::Pair<Rule>,
+    sources: &mut HashMap<String, &'static str>,
+    output: &mut OutputCollector,
+) {
+    for child in pair.into_inner() {
+        match child.as_rule() {
+            Rule::conjure => {
+                let mut parts = child.clone().into_inner();
+                let name = parts.next().unwrap().as_str().to_string();
+                match parts
+                    .next()
+                    .and_then(|expr| known_builtin_call(expr.as_str()))
+                {
+                    Some(builtin) => sources.insert(name, builtin),
+                    None => sources.remove(&name),
+                };
+            }
+            Rule::divine_block => check_divine(child.clone(), sources, output),
+            _ => {}
+        }
+        check_divine_in(child, sources, output);
+    }
+}
+
+fn check_divine(
+    pair: pest::iterators::Pair<Rule>,
+    sources: &HashMap<String, &'static str>,
+    output: &mut OutputCollector,
+) {
+    let line = pair.as_span().start_pos().line_col().0;
+    let mut inner = pair.into_inner();
+    let subject = inner.next().unwrap().as_str().trim();
+
+    let mut literals = Vec::new();
+    let mut covered = Vec::new();
+    for arm in inner {
+        if arm.as_rule() == Rule::lest_block {
+            return;
+        }
+        let parts: Vec<_> = arm.into_inner().collect();
+        let guarded = parts.iter().any(|p| p.as_rule() == Rule::guard);
+        for pattern in parts.iter().filter(|p| p.as_rule() == Rule::pattern) {
+            let inner = pattern.clone().into_inner().next().unwrap();
+            match inner.as_rule() {
+                Rule::wildcard | Rule::IDENT if !guarded => return,
+                Rule::literal_pattern => {
+                    let literal = inner.into_inner().next().unwrap();
+                    let text = match literal.as_rule() {
+                        Rule::string => {
+                            process_escape_sequences(literal.as_str().trim_matches('"'))
+                        }
+                        Rule::raw_string => {
+                            literal.as_str()[2..literal.as_str().len() - 1].to_string()
+                        }
+                        _ => continue,
+                    };
+                    if !guarded {
+                        covered.push(text.clone());
+                    }
+                    literals.push(text);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let known = known_builtin_call(subject)
+        .or_else(|| sources.get(subject).copied())
+        .and_then(|name| {
+            builtins::KNOWN_RESULTS
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+        })
+        .or_else(|| {
+            builtins::KNOWN_RESULTS.iter().find(|(_, values)| {
+                !literals.is_empty() && literals.iter().all(|l| values.contains(&l.as_str()))
+            })
+        });
+    let Some((builtin, values)) = known else {
+        return;
+    };
+    let missing: Vec<String> = values
+        .iter()
+        .filter(|v| !covered.iter().any(|c| c == *v))
+        .map(|v| format!("\"{}\"", v))
+        .collect();
+    if !missing.is_empty() {
+        output.eprintln(&format!(
+            "warning: line {}: divine does not handle {} from {}(); add a lest arm",
+            line,
+            missing.join(", "),
+            builtin
+        ));
+    }
+}
+
+/// The builtin named by `cast name()` if it has a known set of results.
+fn known_builtin_call(expr: &str) -> Option<&'static str> {
+    let compact: String = expr.chars().filter(|c| !c.is_whitespace()).collect();
+    let name = compact.strip_prefix("cast")?.strip_suffix("()")?;
+    builtins::KNOWN_RESULTS
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(builtin, _)| *builtin)
+}
+
 // ─── Loops ───────────────────────────────────────────────────────────
 
 fn handle_loop_block<'i>(
@@ -441,6 +865,9 @@ fn handle_channel_block<'i>(
 
     let mut iteration_count = 0;
     'outer: while eval_condition(cond.clone(), scope, functions, output) {
+        if let Some(signal) = raised_error(output) {
+            return signal;
+        }
         iteration_count += 1;
 
         if iteration_count > 10 {
@@ -493,12 +920,16 @@ fn handle_chant_block<'i>(
     let step_val = if let Some(step) = step_expr {
         evaluate_expression(step, scope, functions, output)
     } else {
-        ExprValue::Number(1.0)
+        ExprValue::Integer(1)
     };
 
-    let start_num = expr_to_i32(&start_val, "Start", output);
-    let end_num = expr_to_i32(&end_val, "End", output);
-    let step_num = expr_to_i32(&step_val, "Step", output);
+    if let Some(signal) = raised_error(output) {
+        return signal;
+    }
+
+    let start_num = expr_to_i64(&start_val, "Start", output);
+    let end_num = expr_to_i64(&end_val, "End", output);
+    let step_num = expr_to_i64(&step_val, "Step", output);
 
     let (start_num, end_num, step_num) = match (start_num, end_num, step_num) {
         (Some(s), Some(e), Some(st)) => (s, e, st),
@@ -513,7 +944,7 @@ fn handle_chant_block<'i>(
     if step_num > 0 {
         let mut current = start_num;
         'outer: while current < end_num {
-            scope.insert(loop_var.clone(), ExprValue::Number(current as f64));
+            scope.insert(loop_var.clone(), ExprValue::Integer(current));
 
             for stmt in &statements {
                 match match_incantation(stmt.clone(), scope, functions, output) {
@@ -525,12 +956,15 @@ fn handle_chant_block<'i>(
                 }
             }
 
-            current += step_num;
+            match current.checked_add(step_num) {
+                Some(next) => current = next,
+                None => break,
+            }
         }
     } else {
         let mut current = start_num;
         'outer: while current > end_num {
-            scope.insert(loop_var.clone(), ExprValue::Number(current as f64));
+            scope.insert(loop_var.clone(), ExprValue::Integer(current));
 
             for stmt in &statements {
                 match match_incantation(stmt.clone(), scope, functions, output) {
@@ -542,7 +976,10 @@ fn handle_chant_block<'i>(
                 }
             }
 
-            current += step_num;
+            match current.checked_add(step_num) {
+                Some(next) => current = next,
+                None => break,
+            }
         }
     }
     Signal::None
@@ -554,64 +991,119 @@ fn handle_recite_block<'i>(
     functions: &mut HashMap<String, FunctionDef<'i>>,
     output: &mut OutputCollector,
 ) -> Signal {
-    let mut inner = pair.into_inner();
-    let loop_var = inner.next().unwrap().as_str().to_string();
+    let mut inner = pair.into_inner().peekable();
+    let mut bindings = Vec::new();
+    while let Some(binding) = inner.next_if(|p| p.as_rule() == Rule::recite_binding) {
+        let unpack = binding.as_str().starts_with('[');
+        let names: Vec<String> = binding
+            .into_inner()
+            .map(|p| p.as_str().to_string())
+            .collect();
+        bindings.push((names, unpack));
+    }
     let list_expr = inner.next().unwrap();
     let block = inner.next().unwrap();
     let statements: Vec<_> = block.into_inner().collect();
 
     let list_val = evaluate_factor(list_expr, scope, functions, output);
-
-    macro_rules! run_loop {
-        ($iter:expr) => {
-            'outer: for item in $iter {
-                scope.insert(loop_var.clone(), item);
-                for stmt in &statements {
-                    match match_incantation(stmt.clone(), scope, functions, output) {
-                        Signal::Break => break 'outer,
-                        Signal::Continue => break,
-                        Signal::Return(val) => return Signal::Return(val),
-                        Signal::Error(msg) => return Signal::Error(msg),
-                        Signal::None => {}
-                    }
-                }
-            }
-        };
+    if let Some(signal) = raised_error(output) {
+        return signal;
     }
 
-    match list_val {
+    // Each step yields (key, item): the map key or the position, and the
+    // value. A single binding gets the key for maps and the item otherwise.
+    let single_binds_key = matches!(list_val, ExprValue::Map(_));
+    let steps: Box<dyn Iterator<Item = (ExprValue, ExprValue)>> = match list_val {
+        ExprValue::None => return Signal::None,
         ExprValue::String(s) => {
-            if s.trim().is_empty() {
-                return Signal::None;
-            }
             let items: Vec<ExprValue> = s
                 .split(',')
                 .map(|s| s.trim())
                 .filter(|item| !item.is_empty())
                 .map(|item| ExprValue::String(item.to_string()))
                 .collect();
-            run_loop!(items.into_iter());
+            Box::new(indexed(items.into_iter()))
+        }
+        ExprValue::Integer(n) => Box::new(indexed((0..n).map(ExprValue::Integer))),
+        ExprValue::Number(n) if n.fract() == 0.0 => {
+            Box::new(indexed((0..n as i64).map(ExprValue::Integer)))
         }
         ExprValue::Number(n) => {
-            let items: Vec<ExprValue> = (0..(n as i32))
-                .map(|i| ExprValue::Number(i as f64))
-                .collect();
-            run_loop!(items.into_iter());
+            return Signal::Error(format!("Cannot recite over {}: not a whole number", n));
+        }
+        ExprValue::Boolean(b) => {
+            return Signal::Error(format!("Cannot recite over a boolean ({})", b));
         }
-        ExprValue::Boolean(_b) => {
-            run_loop!(std::iter::once(ExprValue::Number(0.0)));
+        ExprValue::List(l) => Box::new(indexed(l.into_iter())),
+        ExprValue::Map(m) => Box::new(m.into_iter().map(|(k, v)| (ExprValue::String(k), v))),
+        ExprValue::Function(name) => {
+            return Signal::Error(format!("Cannot recite over function '{}'", name));
         }
-        ExprValue::List(l) => {
-            run_loop!(l.into_iter());
+    };
+
+    'outer: for (key, item) in steps {
+        let bound = match bindings.as_slice() {
+            [single] if single_binds_key => bind_recite(single, key, scope),
+            [single] => bind_recite(single, item, scope),
+            [first, second] => {
+                bind_recite(first, key, scope).and_then(|_| bind_recite(second, item, scope))
+            }
+            _ => unreachable!("recite takes one or two bindings"),
+        };
+        if let Err(e) = bound {
+            return Signal::Error(e);
         }
-        ExprValue::Map(m) => {
-            let keys: Vec<ExprValue> = m.into_keys().map(ExprValue::String).collect();
-            run_loop!(keys.into_iter());
+        for stmt in &statements {
+            match match_incantation(stmt.clone(), scope, functions, output) {
+                Signal::Break => break 'outer,
+                Signal::Continue => break,
+                Signal::Return(val) => return Signal::Return(val),
+                Signal::Error(msg) => return Signal::Error(msg),
+                Signal::None => {}
+            }
         }
     }
     Signal::None
 }
 
+/// Pair each item with its position.
+fn indexed(items: impl Iterator<Item = ExprValue>) -> impl Iterator<Item = (ExprValue, ExprValue)> {
+    items
+        .enumerate()
+        .map(|(i, item)| (ExprValue::Integer(i as i64), item))
+}
+
+/// Bind a recite loop variable. A bracketed binding such as `[key, value]`
+/// unpacks a list with exactly that many items.
+fn bind_recite(
+    (names, unpack): &(Vec<String>, bool),
+    value: ExprValue,
+    scope: &mut HashMap<String, ExprValue>,
+) -> Result<(), String> {
+    if !unpack {
+        scope.insert(names[0].clone(), value);
+        return Ok(());
+    }
+    match value {
+        ExprValue::List(items) if items.len() == names.len() => {
+            for (name, item) in names.iter().zip(items) {
+                scope.insert(name.clone(), item);
+            }
+            Ok(())
+        }
+        ExprValue::List(items) => Err(format!(
+            "Cannot unpack {} item(s) into [{}]",
+            items.len(),
+            names.join(", ")
+        )),
+        other => Err(format!(
+            "Cannot unpack {} into [{}]: not a list",
+            other,
+            names.join(", ")
+        )),
+    }
+}
+
 fn handle_enchant<'i>(
     pair: pest::iterators::Pair<'i, Rule>,
     functions: &mut HashMap<String, FunctionDef<'i>>,
@@ -636,6 +1128,75 @@ fn handle_enchant<'i>(
     functions.insert(name, func);
 }
 
+/// Register the `enchant` definitions at the top level of a program without
+/// running anything else. Task bodies use this to call the functions of
+/// their task file.
+pub(crate) fn define_functions<'i>(
+    pairs: Pairs<'i, Rule>,
+    functions: &mut HashMap<String, FunctionDef<'i>>,
+) {
+    for pair in pairs.filter(|p| p.as_rule() == Rule::program) {
+        for incantation in pair.into_inner() {
+            if let Some(stmt) = incantation.into_inner().next()
+                && stmt.as_rule() == Rule::enchant
+            {
+                handle_enchant(stmt, functions);
+            }
+        }
+    }
+}
+
+// ─── Tasks ───────────────────────────────────────────────────────────
+
+/// Declare a task. The body is kept as source and only runs from `mage task`.
+fn handle_ritual(
+    pair: pest::iterators::Pair<Rule>,
+    scope: &mut HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) {
+    let mut inner = pair.into_inner();
+    let name = inner.next().unwrap().as_str().to_string();
+    let mut task = Task::new(&name);
+
+    for clause in inner {
+        if clause.as_rule() == Rule::block {
+            let body = clause.as_str();
+            task.body = body[1..body.len() - 1].to_string();
+            break;
+        }
+
+        let mut parts = clause.into_inner();
+        let keyword = parts.next().unwrap().as_str();
+        let value = evaluate_expression(parts.next().unwrap(), scope, functions, output);
+        if output.has_error() {
+            return;
+        }
+        let paths = match value {
+            ExprValue::String(s) => vec![s],
+            ExprValue::List(items) if items.iter().all(|i| matches!(i, ExprValue::String(_))) => {
+                items.iter().map(|i| i.to_display_string()).collect()
+            }
+            other => {
+                output.raise(format!(
+                    "Task '{}': {} expects a string or a list of strings, got {}",
+                    name, keyword, other
+                ));
+                return;
+            }
+        };
+        match keyword {
+            "needs" => task.needs.extend(paths),
+            "reads" => task.reads.extend(paths),
+            _ => task.makes.extend(paths),
+        }
+    }
+
+    if let Err(e) = output.declare_task(task) {
+        output.raise(e);
+    }
+}
+
 fn handle_cast<'i>(
     pair: pest::iterators::Pair<'i, Rule>,
     parent_scope: &mut HashMap<String, ExprValue>,
@@ -647,8 +1208,18 @@ fn handle_cast<'i>(
     let args_pair = inner.next();
 
     let args = resolve_args(args_pair, parent_scope, functions, output);
+    if output.has_error() {
+        return;
+    }
 
-    if builtins::is_builtin(name) {
+    if builtins::is_value_builtin(name) {
+        match builtins::call_value_builtin(name, args, output) {
+            Ok(ExprValue::Boolean(true)) | Ok(ExprValue::None) => {}
+            Ok(ExprValue::String(s)) if s.is_empty() => {}
+            Ok(result) => output.println(&result.to_display_string()),
+            Err(e) => output.eprintln(&format!("Error calling {}: {}", name, e)),
+        }
+    } else if builtins::is_builtin(name) {
         let string_args: Vec<String> = args.iter().map(|a| a.to_display_string()).collect();
         match builtins::call_builtin(name, string_args, output) {
             Ok(result) => match result {
@@ -658,21 +1229,47 @@ fn handle_cast<'i>(
             },
             Err(e) => output.eprintln(&format!("Error calling {}: {}", name, e)),
         }
-    } else if let Some(func) = functions.get(name) {
-        let mut scope = parent_scope.clone();
-        for (param, arg) in func.params.iter().zip(args.into_iter()) {
-            scope.insert(param.clone(), arg);
-        }
-        for stmt in func.body.clone() {
-            if let Signal::Return(_val) = match_incantation(stmt, &mut scope, functions, output) {
-                return;
-            }
-        }
+    } else if functions.contains_key(name) {
+        call_user_function(name, args, parent_scope, functions, output);
+    } else if let Some(ExprValue::Function(target)) = parent_scope.get(name).cloned() {
+        call_user_function(&target, args, parent_scope, functions, output);
     } else {
         output.eprintln(&format!("Unknown function: {}", name));
     }
 }
 
+/// Run a function defined with `enchant`. Errors it signals are raised to
+/// the caller; a function that does not `bestow` returns an empty string.
+fn call_user_function(
+    name: &str,
+    args: Vec<ExprValue>,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) -> ExprValue {
+    let Some(func) = functions.get(name) else {
+        output.raise(format!("Unknown function: {}", name));
+        return ExprValue::None;
+    };
+    let mut func_scope = scope.clone();
+    for (param, arg) in func.params.iter().zip(args) {
+        func_scope.insert(param.clone(), arg);
+    }
+
+    for stmt in func.body.clone() {
+        match match_incantation(stmt, &mut func_scope, functions, output) {
+            Signal::Return(val) => return val,
+            Signal::Error(msg) => {
+                output.raise(msg);
+                return ExprValue::None;
+            }
+            _ => {}
+        }
+    }
+
+    ExprValue::String("".to_string())
+}
+
 /// Resolve an argument list from parsed pairs into typed ExprValues
 fn resolve_args(
     args_pair: Option<pest::iterators::Pair<Rule>>,
@@ -736,12 +1333,23 @@ fn evaluate_expression(
     match pair.as_rule() {
         Rule::expression => {
             let mut inner = pair.into_inner();
-            let mut result = evaluate_term(inner.next().unwrap(), scope, functions, output);
-
-            while let Some(op) = inner.next() {
-                let term = inner.next().unwrap();
-                let term_val = evaluate_term(term, scope, functions, output);
-                result = apply_add_op(result, op.as_str(), term_val, output);
+            let had_error = output.has_error();
+            let undefined_before = output.undefined_reads();
+            let errors_before = output.errors_raised();
+            let mut result = evaluate_sum(inner.next().unwrap(), scope, functions, output);
+
+            // `value ? default` falls back when `value` is none or undefined
+            while let Some(_op) = inner.next() {
+                let fallback = inner.next().unwrap();
+                let undefined = output.undefined_reads() > undefined_before;
+                if matches!(result, ExprValue::None) || undefined {
+                    // Only the strict-mode undefined variable error is handled
+                    // by the fallback; any other error stays pending
+                    if undefined && !had_error && output.errors_raised() == errors_before {
+                        output.take_error();
+                    }
+                    result = evaluate_sum(fallback, scope, functions, output);
+                }
             }
             result
         }
@@ -752,6 +1360,23 @@ fn evaluate_expression(
     }
 }
 
+fn evaluate_sum(
+    pair: pest::iterators::Pair<Rule>,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) -> ExprValue {
+    let mut inner = pair.into_inner();
+    let mut result = evaluate_term(inner.next().unwrap(), scope, functions, output);
+
+    while let Some(op) = inner.next() {
+        let term = inner.next().unwrap();
+        let term_val = evaluate_term(term, scope, functions, output);
+        result = apply_add_op(result, op.as_str(), term_val, output);
+    }
+    result
+}
+
 fn evaluate_term(
     pair: pest::iterators::Pair<Rule>,
     scope: &HashMap<String, ExprValue>,
@@ -780,30 +1405,31 @@ fn evaluate_function_call(
     let args_pair = inner.next();
 
     let args = resolve_args(args_pair, scope, functions, output);
+    if output.has_error() {
+        return ExprValue::None;
+    }
 
-    if builtins::is_builtin(name) {
+    if builtins::is_value_builtin(name) {
+        match builtins::call_value_builtin(name, args, output) {
+            Ok(result) => result,
+            Err(e) => {
+                output.eprintln(&format!("Error calling {}: {}", name, e));
+                ExprValue::None
+            }
+        }
+    } else if builtins::is_builtin(name) {
         let string_args: Vec<String> = args.iter().map(|a| a.to_display_string()).collect();
         match builtins::call_builtin(name, string_args, output) {
             Ok(result) => builtin_to_expr(result),
             Err(e) => {
                 output.eprintln(&format!("Error calling {}: {}", name, e));
-                ExprValue::String("".to_string())
+                ExprValue::None
             }
         }
-    } else if let Some(func) = functions.get(name) {
-        let mut func_scope = scope.clone();
-        for (param, arg) in func.params.iter().zip(args.iter()) {
-            func_scope.insert(param.clone(), arg.clone());
-        }
-
-        for stmt in func.body.clone() {
-            if let Signal::Return(val) = match_incantation(stmt, &mut func_scope, functions, output)
-            {
-                return val;
-            }
-        }
-
-        ExprValue::String("".to_string())
+    } else if functions.contains_key(name) {
+        call_user_function(name, args, scope, functions, output)
+    } else if let Some(ExprValue::Function(target)) = scope.get(name) {
+        call_user_function(target, args, scope, functions, output)
     } else {
         output.eprintln(&format!("Unknown function: {}", name));
         ExprValue::String("".to_string())
@@ -826,13 +1452,20 @@ fn evaluate_method_call(
             let var_name = object_pair.as_str();
             if let Some(val) = scope.get(var_name) {
                 val.clone()
+            } else if output.is_strict() {
+                output.undefined_variable(var_name);
+                return ExprValue::None;
             } else {
+                output.undefined_variable(var_name);
                 output.eprintln(&format!("Unknown variable: {}", var_name));
                 ExprValue::String("".to_string())
             }
         }
-        Rule::string => ExprValue::String(process_escape_sequences(
-            object_pair.as_str().trim_matches('"'),
+        Rule::string => ExprValue::String(evaluate_string_literal(
+            object_pair,
+            scope,
+            functions,
+            output,
         )),
         _ => evaluate_factor(object_pair, scope, functions, output),
     };
@@ -841,8 +1474,8 @@ fn evaluate_method_call(
 
     match object {
         ExprValue::String(s) => call_string_method(&s, method_name, args, output),
-        ExprValue::List(l) => call_list_method(&l, method_name, args, output),
-        ExprValue::Map(m) => call_map_method(&m, method_name, args, output),
+        ExprValue::List(l) => call_list_method(&l, method_name, args, scope, functions, output),
+        ExprValue::Map(m) => call_map_method(&m, method_name, args, scope, functions, output),
         _ => {
             output.eprintln(&format!("Cannot call method on {:?}", object));
             ExprValue::String("".to_string())
@@ -864,11 +1497,12 @@ fn evaluate_factor(
         Rule::value => {
             let inner_value = pair.into_inner().next().unwrap();
             match inner_value.as_rule() {
-                Rule::string => ExprValue::String(process_escape_sequences(
-                    inner_value.as_str().trim_matches('"'),
-                )),
-                Rule::number => ExprValue::Number(inner_value.as_str().parse().unwrap_or(0.0)),
+                Rule::string | Rule::triple_string | Rule::raw_string => ExprValue::String(
+                    evaluate_string_literal(inner_value, scope, functions, output),
+                ),
+                Rule::number => parse_number(inner_value.as_str(), output),
                 Rule::boolean => ExprValue::Boolean(inner_value.as_str() == "true"),
+                Rule::none => ExprValue::None,
                 Rule::list => ExprValue::List(
                     inner_value
                         .into_inner()
@@ -880,12 +1514,15 @@ fn evaluate_factor(
                         .into_inner()
                         .map(|v| {
                             let mut parts = v.into_inner();
-                            let key = parts.next().unwrap().as_str();
+                            let key_pair = parts.next().unwrap();
+                            let key = match key_pair.as_rule() {
+                                Rule::string => {
+                                    process_escape_sequences(key_pair.as_str().trim_matches('"'))
+                                }
+                                _ => key_pair.as_str().to_string(),
+                            };
                             let value = parts.next().unwrap();
-                            (
-                                key.to_string(),
-                                evaluate_expression(value, scope, functions, output),
-                            )
+                            (key, evaluate_expression(value, scope, functions, output))
                         })
                         .collect(),
                 ),
@@ -893,13 +1530,21 @@ fn evaluate_factor(
                     let var_name = inner_value.as_str();
                     if let Some(val) = scope.get(var_name) {
                         val.clone()
+                    } else if functions.contains_key(var_name) {
+                        ExprValue::Function(var_name.to_string())
                     } else {
-                        ExprValue::String(format!("${{{}}}", var_name))
+                        output.undefined_variable(var_name);
+                        if output.is_strict() {
+                            ExprValue::None
+                        } else {
+                            ExprValue::String(format!("${{{}}}", var_name))
+                        }
                     }
                 }
+                Rule::field_access => evaluate_field_access(inner_value, scope, output),
                 Rule::call => evaluate_function_call(inner_value, scope, functions, output),
                 Rule::method_call => evaluate_method_call(inner_value, scope, functions, output),
-                Rule::imbue => evaluate_imbue(inner_value, scope, None, output),
+                Rule::imbue => evaluate_imbue(inner_value, scope, functions, None, output),
                 _ => ExprValue::Number(0.0),
             }
         }
@@ -911,14 +1556,62 @@ fn evaluate_factor(
     }
 }
 
+/// Evaluate `name.field.0`: map keys and list indices separated by dots.
+fn evaluate_field_access(
+    pair: pest::iterators::Pair<Rule>,
+    scope: &HashMap<String, ExprValue>,
+    output: &mut OutputCollector,
+) -> ExprValue {
+    let path = pair.as_str();
+    let mut parts = pair.into_inner();
+    let name = parts.next().unwrap().as_str();
+
+    let Some(mut current) = scope.get(name).cloned() else {
+        output.undefined_variable(name);
+        return if output.is_strict() {
+            ExprValue::None
+        } else {
+            ExprValue::String(format!("${{{}}}", path))
+        };
+    };
+
+    for part in parts {
+        let field = part.as_str();
+        let next = match &current {
+            ExprValue::Map(m) => m.get(field).cloned(),
+            ExprValue::List(l) => field.parse::<usize>().ok().and_then(|i| l.get(i).cloned()),
+            _ => None,
+        };
+        match next {
+            Some(value) => current = value,
+            None => {
+                if output.is_strict() {
+                    output.raise(format!("No field '{}' in {}", field, path));
+                }
+                return ExprValue::None;
+            }
+        }
+    }
+    current
+}
+
 fn evaluate_imbue(
     pair: pest::iterators::Pair<'_, Rule>,
     scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
     shell_override: Option<&str>,
     output: &mut OutputCollector,
 ) -> ExprValue {
-    let raw = pair.into_inner().next().unwrap().as_str().trim_matches('"');
-    let command = interpolate(raw, scope);
+    let literal = pair.into_inner().next().unwrap();
+    let command = evaluate_command_literal(literal, scope, functions, output);
+    if output.has_error() {
+        return ExprValue::None;
+    }
+
+    if output.is_dry_run() {
+        output.dry_run_note(&format!("would imbue: {}", command));
+        return ExprValue::String("".to_string());
+    }
 
     match shell_command(&command, shell_override).output() {
         Ok(cmd_output) => {
@@ -943,11 +1636,25 @@ fn apply_add_op(
     output: &mut OutputCollector,
 ) -> ExprValue {
     match (&left, &right) {
-        (ExprValue::Number(l), ExprValue::Number(r)) => match op {
-            "+" => ExprValue::Number(l + r),
-            "-" => ExprValue::Number(l - r),
-            _ => ExprValue::Number(0.0),
-        },
+        (ExprValue::Integer(l), ExprValue::Integer(r)) => {
+            let result = match op {
+                "+" => l.checked_add(*r),
+                "-" => l.checked_sub(*r),
+                _ => Some(0),
+            };
+            integer_result(result, &left, op, &right, output)
+        }
+        (
+            ExprValue::Number(_) | ExprValue::Integer(_),
+            ExprValue::Number(_) | ExprValue::Integer(_),
+        ) => {
+            let (l, r) = (as_float(&left), as_float(&right));
+            match op {
+                "+" => ExprValue::Number(l + r),
+                "-" => ExprValue::Number(l - r),
+                _ => ExprValue::Number(0.0),
+            }
+        }
         (ExprValue::String(l), ExprValue::String(r)) if op == "+" => {
             ExprValue::String(format!("{}{}", l, r))
         }
@@ -980,12 +1687,33 @@ fn apply_mult_op(
     output: &mut OutputCollector,
 ) -> ExprValue {
     match (&left, &right) {
-        (ExprValue::Number(l), ExprValue::Number(r)) => match op {
-            "*" => ExprValue::Number(l * r),
-            "/" => ExprValue::Number(if *r != 0.0 { l / r } else { 0.0 }),
-            "%" => ExprValue::Number(if *r != 0.0 { l % r } else { 0.0 }),
-            _ => ExprValue::Number(0.0),
+        (
+            ExprValue::Number(_) | ExprValue::Integer(_),
+            ExprValue::Number(_) | ExprValue::Integer(_),
+        ) if op != "*" && as_float(&right) == 0.0 => {
+            output.raise(format!("Division by zero: {} {} {}", left, op, right));
+            ExprValue::None
+        }
+        (ExprValue::Integer(l), ExprValue::Integer(r)) => match op {
+            "*" => integer_result(l.checked_mul(*r), &left, op, &right, output),
+            "/" => ExprValue::Number(*l as f64 / *r as f64),
+            "//" => integer_result(l.checked_div(*r), &left, op, &right, output),
+            "%" => integer_result(l.checked_rem(*r), &left, op, &right, output),
+            _ => ExprValue::Integer(0),
         },
+        (
+            ExprValue::Number(_) | ExprValue::Integer(_),
+            ExprValue::Number(_) | ExprValue::Integer(_),
+        ) => {
+            let (l, r) = (as_float(&left), as_float(&right));
+            match op {
+                "*" => ExprValue::Number(l * r),
+                "/" => ExprValue::Number(l / r),
+                "//" => ExprValue::Number((l / r).trunc()),
+                "%" => ExprValue::Number(l % r),
+                _ => ExprValue::Number(0.0),
+            }
+        }
         _ => {
             output.eprintln(&format!("Invalid operation: {} {} {}", left, op, right));
             ExprValue::Number(0.0)
@@ -1026,13 +1754,32 @@ fn eval_condition(
     }
 }
 
+/// Rank of each kind of value, used to order values of different kinds.
+fn kind_rank(value: &ExprValue) -> u8 {
+    match value {
+        ExprValue::None => 0,
+        ExprValue::Boolean(_) => 1,
+        ExprValue::Number(_) | ExprValue::Integer(_) => 2,
+        ExprValue::String(_) => 3,
+        ExprValue::List(_) => 4,
+        ExprValue::Map(_) => 5,
+        ExprValue::Function(_) => 6,
+    }
+}
+
 fn compare_values(left: &ExprValue, right: &ExprValue) -> std::cmp::Ordering {
     match (left, right) {
-        (ExprValue::Number(l), ExprValue::Number(r)) => {
-            l.partial_cmp(r).unwrap_or(std::cmp::Ordering::Equal)
-        }
+        (ExprValue::None, ExprValue::None) => std::cmp::Ordering::Equal,
+        (ExprValue::Integer(l), ExprValue::Integer(r)) => l.cmp(r),
+        (
+            ExprValue::Number(_) | ExprValue::Integer(_),
+            ExprValue::Number(_) | ExprValue::Integer(_),
+        ) => as_float(left)
+            .partial_cmp(&as_float(right))
+            .unwrap_or(std::cmp::Ordering::Equal),
         (ExprValue::String(l), ExprValue::String(r)) => l.cmp(r),
         (ExprValue::Boolean(l), ExprValue::Boolean(r)) => l.cmp(r),
+        (ExprValue::Function(l), ExprValue::Function(r)) => l.cmp(r),
         (ExprValue::List(l), ExprValue::List(r)) => {
             let mut l_iter = l.iter();
             let mut r_iter = r.iter();
@@ -1049,27 +1796,79 @@ fn compare_values(left: &ExprValue, right: &ExprValue) -> std::cmp::Ordering {
             let mut r_keys = r.keys().collect::<Vec<&String>>();
             l_keys.sort();
             r_keys.sort();
-            l_keys.cmp(&r_keys)
+            l_keys.cmp(&r_keys).then_with(|| {
+                l_keys
+                    .iter()
+                    .map(|k| compare_values(&l[*k], &r[*k]))
+                    .find(|ord| *ord != std::cmp::Ordering::Equal)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
         }
-        _ => left.to_string().cmp(&right.to_string()),
+        // Values of different kinds never compare equal
+        _ => kind_rank(left).cmp(&kind_rank(right)),
     }
 }
 
 // ─── Helpers ─────────────────────────────────────────────────────────
 
-fn expr_to_i32(val: &ExprValue, label: &str, output: &mut OutputCollector) -> Option<i32> {
+/// Parse a number literal: integers stay exact, anything with a fraction is a float.
+fn parse_number(text: &str, output: &mut OutputCollector) -> ExprValue {
+    if text.contains('.') {
+        return ExprValue::Number(text.parse().unwrap_or(0.0));
+    }
+    match text.parse::<i64>() {
+        Ok(i) => ExprValue::Integer(i),
+        Err(_) => {
+            output.raise(format!("Integer literal out of range: {}", text));
+            ExprValue::None
+        }
+    }
+}
+
+/// Widen a numeric value to a float.
+fn as_float(val: &ExprValue) -> f64 {
+    match val {
+        ExprValue::Integer(i) => *i as f64,
+        ExprValue::Number(n) => *n,
+        _ => 0.0,
+    }
+}
+
+/// Wrap a checked integer operation, raising an error on overflow.
+fn integer_result(
+    result: Option<i64>,
+    left: &ExprValue,
+    op: &str,
+    right: &ExprValue,
+    output: &mut OutputCollector,
+) -> ExprValue {
+    match result {
+        Some(i) => ExprValue::Integer(i),
+        None => {
+            output.raise(format!("Integer overflow: {} {} {}", left, op, right));
+            ExprValue::None
+        }
+    }
+}
+
+fn expr_to_i64(val: &ExprValue, label: &str, output: &mut OutputCollector) -> Option<i64> {
     match val {
-        ExprValue::Number(n) => Some(*n as i32),
-        ExprValue::String(s) => s.parse().ok().or_else(|| {
+        ExprValue::Integer(i) => Some(*i),
+        ExprValue::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Some(*n as i64),
+        ExprValue::Number(n) => {
+            output.eprintln(&format!("{} value must be an integer, got {}", label, n));
+            None
+        }
+        ExprValue::String(s) => s.trim().parse().ok().or_else(|| {
             output.eprintln(&format!(
                 "{} value must be a number, got string: {}",
                 label, s
             ));
             None
         }),
-        ExprValue::List(l) => Some(l.len() as i32),
-        ExprValue::Map(m) => Some(m.len() as i32),
-        ExprValue::Boolean(_) => {
+        ExprValue::List(l) => Some(l.len() as i64),
+        ExprValue::Map(m) => Some(m.len() as i64),
+        ExprValue::Boolean(_) | ExprValue::Function(_) | ExprValue::None => {
             output.eprintln(&format!("{} value must be a number", label));
             None
         }
@@ -1079,7 +1878,7 @@ fn expr_to_i32(val: &ExprValue, label: &str, output: &mut OutputCollector) -> Op
 /// Convert a builtins::BuiltinValue to an ExprValue.
 fn builtin_to_expr(val: builtins::BuiltinValue) -> ExprValue {
     match val {
-        builtins::BuiltinValue::None => ExprValue::String("".to_string()),
+        builtins::BuiltinValue::None => ExprValue::None,
         builtins::BuiltinValue::String(s) => ExprValue::String(s),
         builtins::BuiltinValue::Number(n) => ExprValue::Number(n),
         builtins::BuiltinValue::Boolean(b) => ExprValue::Boolean(b),
@@ -1089,6 +1888,98 @@ fn builtin_to_expr(val: builtins::BuiltinValue) -> ExprValue {
     }
 }
 
+/// Evaluate a string literal:
+///
+/// - `"..."` processes escapes and interpolation
+/// - `"""..."""` is dedented first, then processed like `"..."`
+/// - `r"..."` and `r"""..."""` are taken as written (triple-quoted raw
+///   strings are still dedented)
+fn evaluate_string_literal(
+    pair: pest::iterators::Pair<Rule>,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) -> String {
+    evaluate_literal(pair, scope, functions, false, output)
+}
+
+/// Evaluate the command string of `evoke` or `imbue`. A `$name` that is not a
+/// mage variable belongs to the shell, so it is left as written and is not
+/// an undefined variable, even in strict mode.
+fn evaluate_command_literal(
+    pair: pest::iterators::Pair<Rule>,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) -> String {
+    evaluate_literal(pair, scope, functions, true, output)
+}
+
+fn evaluate_literal(
+    pair: pest::iterators::Pair<Rule>,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    command: bool,
+    output: &mut OutputCollector,
+) -> String {
+    let text = pair.as_str();
+    match pair.as_rule() {
+        Rule::raw_string => match text.strip_prefix("r\"\"\"") {
+            Some(body) => dedent(&body[..body.len() - 3]),
+            None => text[2..text.len() - 1].to_string(),
+        },
+        Rule::triple_string => {
+            let body = dedent(&text[3..text.len() - 3]);
+            interpolate(
+                &process_escape_sequences(&body),
+                scope,
+                functions,
+                command,
+                output,
+            )
+        }
+        _ => interpolate(
+            &process_escape_sequences(&text[1..text.len() - 1]),
+            scope,
+            functions,
+            command,
+            output,
+        ),
+    }
+}
+
+/// Strip the layout of a triple-quoted string: a line break right after the
+/// opening quotes, the indentation before the closing quotes, and the
+/// indentation shared by every non-blank line.
+fn dedent(body: &str) -> String {
+    let body = match body.split_once('\n') {
+        Some((first, rest)) if first.trim().is_empty() => rest,
+        _ => body,
+    };
+    let body = match body.rsplit_once('\n') {
+        Some((rest, last)) if last.trim().is_empty() => &body[..rest.len() + 1],
+        _ => body,
+    };
+
+    let indent = body
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+
+    body.split('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.trim_start_matches([' ', '\t'])
+            } else {
+                &line[indent..]
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 fn process_escape_sequences(text: &str) -> String {
     let mut result = String::new();
     let mut chars = text.chars().peekable();
@@ -1142,7 +2033,15 @@ fn process_escape_sequences(text: &str) -> String {
     result
 }
 
-fn interpolate(text: &str, scope: &HashMap<String, ExprValue>) -> String {
+/// Expand `$name` and `${expression}` / `${expression:spec}` in `text`. In
+/// `command` text, an unknown `$name` is left for the shell to expand.
+fn interpolate(
+    text: &str,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    command: bool,
+    output: &mut OutputCollector,
+) -> String {
     let mut result = String::new();
     let mut chars = text.chars().peekable();
 
@@ -1166,29 +2065,18 @@ fn interpolate(text: &str, scope: &HashMap<String, ExprValue>) -> String {
                         result.push('}');
                         chars.next();
                     }
-                    _ => {
-                        result.push(next_ch);
-                        chars.next();
-                    }
+                    _ => result.push('\\'),
                 }
+            } else {
+                result.push('\\');
             }
         } else if ch == '$' {
             if let Some(&'{') = chars.peek() {
                 chars.next();
-                let mut var = String::new();
-                while let Some(&c) = chars.peek() {
-                    if c == '}' {
-                        chars.next();
-                        break;
-                    } else {
-                        var.push(c);
-                        chars.next();
-                    }
-                }
-                if let Some(value) = scope.get(&var) {
-                    result.push_str(&value.to_display_string());
-                } else {
-                    result.push_str(&format!("${{{}}}", var));
+                let inner = take_interpolation(&mut chars);
+                match interpolate_expression(&inner, scope, functions, output) {
+                    Some(text) => result.push_str(&text),
+                    None => result.push_str(&format!("${{{}}}", inner)),
                 }
             } else {
                 let mut var = String::new();
@@ -1202,7 +2090,12 @@ fn interpolate(text: &str, scope: &HashMap<String, ExprValue>) -> String {
                 }
                 if let Some(value) = scope.get(&var) {
                     result.push_str(&value.to_display_string());
+                } else if var.is_empty() {
+                    result.push('$');
                 } else {
+                    if !command {
+                        output.undefined_variable(&var);
+                    }
                     result.push_str(&format!("${}", var));
                 }
             }
@@ -1214,6 +2107,81 @@ fn interpolate(text: &str, scope: &HashMap<String, ExprValue>) -> String {
     result
 }
 
+/// Consume the body of `${...}` up to its matching `}`, skipping braces
+/// that are nested or quoted.
+fn take_interpolation(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut inner = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    for c in chars.by_ref() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') if depth == 0 => break,
+            (None, '}') => depth -= 1,
+            _ => {}
+        }
+        inner.push(c);
+    }
+    inner
+}
+
+/// Byte offset of the last `:` that is outside quotes and brackets.
+fn spec_separator(inner: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut found = None;
+    for (i, c) in inner.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth -= 1,
+            (None, ':') if depth == 0 => found = Some(i),
+            _ => {}
+        }
+    }
+    found
+}
+
+/// Evaluate the body of `${...}`. Returns `None` when it is not a mage
+/// expression (for example shell syntax such as `${HOME:-/root}`), in which
+/// case the text is left as written.
+fn interpolate_expression(
+    inner: &str,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) -> Option<String> {
+    let (expr, spec) = match spec_separator(inner) {
+        Some(i) => (&inner[..i], Some(&inner[i + 1..])),
+        None => (inner, None),
+    };
+    let pair = MageParser::parse(Rule::interpolation, expr)
+        .ok()?
+        .next()?
+        .into_inner()
+        .next()?;
+    if spec.is_some_and(|spec| !format_spec::is_valid_spec(spec)) {
+        return None;
+    }
+
+    let value = evaluate_expression(pair, scope, functions, output);
+    match spec {
+        Some(spec) => match format_spec::format_value(&value, spec) {
+            Ok(text) => Some(text),
+            Err(e) => {
+                output.raise(e);
+                None
+            }
+        },
+        None => Some(value.to_display_string()),
+    }
+}
+
 // ─── String Methods ──────────────────────────────────────────────────
 
 fn call_string_method(
@@ -1226,7 +2194,7 @@ fn call_string_method(
         "upper" => ExprValue::String(s.to_uppercase()),
         "lower" => ExprValue::String(s.to_lowercase()),
         "trim" => ExprValue::String(s.trim().to_string()),
-        "len" => ExprValue::Number(s.len() as f64),
+        "len" => ExprValue::Integer(s.chars().count() as i64),
         "contains" => {
             if let Some(ExprValue::String(substr)) = args.first() {
                 ExprValue::Boolean(s.contains(substr.as_str()))
@@ -1262,23 +2230,200 @@ fn call_string_method(
                 ExprValue::List(vec![])
             }
         }
-        _ => {
-            output.eprintln(&format!("Unknown string method: {}", method_name));
-            ExprValue::String("".to_string())
+        _ => match string_method(s, method_name, &args) {
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                output.eprintln(&format!("Unknown string method: {}", method_name));
+                ExprValue::String("".to_string())
+            }
+            Err(e) => {
+                output.raise(e);
+                ExprValue::None
+            }
+        },
+    }
+}
+
+/// String methods that raise a catchable error on bad arguments. Positions
+/// and lengths count characters, not bytes. Returns `Ok(None)` for an
+/// unknown method.
+fn string_method(s: &str, name: &str, args: &[ExprValue]) -> Result<Option<ExprValue>, String> {
+    let value = match name {
+        "starts_with" => ExprValue::Boolean(s.starts_with(str_arg(args, 0, name)?)),
+        "ends_with" => ExprValue::Boolean(s.ends_with(str_arg(args, 0, name)?)),
+        "find" => match s.find(str_arg(args, 0, name)?) {
+            Some(byte) => ExprValue::Integer(s[..byte].chars().count() as i64),
+            None => ExprValue::None,
+        },
+        "slice" => {
+            let len = s.chars().count();
+            let start = char_index(int_arg(args, 0, name)?, len);
+            let end = match args.get(1) {
+                Some(_) => char_index(int_arg(args, 1, name)?, len),
+                None => len,
+            };
+            let taken = end.saturating_sub(start);
+            ExprValue::String(s.chars().skip(start).take(taken).collect())
+        }
+        "chars" => ExprValue::List(
+            s.chars()
+                .map(|c| ExprValue::String(c.to_string()))
+                .collect(),
+        ),
+        "lines" => ExprValue::List(
+            s.lines()
+                .map(|line| ExprValue::String(line.to_string()))
+                .collect(),
+        ),
+        "pad_left" | "pad_right" => {
+            let width = usize::try_from(int_arg(args, 0, name)?).unwrap_or(0);
+            let fill = match args.get(1) {
+                Some(_) => {
+                    let fill = str_arg(args, 1, name)?;
+                    let mut chars = fill.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => c,
+                        _ => return Err(format!("{} fill must be a single character", name)),
+                    }
+                }
+                None => ' ',
+            };
+            let missing = width.saturating_sub(s.chars().count());
+            let padding = repeat_checked(&fill.to_string(), missing, s.len(), name)?;
+            if name == "pad_left" {
+                ExprValue::String(format!("{}{}", padding, s))
+            } else {
+                ExprValue::String(format!("{}{}", s, padding))
+            }
+        }
+        "repeat" => {
+            let count = int_arg(args, 0, name)?;
+            let count = usize::try_from(count)
+                .map_err(|_| format!("repeat count must not be negative, got {}", count))?;
+            ExprValue::String(repeat_checked(s, count, 0, name)?)
+        }
+        "reverse" => ExprValue::String(s.chars().rev().collect()),
+        "matches" => ExprValue::Boolean(compile_regex(str_arg(args, 0, name)?)?.is_match(s)),
+        "captures" => {
+            let regex = compile_regex(str_arg(args, 0, name)?)?;
+            match regex.captures(s) {
+                Some(caps) => {
+                    let group = |m: Option<regex::Match>| {
+                        m.map_or(ExprValue::None, |m| {
+                            ExprValue::String(m.as_str().to_string())
+                        })
+                    };
+                    let mut groups: IndexMap<String, ExprValue> = caps
+                        .iter()
+                        .enumerate()
+                        .map(|(i, m)| (i.to_string(), group(m)))
+                        .collect();
+                    for group_name in regex.capture_names().flatten() {
+                        groups.insert(group_name.to_string(), group(caps.name(group_name)));
+                    }
+                    ExprValue::Map(groups)
+                }
+                None => ExprValue::None,
+            }
+        }
+        "replace_all" => {
+            let regex = compile_regex(str_arg(args, 0, name)?)?;
+            let replacement = str_arg(args, 1, name)?;
+            ExprValue::String(regex.replace_all(s, replacement).into_owned())
         }
+        "split_regex" => ExprValue::List(
+            compile_regex(str_arg(args, 0, name)?)?
+                .split(s)
+                .map(|part| ExprValue::String(part.to_string()))
+                .collect(),
+        ),
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+/// The largest string, in bytes, that `repeat` and the pad methods build.
+const MAX_STRING_BYTES: usize = 1 << 30;
+
+/// `s` repeated `count` times, raising an error instead of allocating past
+/// `MAX_STRING_BYTES` once `extra` bytes are added to it.
+fn repeat_checked(s: &str, count: usize, extra: usize, method: &str) -> Result<String, String> {
+    match s
+        .len()
+        .checked_mul(count)
+        .and_then(|n| n.checked_add(extra))
+    {
+        Some(n) if n <= MAX_STRING_BYTES => Ok(s.repeat(count)),
+        _ => Err(format!(
+            "{} result would be larger than {} bytes",
+            method, MAX_STRING_BYTES
+        )),
+    }
+}
+
+fn str_arg<'a>(args: &'a [ExprValue], index: usize, method: &str) -> Result<&'a str, String> {
+    match args.get(index) {
+        Some(ExprValue::String(s)) => Ok(s),
+        Some(other) => Err(format!(
+            "{} argument {} must be a string, got {}",
+            method,
+            index + 1,
+            other
+        )),
+        None => Err(format!("{} requires a string argument", method)),
     }
 }
 
+fn int_arg(args: &[ExprValue], index: usize, method: &str) -> Result<i64, String> {
+    match args.get(index) {
+        Some(ExprValue::Integer(i)) => Ok(*i),
+        Some(ExprValue::Number(n)) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+            Ok(*n as i64)
+        }
+        Some(other) => Err(format!(
+            "{} argument {} must be an integer, got {}",
+            method,
+            index + 1,
+            other
+        )),
+        None => Err(format!("{} requires an integer argument", method)),
+    }
+}
+
+/// Clamp a character index to `0..=len`, counting negative indices from the end.
+fn char_index(index: i64, len: usize) -> usize {
+    if index < 0 {
+        len.saturating_sub(index.unsigned_abs() as usize)
+    } else {
+        (index as usize).min(len)
+    }
+}
+
+fn compile_regex(pattern: &str) -> Result<regex::Regex, String> {
+    regex::Regex::new(pattern).map_err(|e| {
+        // Syntax errors draw a caret under the pattern; keep only the reason
+        let message = e.to_string();
+        let reason = message.lines().last().unwrap_or_default();
+        format!(
+            "Invalid pattern '{}': {}",
+            pattern,
+            reason.trim_start_matches("error: ")
+        )
+    })
+}
+
 // ─── List Methods ────────────────────────────────────────────────────
 
 fn call_list_method(
     l: &[ExprValue],
     method_name: &str,
     args: Vec<ExprValue>,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
     output: &mut OutputCollector,
 ) -> ExprValue {
     match method_name {
-        "len" => ExprValue::Number(l.len() as f64),
+        "len" => ExprValue::Integer(l.len() as i64),
         "first" => l
             .first()
             .cloned()
@@ -1304,23 +2449,197 @@ fn call_list_method(
                 ExprValue::String(joined)
             }
         }
-        _ => {
-            output.eprintln(&format!("Unknown list method: {}", method_name));
-            ExprValue::String("".to_string())
-        }
+        _ => match list_method(l, method_name, &args, scope, functions, output) {
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                output.eprintln(&format!("Unknown list method: {}", method_name));
+                ExprValue::String("".to_string())
+            }
+            Err(e) => {
+                output.raise(e);
+                ExprValue::None
+            }
+        },
     }
 }
 
+/// List methods. Lists are values, so methods such as `push` and `remove`
+/// return a new list rather than changing the one they are called on.
+/// Returns `Ok(None)` for an unknown method.
+fn list_method(
+    l: &[ExprValue],
+    name: &str,
+    args: &[ExprValue],
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) -> Result<Option<ExprValue>, String> {
+    let position = |index: i64| {
+        let len = l.len() as i64;
+        let resolved = if index < 0 { len + index } else { index };
+        usize::try_from(resolved)
+            .ok()
+            .filter(|i| *i < l.len())
+            .ok_or_else(|| format!("{} index {} out of range for {} item(s)", name, index, len))
+    };
+    let mut call = |f: &str, call_args: Vec<ExprValue>, output: &mut OutputCollector| {
+        let result = call_user_function(f, call_args, scope, functions, output);
+        match output.take_error() {
+            Some(e) => Err(e),
+            None => Ok(result),
+        }
+    };
+
+    let value = match name {
+        "push" => ExprValue::List(l.iter().chain(args).cloned().collect()),
+        "pop" => ExprValue::List(l[..l.len().saturating_sub(1)].to_vec()),
+        "insert" => {
+            let index = int_arg(args, 0, name)?;
+            let at = if index < 0 {
+                l.len() as i64 + index
+            } else {
+                index
+            };
+            let at = usize::try_from(at)
+                .ok()
+                .filter(|i| *i <= l.len())
+                .ok_or_else(|| format!("insert index {} out of range", index))?;
+            let item = args
+                .get(1)
+                .cloned()
+                .ok_or("insert requires an index and a value")?;
+            let mut list = l.to_vec();
+            list.insert(at, item);
+            ExprValue::List(list)
+        }
+        "remove" => {
+            let at = position(int_arg(args, 0, name)?)?;
+            let mut list = l.to_vec();
+            list.remove(at);
+            ExprValue::List(list)
+        }
+        "contains" => {
+            let item = args.first().ok_or("contains requires a value")?;
+            ExprValue::Boolean(l.iter().any(|v| values_equal(v, item)))
+        }
+        "index_of" => {
+            let item = args.first().ok_or("index_of requires a value")?;
+            l.iter()
+                .position(|v| values_equal(v, item))
+                .map_or(ExprValue::None, |i| ExprValue::Integer(i as i64))
+        }
+        "sort" => {
+            let mut list = l.to_vec();
+            list.sort_by(compare_values);
+            ExprValue::List(list)
+        }
+        "sort_by" => {
+            let f = function_arg(args, 0, name)?;
+            let mut keyed = Vec::with_capacity(l.len());
+            for item in l {
+                keyed.push((call(f, vec![item.clone()], output)?, item.clone()));
+            }
+            keyed.sort_by(|(a, _), (b, _)| compare_values(a, b));
+            ExprValue::List(keyed.into_iter().map(|(_, item)| item).collect())
+        }
+        "reverse" => ExprValue::List(l.iter().rev().cloned().collect()),
+        "unique" => {
+            let mut list: Vec<ExprValue> = Vec::new();
+            for item in l {
+                if !list.iter().any(|v| values_equal(v, item)) {
+                    list.push(item.clone());
+                }
+            }
+            ExprValue::List(list)
+        }
+        "slice" => {
+            let start = char_index(int_arg(args, 0, name)?, l.len());
+            let end = match args.get(1) {
+                Some(_) => char_index(int_arg(args, 1, name)?, l.len()),
+                None => l.len(),
+            };
+            ExprValue::List(l[start..end.max(start)].to_vec())
+        }
+        "concat" => {
+            let mut list = l.to_vec();
+            for arg in args {
+                match arg {
+                    ExprValue::List(other) => list.extend(other.iter().cloned()),
+                    other => return Err(format!("concat requires lists, got {}", other)),
+                }
+            }
+            ExprValue::List(list)
+        }
+        "map" => {
+            let f = function_arg(args, 0, name)?;
+            let mut list = Vec::with_capacity(l.len());
+            for item in l {
+                list.push(call(f, vec![item.clone()], output)?);
+            }
+            ExprValue::List(list)
+        }
+        "filter" => {
+            let f = function_arg(args, 0, name)?;
+            let mut list = Vec::new();
+            for item in l {
+                if call(f, vec![item.clone()], output)?.is_truthy() {
+                    list.push(item.clone());
+                }
+            }
+            ExprValue::List(list)
+        }
+        "reduce" => {
+            let f = function_arg(args, 0, name)?;
+            let mut items = l.iter().cloned();
+            let mut acc = match args.get(1) {
+                Some(initial) => initial.clone(),
+                None => items
+                    .next()
+                    .ok_or("reduce of an empty list requires an initial value")?,
+            };
+            for item in items {
+                acc = call(f, vec![acc, item], output)?;
+            }
+            acc
+        }
+        "any" | "all" => {
+            let f = function_arg(args, 0, name)?;
+            let want = name == "all";
+            let mut result = want;
+            for item in l {
+                if call(f, vec![item.clone()], output)?.is_truthy() != want {
+                    result = !want;
+                    break;
+                }
+            }
+            ExprValue::Boolean(result)
+        }
+        "zip" => match args.first() {
+            Some(ExprValue::List(other)) => ExprValue::List(
+                l.iter()
+                    .zip(other)
+                    .map(|(a, b)| ExprValue::List(vec![a.clone(), b.clone()]))
+                    .collect(),
+            ),
+            _ => return Err("zip requires a list argument".to_string()),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
 // ─── Map Methods ─────────────────────────────────────────────────────
 
 fn call_map_method(
-    m: &HashMap<String, ExprValue>,
+    m: &IndexMap<String, ExprValue>,
     method_name: &str,
     args: Vec<ExprValue>,
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
     output: &mut OutputCollector,
 ) -> ExprValue {
     match method_name {
-        "len" => ExprValue::Number(m.len() as f64),
+        "len" => ExprValue::Integer(m.len() as i64),
         "keys" => {
             let keys: Vec<ExprValue> = m.keys().map(|k| ExprValue::String(k.clone())).collect();
             ExprValue::List(keys)
@@ -1329,6 +2648,10 @@ fn call_map_method(
             let values: Vec<ExprValue> = m.values().cloned().collect();
             ExprValue::List(values)
         }
+        "sort_keys" => {
+            let recursive = matches!(args.first(), Some(ExprValue::Boolean(true)));
+            ExprValue::Map(sort_map_keys(m, recursive))
+        }
         "has" => {
             if let Some(ExprValue::String(key)) = args.first() {
                 ExprValue::Boolean(m.contains_key(key))
@@ -1337,9 +2660,140 @@ fn call_map_method(
                 ExprValue::Boolean(false)
             }
         }
-        _ => {
-            output.eprintln(&format!("Unknown map method: {}", method_name));
-            ExprValue::String("".to_string())
+        _ => match map_method(m, method_name, &args, scope, functions, output) {
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                output.eprintln(&format!("Unknown map method: {}", method_name));
+                ExprValue::String("".to_string())
+            }
+            Err(e) => {
+                output.raise(e);
+                ExprValue::None
+            }
+        },
+    }
+}
+
+/// Map methods. Like list methods, `set`, `remove` and `merge` return a new
+/// map. Returns `Ok(None)` for an unknown method.
+fn map_method(
+    m: &IndexMap<String, ExprValue>,
+    name: &str,
+    args: &[ExprValue],
+    scope: &HashMap<String, ExprValue>,
+    functions: &mut HashMap<String, FunctionDef>,
+    output: &mut OutputCollector,
+) -> Result<Option<ExprValue>, String> {
+    let value = match name {
+        "get" => {
+            let key = str_arg(args, 0, name)?;
+            m.get(key)
+                .or(args.get(1))
+                .cloned()
+                .unwrap_or(ExprValue::None)
+        }
+        "set" => {
+            let key = str_arg(args, 0, name)?;
+            let value = args
+                .get(1)
+                .cloned()
+                .ok_or("set requires a key and a value")?;
+            let mut map = m.clone();
+            map.insert(key.to_string(), value);
+            ExprValue::Map(map)
+        }
+        "remove" => {
+            let mut map = m.clone();
+            for (i, _) in args.iter().enumerate() {
+                map.shift_remove(str_arg(args, i, name)?);
+            }
+            ExprValue::Map(map)
+        }
+        "merge" => {
+            let mut map = m.clone();
+            for arg in args {
+                match arg {
+                    ExprValue::Map(other) => deep_merge(&mut map, other),
+                    other => return Err(format!("merge requires maps, got {}", other)),
+                }
+            }
+            ExprValue::Map(map)
+        }
+        "entries" => ExprValue::List(
+            m.iter()
+                .map(|(k, v)| ExprValue::List(vec![ExprValue::String(k.clone()), v.clone()]))
+                .collect(),
+        ),
+        "filter" => {
+            let f = function_arg(args, 0, name)?;
+            let mut map = IndexMap::new();
+            for (key, value) in m {
+                let call_args = vec![ExprValue::String(key.clone()), value.clone()];
+                let keep = call_user_function(f, call_args, scope, functions, output);
+                if let Some(e) = output.take_error() {
+                    return Err(e);
+                }
+                if keep.is_truthy() {
+                    map.insert(key.clone(), value.clone());
+                }
+            }
+            ExprValue::Map(map)
         }
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+/// Merge `other` into `base`: nested maps are merged key by key, anything
+/// else in `other` replaces the value in `base`.
+fn deep_merge(base: &mut IndexMap<String, ExprValue>, other: &IndexMap<String, ExprValue>) {
+    for (key, value) in other {
+        match (base.get_mut(key), value) {
+            (Some(ExprValue::Map(existing)), ExprValue::Map(incoming)) => {
+                deep_merge(existing, incoming)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn function_arg<'a>(args: &'a [ExprValue], index: usize, method: &str) -> Result<&'a str, String> {
+    match args.get(index) {
+        Some(ExprValue::Function(name)) => Ok(name),
+        Some(other) => Err(format!(
+            "{} argument {} must be a function, got {}",
+            method,
+            index + 1,
+            other
+        )),
+        None => Err(format!("{} requires a function argument", method)),
+    }
+}
+
+/// Equality used by list and map methods. Values of different kinds are never
+/// equal, except that integers and floats compare by value.
+fn values_equal(left: &ExprValue, right: &ExprValue) -> bool {
+    compare_values(left, right) == std::cmp::Ordering::Equal
+}
+
+/// Copy of `m` with its keys in sorted order, optionally sorting nested maps too.
+fn sort_map_keys(m: &IndexMap<String, ExprValue>, recursive: bool) -> IndexMap<String, ExprValue> {
+    let mut sorted = m.clone();
+    sorted.sort_keys();
+    if recursive {
+        for value in sorted.values_mut() {
+            *value = sort_value_keys(value);
+        }
+    }
+    sorted
+}
+
+fn sort_value_keys(value: &ExprValue) -> ExprValue {
+    match value {
+        ExprValue::Map(m) => ExprValue::Map(sort_map_keys(m, true)),
+        ExprValue::List(l) => ExprValue::List(l.iter().map(sort_value_keys).collect()),
+        other => other.clone(),
     }
 }
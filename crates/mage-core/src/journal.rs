@@ -0,0 +1,297 @@
+//! Undo journal for destructive file operations.
+//!
+//! When journaling is enabled, every file, directory or symlink is snapshotted
+//! into `.mage/journal/<run-id>` the first time a run is about to modify it.
+//! [`undo`] restores those snapshots, rolling the file system back to the state
+//! it was in before the run.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE: &str = "journal.json";
+
+/// What was at a path before the run touched it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PriorState {
+    /// Nothing existed; undo removes whatever the run created
+    Missing,
+    /// A regular file, copied to `snapshot` inside the run directory
+    File { snapshot: String },
+    /// A directory tree, copied to `snapshot` inside the run directory
+    Directory { snapshot: String },
+    /// A symlink pointing at `target`
+    Symlink { target: PathBuf },
+    /// A directory the run created to hold another path; undo removes it
+    /// once it is empty again
+    CreatedDirectory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub path: PathBuf,
+    pub prior: PriorState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalRecord {
+    run_id: String,
+    started_at: String,
+    entries: Vec<JournalEntry>,
+}
+
+/// Journal for a single run.
+pub struct Journal {
+    dir: PathBuf,
+    record: JournalRecord,
+}
+
+/// Directory holding all journals for a project.
+pub fn journal_root(project_root: &Path) -> PathBuf {
+    project_root.join(".mage/journal")
+}
+
+impl Journal {
+    /// Start a new journal under `<project_root>/.mage/journal`.
+    pub fn start(project_root: &Path) -> Result<Self, String> {
+        let root = journal_root(project_root);
+        let base = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+
+        let mut run_id = base.clone();
+        let mut suffix = 1;
+        while root.join(&run_id).exists() {
+            suffix += 1;
+            run_id = format!("{}-{}", base, suffix);
+        }
+
+        let dir = root.join(&run_id);
+        fs::create_dir_all(dir.join("files"))
+            .map_err(|e| format!("Failed to create journal directory: {}", e))?;
+
+        let journal = Self {
+            dir,
+            record: JournalRecord {
+                run_id,
+                started_at: chrono::Utc::now().to_rfc3339(),
+                entries: Vec::new(),
+            },
+        };
+        journal.save()?;
+        Ok(journal)
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.record.run_id
+    }
+
+    /// Returns true if nothing has been snapshotted yet.
+    pub fn is_empty(&self) -> bool {
+        self.record.entries.is_empty()
+    }
+
+    /// Delete the journal directory, e.g. after a run that changed nothing.
+    pub fn discard(self) -> Result<(), String> {
+        fs::remove_dir_all(&self.dir).map_err(|e| format!("Failed to remove journal: {}", e))
+    }
+
+    /// Record the current state of `path` unless it was already recorded in
+    /// this run, along with any missing parent directories the run will
+    /// create for it.
+    pub fn snapshot(&mut self, path: &str) -> Result<(), String> {
+        let path = absolute(Path::new(path));
+        if self.is_recorded(&path) {
+            return Ok(());
+        }
+
+        let mut created: Vec<PathBuf> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| fs::symlink_metadata(dir).is_err())
+            .map(Path::to_path_buf)
+            .collect();
+        created.reverse();
+        for dir in created {
+            if !self.is_recorded(&dir) {
+                self.record.entries.push(JournalEntry {
+                    path: dir,
+                    prior: PriorState::CreatedDirectory,
+                });
+            }
+        }
+
+        let snapshot_name = format!("files/{}", self.record.entries.len());
+        let snapshot_path = self.dir.join(&snapshot_name);
+
+        let prior = match fs::symlink_metadata(&path) {
+            Err(_) => PriorState::Missing,
+            Ok(meta) if meta.file_type().is_symlink() => PriorState::Symlink {
+                target: fs::read_link(&path)
+                    .map_err(|e| format!("Failed to read link '{}': {}", path.display(), e))?,
+            },
+            Ok(meta) if meta.is_dir() => {
+                copy_tree(&path, &snapshot_path)
+                    .map_err(|e| format!("Failed to snapshot '{}': {}", path.display(), e))?;
+                PriorState::Directory {
+                    snapshot: snapshot_name,
+                }
+            }
+            Ok(_) => {
+                fs::copy(&path, &snapshot_path)
+                    .map_err(|e| format!("Failed to snapshot '{}': {}", path.display(), e))?;
+                PriorState::File {
+                    snapshot: snapshot_name,
+                }
+            }
+        };
+
+        self.record.entries.push(JournalEntry { path, prior });
+        self.save()
+    }
+
+    fn is_recorded(&self, path: &Path) -> bool {
+        self.record.entries.iter().any(|e| e.path == path)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(&self.record)
+            .map_err(|e| format!("Failed to serialize journal: {}", e))?;
+        fs::write(self.dir.join(JOURNAL_FILE), content)
+            .map_err(|e| format!("Failed to write journal: {}", e))
+    }
+}
+
+/// Summary of a journaled run, as shown by `mage undo --list`.
+#[derive(Debug, Clone)]
+pub struct RunInfo {
+    pub run_id: String,
+    pub started_at: String,
+    pub changes: usize,
+}
+
+fn read_record(dir: &Path) -> Result<JournalRecord, String> {
+    let content = fs::read_to_string(dir.join(JOURNAL_FILE))
+        .map_err(|e| format!("Failed to read journal: {}", e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Failed to parse journal: {}", e))
+}
+
+/// List journaled runs, oldest first.
+pub fn list_runs(project_root: &Path) -> Vec<RunInfo> {
+    let Ok(entries) = fs::read_dir(journal_root(project_root)) else {
+        return Vec::new();
+    };
+
+    let mut runs: Vec<RunInfo> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| read_record(&e.path()).ok())
+        .map(|r| RunInfo {
+            run_id: r.run_id,
+            started_at: r.started_at,
+            changes: r.entries.len(),
+        })
+        .collect();
+    runs.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+    runs
+}
+
+/// Restore every path recorded by `run_id` (or the most recent run) and
+/// discard the journal. Returns the restored paths.
+pub fn undo(project_root: &Path, run_id: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    let mut runs = list_runs(project_root);
+    // Only ids of recorded runs are accepted, so `run_id` can never name a
+    // directory outside the journal
+    let run_id = match run_id {
+        Some(id) => runs
+            .into_iter()
+            .map(|r| r.run_id)
+            .find(|r| r == id)
+            .ok_or_else(|| format!("No journal found for run '{}'", id))?,
+        None => runs
+            .pop()
+            .map(|r| r.run_id)
+            .ok_or_else(|| "No journaled runs to undo".to_string())?,
+    };
+
+    let dir = journal_root(project_root).join(&run_id);
+    let record = read_record(&dir)?;
+
+    let mut restored = Vec::new();
+    for entry in record.entries.iter().rev() {
+        restore(&dir, entry)
+            .map_err(|e| format!("Failed to restore '{}': {}", entry.path.display(), e))?;
+        restored.push(entry.path.clone());
+    }
+
+    fs::remove_dir_all(&dir).map_err(|e| format!("Failed to remove journal: {}", e))?;
+    Ok(restored)
+}
+
+fn restore(dir: &Path, entry: &JournalEntry) -> std::io::Result<()> {
+    let path = &entry.path;
+    if let PriorState::CreatedDirectory = entry.prior {
+        // Anything still inside was not created by this run
+        let _ = fs::remove_dir(path);
+        return Ok(());
+    }
+
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if meta.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match &entry.prior {
+        PriorState::Missing | PriorState::CreatedDirectory => Ok(()),
+        PriorState::File { snapshot } => fs::copy(dir.join(snapshot), path).map(|_| ()),
+        PriorState::Directory { snapshot } => copy_tree(&dir.join(snapshot), path),
+        PriorState::Symlink { target } => make_symlink(target, path),
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
+fn copy_tree(source: &Path, dest: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let from = entry.path();
+        let to = dest.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            make_symlink(&fs::read_link(&from)?, &to)?;
+        } else if file_type.is_dir() {
+            copy_tree(&from, &to)?;
+        } else {
+            fs::copy(&from, &to)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+fn make_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(target_family = "windows")]
+fn make_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    if target.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
@@ -3,15 +3,27 @@ extern crate pest_derive;
 
 use std::collections::HashMap;
 
+pub mod archive;
 pub mod builtins;
+pub mod cache;
 pub mod config;
+pub mod ensure;
+pub mod file_edit;
+pub mod format_spec;
+pub mod formats;
 pub mod interpreter;
+pub mod journal;
 pub mod output;
 pub mod package;
 pub mod parser;
+pub mod registry;
+pub mod scripts;
+pub mod tasks;
+pub mod template;
 
 use crate::config::MageConfig;
 use crate::interpreter::{ExprValue, interpret};
+use crate::journal::Journal;
 use crate::output::OutputCollector;
 use pest::Parser;
 use pest::iterators::Pairs;
@@ -30,21 +42,67 @@ fn extract_shell_override(source: &str) -> Option<String> {
     None
 }
 
+/// The shell to run commands with: the CLI flag, then the script's
+/// `#!shell:` line, then `.mageconfig`.
+pub(crate) fn resolve_shell(source: &str, cli_shell: Option<&str>) -> Option<String> {
+    cli_shell
+        .map(String::from)
+        .or_else(|| extract_shell_override(source))
+        .or_else(|| MageConfig::find_config().and_then(|c| c.shell))
+}
+
+/// Options controlling how a script is executed
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Report side-effecting operations instead of performing them
+    pub dry_run: bool,
+    /// Snapshot files into `.mage/journal/<run-id>` before modifying them
+    pub journal: bool,
+    /// Treat reading an undefined variable as an error
+    pub strict: bool,
+    /// Arguments passed to the script, available to it as the `args` list
+    pub args: Vec<String>,
+}
+
+/// The variables every script starts with.
+pub(crate) fn script_scope(options: &RunOptions) -> HashMap<String, ExprValue> {
+    HashMap::from([(
+        "args".to_string(),
+        ExprValue::List(
+            options
+                .args
+                .iter()
+                .cloned()
+                .map(ExprValue::String)
+                .collect(),
+        ),
+    )])
+}
+
 /// Run mage source code with optional shell override
 pub fn run(source: &str, cli_shell: Option<&str>) -> Result<(), String> {
-    let script_shell = extract_shell_override(source);
-    let config_shell = MageConfig::find_config().and_then(|c| c.shell);
+    run_with_options(source, cli_shell, &RunOptions::default())
+}
 
-    let shell_override = cli_shell
-        .map(String::from)
-        .or(script_shell)
-        .or(config_shell);
+/// Run mage source code with optional shell override and execution options
+pub fn run_with_options(
+    source: &str,
+    cli_shell: Option<&str>,
+    options: &RunOptions,
+) -> Result<(), String> {
+    let shell_override = resolve_shell(source, cli_shell);
 
-    let mut scope: HashMap<String, ExprValue> = HashMap::new();
+    let mut scope = script_scope(options);
     let mut functions = HashMap::new();
-    let mut output = OutputCollector::direct();
+    let mut output = OutputCollector::direct()
+        .with_dry_run(options.dry_run)
+        .with_strict(options.strict);
+    if options.journal && !options.dry_run {
+        let root = std::env::current_dir().map_err(|e| format!("{}", e))?;
+        output = output.with_journal(Journal::start(&root)?);
+    }
     let pairs = MageParser::parse(crate::Rule::program, source);
-    match pairs {
+    let result = match pairs {
         Ok(pairs) => interpret(
             pairs,
             shell_override.as_deref(),
@@ -54,7 +112,26 @@ pub fn run(source: &str, cli_shell: Option<&str>) -> Result<(), String> {
         )
         .map_err(|e| format!("{}", e)),
         Err(err) => Err(format!("Parse error: {}", err)),
+    };
+
+    let summary = output.resource_summary();
+    if summary.total() > 0 {
+        output.println(&summary.to_string());
     }
+    if let Some(journal) = output.take_journal() {
+        if journal.is_empty() {
+            // The script's own error is the one worth reporting
+            return result.and(journal.discard());
+        } else {
+            let run_id = journal.run_id();
+            output.println(&format!(
+                "Journaled as run {} (undo with `mage undo {}`)",
+                run_id, run_id
+            ));
+        }
+    }
+
+    result
 }
 
 /// Format mage source code
@@ -1,3 +1,6 @@
+use crate::ensure::{EnsureStatus, EnsureSummary};
+use crate::journal::Journal;
+use crate::tasks::Task;
 use std::io::{self, Write};
 
 /// Error type replacing process::exit() calls in the interpreter.
@@ -27,8 +30,22 @@ impl std::fmt::Display for InterpreterError {
 ///
 /// - `Direct`: prints to real stdout/stderr (CLI, scripts)
 /// - `Buffered`: captures into vectors (TUI, testing)
+///
+/// A collector can additionally be put in dry-run mode, in which case
+/// side-effecting operations report what they would do instead of doing it,
+/// and can carry a [`Journal`] that snapshots files before they are modified.
+/// In strict mode, reading an undefined variable raises an error that the
+/// interpreter surfaces as an error catchable with `invoke`/`seal`.
 pub struct OutputCollector {
     mode: OutputMode,
+    dry_run: bool,
+    strict: bool,
+    undefined_reads: usize,
+    errors_raised: usize,
+    pending_error: Option<String>,
+    resources: EnsureSummary,
+    journal: Option<Journal>,
+    tasks: Vec<Task>,
 }
 
 enum OutputMode {
@@ -44,6 +61,14 @@ impl OutputCollector {
     pub fn direct() -> Self {
         Self {
             mode: OutputMode::Direct,
+            dry_run: false,
+            strict: false,
+            undefined_reads: 0,
+            errors_raised: 0,
+            pending_error: None,
+            resources: EnsureSummary::default(),
+            journal: None,
+            tasks: Vec::new(),
         }
     }
 
@@ -54,14 +79,127 @@ impl OutputCollector {
                 stdout_buf: Vec::new(),
                 stderr_buf: Vec::new(),
             },
+            dry_run: false,
+            strict: false,
+            undefined_reads: 0,
+            errors_raised: 0,
+            pending_error: None,
+            resources: EnsureSummary::default(),
+            journal: None,
+            tasks: Vec::new(),
         }
     }
 
+    /// Snapshot files into `journal` before they are modified.
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Enable or disable dry-run mode.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Enable or disable strict mode.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
     /// Returns true if this collector is in buffered mode.
     pub fn is_buffered(&self) -> bool {
         matches!(self.mode, OutputMode::Buffered { .. })
     }
 
+    /// Returns true if side effects should be reported instead of performed.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Returns true if undefined variables are errors.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Note a read of an undefined variable, raising an error in strict mode.
+    pub fn undefined_variable(&mut self, name: &str) {
+        self.undefined_reads += 1;
+        if self.strict {
+            self.pending_error
+                .get_or_insert_with(|| format!("Undefined variable '{}'", name));
+        }
+    }
+
+    /// Number of undefined variable reads so far.
+    pub fn undefined_reads(&self) -> usize {
+        self.undefined_reads
+    }
+
+    /// Number of errors raised so far, not counting undefined variable reads.
+    pub fn errors_raised(&self) -> usize {
+        self.errors_raised
+    }
+
+    /// Raise an error from inside an expression. The first error wins until it is taken.
+    pub fn raise(&mut self, message: String) {
+        self.errors_raised += 1;
+        self.pending_error.get_or_insert(message);
+    }
+
+    /// Returns true if an error has been raised and not yet handled.
+    pub fn has_error(&self) -> bool {
+        self.pending_error.is_some()
+    }
+
+    /// Take the pending error, if any.
+    pub fn take_error(&mut self) -> Option<String> {
+        self.pending_error.take()
+    }
+
+    /// Record the current state of `path` in the undo journal, if journaling is enabled.
+    pub fn journal_snapshot(&mut self, path: &str) -> Result<(), String> {
+        match &mut self.journal {
+            Some(journal) => journal.snapshot(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Detach the undo journal, if journaling is enabled.
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    /// Record the outcome of an `ensure_*` resource.
+    pub fn record_resource(&mut self, status: EnsureStatus) {
+        self.resources.record(status);
+    }
+
+    /// Tally of `ensure_*` outcomes recorded so far.
+    pub fn resource_summary(&self) -> EnsureSummary {
+        self.resources
+    }
+
+    /// Record a task declared with `ritual`. Task names must be unique.
+    pub fn declare_task(&mut self, task: Task) -> Result<(), String> {
+        if self.tasks.iter().any(|t| t.name == task.name) {
+            return Err(format!("Task '{}' is defined more than once", task.name));
+        }
+        self.tasks.push(task);
+        Ok(())
+    }
+
+    /// Take the tasks declared so far, in declaration order.
+    pub fn take_tasks(&mut self) -> Vec<Task> {
+        std::mem::take(&mut self.tasks)
+    }
+
+    /// Report an operation that was skipped because of dry-run mode.
+    pub fn dry_run_note(&mut self, msg: &str) {
+        self.println(&format!("[dry-run] {}", msg));
+    }
+
     pub fn println(&mut self, msg: &str) {
         match &mut self.mode {
             OutputMode::Direct => {
@@ -1,5 +1,9 @@
+use crate::cache::PackageCache;
+use crate::registry::{MAGE_REGISTRY, Registry, RegistryEntry, RegistryIndex};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -10,9 +14,13 @@ pub struct PackageManifest {
     pub description: Option<String>,
     pub author: Option<String>,
     pub license: Option<String>,
+    #[serde(default)]
     pub dependencies: HashMap<String, PackageDependency>,
+    #[serde(default)]
     pub dev_dependencies: HashMap<String, PackageDependency>,
+    #[serde(default)]
     pub scripts: HashMap<String, String>,
+    #[serde(default)]
     pub keywords: Vec<String>,
     pub repository: Option<String>,
     pub homepage: Option<String>,
@@ -22,6 +30,7 @@ pub struct PackageManifest {
 pub struct PackageDependency {
     pub version: String,
     pub source: PackageSource,
+    #[serde(default)]
     pub optional: bool,
     pub platform: Option<String>, // "windows", "macos", "linux"
 }
@@ -46,8 +55,16 @@ pub struct LockedPackage {
     pub version: String,
     pub source: PackageSource,
     pub checksum: Option<String>,
+    /// The digest of a URL or mage registry package's unpacked files, which
+    /// `checksum` only covers as an archive
+    #[serde(default)]
+    pub tree_checksum: Option<String>,
     pub dependencies: Vec<String>,
     pub resolved_at: String, // timestamp
+    /// The `rev` a git dependency asked for in `mage.toml`, which `source`
+    /// pins to a commit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_rev: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,10 +75,128 @@ pub struct LockMetadata {
     pub mage_version: String,
 }
 
+/// The locked version of a package installed by a system package manager,
+/// which picks the version itself.
+pub const SYSTEM_VERSION: &str = "system";
+
+/// The locked version of a URL package that has no `mage.toml` version.
+pub const UNVERSIONED: &str = "unversioned";
+
+/// A version constraint on a package and the chain of dependents that imposed it.
+#[derive(Debug, Clone)]
+struct Requirement {
+    constraint: String,
+    chain: Vec<String>,
+    /// The package that declared the constraint, or `None` for the project itself
+    from: Option<String>,
+}
+
+impl Requirement {
+    /// Describe the requirement as `app -> tool 1.2.0 requires lib ^2`.
+    fn describe(&self, name: &str) -> String {
+        format!(
+            "{} requires {} {}",
+            self.chain.join(" -> "),
+            name,
+            self.constraint
+        )
+    }
+}
+
+/// The version picked for a package and the source to fetch that version from.
+struct Resolution {
+    version: String,
+    source: PackageSource,
+    requested_rev: Option<String>,
+}
+
+/// A package in the resolved dependency graph.
+struct ResolvedPackage {
+    resolution: Resolution,
+    dependencies: Vec<String>,
+}
+
+/// The result of checking an installed package against its `mage.lock` checksum.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntegrityStatus {
+    Verified,
+    Mismatch {
+        expected: String,
+        actual: String,
+    },
+    NotInstalled,
+    /// Installed by a system package manager, so there is nothing to hash
+    Unchecked,
+}
+
+#[derive(Debug, Clone)]
+pub struct IntegrityCheck {
+    pub name: String,
+    pub status: IntegrityStatus,
+}
+
+/// What [`PackageResolver::vendor`] did with a locked package.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VendorStatus {
+    Vendored,
+    AlreadyVendored,
+    /// Path and system packages are not copied; the reason says which
+    Skipped(&'static str),
+}
+
+#[derive(Debug, Clone)]
+pub struct VendoredPackage {
+    pub name: String,
+    pub version: String,
+    pub status: VendorStatus,
+}
+
+#[derive(Debug, Clone)]
+pub struct VendorReport {
+    pub packages: Vec<VendoredPackage>,
+    /// Entries removed from `vendor/` because `mage.lock` no longer has them
+    pub removed: usize,
+}
+
+/// A package whose locked version changed during [`PackageResolver::update`].
+#[derive(Debug, Clone)]
+pub struct PackageUpdate {
+    pub name: String,
+    /// `None` for a package that was not locked before
+    pub from: Option<String>,
+    /// `None` for a package that is no longer needed
+    pub to: Option<String>,
+}
+
+/// A direct dependency with a newer version available.
+#[derive(Debug, Clone)]
+pub struct OutdatedPackage {
+    pub name: String,
+    pub current: String,
+    /// The newest version the manifest's constraint allows
+    pub wanted: String,
+    /// The newest version available at all
+    pub latest: String,
+}
+
 pub struct PackageResolver {
     manifest_path: PathBuf,
     lock_path: PathBuf,
     packages_dir: PathBuf,
+    /// Git mirrors already fetched by this resolver
+    fetched: RefCell<HashSet<String>>,
+    /// URL packages already unpacked by this resolver
+    unpacked: RefCell<HashSet<String>>,
+    /// Registry for mage-native packages
+    registry: Option<Registry>,
+    /// Registry indexes already read by this resolver
+    indexes: RefCell<HashMap<String, Option<RegistryIndex>>>,
+    /// Shared content-addressed cache of downloaded packages
+    cache: Option<PackageCache>,
+    /// Packages copied into the project by `mage vendor`
+    vendor: PackageCache,
+    /// Install only from `vendor` and `cache`, never from the network
+    offline: bool,
 }
 
 impl PackageResolver {
@@ -70,9 +205,35 @@ impl PackageResolver {
             manifest_path: project_root.join("mage.toml"),
             lock_path: project_root.join("mage.lock"),
             packages_dir: project_root.join(".mage/packages"),
+            fetched: RefCell::new(HashSet::new()),
+            unpacked: RefCell::new(HashSet::new()),
+            registry: Registry::configured(),
+            indexes: RefCell::new(HashMap::new()),
+            cache: PackageCache::global(),
+            vendor: PackageCache::new(&project_root.join("vendor")),
+            offline: false,
         }
     }
 
+    /// Resolve mage-native packages from `registry` instead of the configured one.
+    pub fn with_registry(mut self, registry: Option<Registry>) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Share downloaded packages through `cache` instead of the global one.
+    pub fn with_cache(mut self, cache: Option<PackageCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Install packages only from `vendor/` and the package cache. A lock
+    /// file that needs updating is an error, since resolving needs the network.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
     pub fn init_project(&self, name: &str) -> Result<(), String> {
         if self.manifest_path.exists() {
             return Err("Project already initialized (mage.toml exists)".to_string());
@@ -103,14 +264,29 @@ impl PackageResolver {
     }
 
     pub fn add_dependency(&self, package: &str, version: &str, dev: bool) -> Result<(), String> {
-        let mut manifest = self.read_manifest()?;
-
         let dependency = PackageDependency {
             version: version.to_string(),
             source: PackageSource::Registry("auto".to_string()),
             optional: false,
             platform: None,
         };
+        self.add(package, dependency, dev)
+    }
+
+    /// Add (or replace) a dependency in `mage.toml` and update `mage.lock`.
+    /// `mage.toml` is left as it was when the new dependency cannot be
+    /// resolved.
+    pub fn add(
+        &self,
+        package: &str,
+        dependency: PackageDependency,
+        dev: bool,
+    ) -> Result<(), String> {
+        validate_package_name(package)?;
+        validate_source(&dependency.source)?;
+        let original = fs::read_to_string(&self.manifest_path)
+            .map_err(|e| format!("Failed to read manifest: {}", e))?;
+        let mut manifest = self.read_manifest()?;
 
         if dev {
             manifest
@@ -123,7 +299,10 @@ impl PackageResolver {
         }
 
         self.write_manifest(&manifest)?;
-        self.resolve_dependencies()?;
+        if let Err(e) = self.resolve_dependencies() {
+            let _ = fs::write(&self.manifest_path, original);
+            return Err(e);
+        }
 
         Ok(())
     }
@@ -144,45 +323,173 @@ impl PackageResolver {
         Ok(())
     }
 
+    /// Write `mage.lock` for the current manifest. Packages that are already
+    /// locked keep their version while it still satisfies their constraints.
     pub fn resolve_dependencies(&self) -> Result<(), String> {
-        let manifest = self.read_manifest()?;
-        let mut resolved_packages = HashMap::new();
-        let mut resolution_queue = Vec::new();
+        let locked = self
+            .read_lock()
+            .map(|lock| lock.packages)
+            .unwrap_or_default();
+        self.lock_dependencies(&locked).map(|_| ())
+    }
 
-        // Add direct dependencies to queue
-        for (name, dep) in &manifest.dependencies {
-            resolution_queue.push((name.clone(), dep.clone(), false));
+    /// Re-resolve `packages` (every package when empty) to the newest
+    /// versions their constraints allow, keeping the rest at their locked
+    /// versions. Returns the packages whose locked version changed.
+    pub fn update(&self, packages: &[String]) -> Result<Vec<PackageUpdate>, String> {
+        let old = self
+            .read_lock()
+            .map(|lock| lock.packages)
+            .unwrap_or_default();
+        if let Some(missing) = packages.iter().find(|name| !old.contains_key(*name)) {
+            return Err(format!("Package '{}' is not in mage.lock", missing));
         }
 
-        for (name, dep) in &manifest.dev_dependencies {
-            resolution_queue.push((name.clone(), dep.clone(), true));
-        }
+        let preferred: HashMap<String, LockedPackage> = if packages.is_empty() {
+            HashMap::new()
+        } else {
+            old.iter()
+                .filter(|(name, _)| !packages.contains(name))
+                .map(|(name, locked)| (name.clone(), locked.clone()))
+                .collect()
+        };
+        let new = self.lock_dependencies(&preferred)?;
+
+        let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+        names.sort();
+        names.dedup();
+        Ok(names
+            .into_iter()
+            .filter_map(|name| {
+                let from = old.get(name);
+                let to = new.get(name);
+                let unchanged = matches!((from, to), (Some(from), Some(to))
+                    if from.version == to.version
+                        && same_revision(&from.source, &to.source)
+                        && from.checksum == to.checksum);
+                (!unchanged).then(|| PackageUpdate {
+                    name: name.clone(),
+                    from: from.map(|locked| locked.version.clone()),
+                    to: to.map(|locked| locked.version.clone()),
+                })
+            })
+            .collect())
+    }
 
-        // Resolve dependencies breadth-first
-        let mut visited = HashSet::new();
-        while let Some((name, dep, _is_dev)) = resolution_queue.pop() {
-            if visited.contains(&name) {
+    /// Direct git, path and mage registry dependencies whose locked version is behind the
+    /// newest version their constraint allows, or the newest one overall.
+    pub fn outdated(&self) -> Result<Vec<OutdatedPackage>, String> {
+        let manifest = self.read_manifest()?;
+        let lock = self.read_lock()?;
+
+        let mut outdated: Vec<OutdatedPackage> = Vec::new();
+        let mut checked: HashSet<&String> = HashSet::new();
+        let direct = sorted_dependencies(&manifest.dependencies)
+            .into_iter()
+            .chain(sorted_dependencies(&manifest.dev_dependencies));
+        for (name, dep) in direct {
+            let Some(locked) = lock.packages.get(name) else {
+                continue;
+            };
+            if !checked.insert(name) {
                 continue;
             }
-            visited.insert(name.clone());
 
-            // Skip platform-specific packages
-            if let Some(platform) = &dep.platform
-                && platform != std::env::consts::OS
-            {
+            let versions: Vec<semver::Version> = match &dep.source {
+                PackageSource::Git { url, rev: None } if git_branch(dep).is_none() => {
+                    git_tag_versions(url)?
+                        .into_iter()
+                        .map(|(version, _)| version)
+                        .collect()
+                }
+                PackageSource::Path(path) => vec![self.path_package_version(path)?],
+                PackageSource::Registry(manager) if from_mage_registry(&locked.source) => {
+                    match self.registry_index(name, manager)? {
+                        Some(index) => index
+                            .versions
+                            .iter()
+                            .filter_map(|entry| parse_version(&entry.version))
+                            .collect(),
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            let constraint = parse_constraint(&dep.version)?;
+            let wanted = versions.iter().filter(|v| constraint.matches(v)).max();
+            let latest = versions.iter().max();
+            let (Some(wanted), Some(latest)) = (wanted, latest) else {
                 continue;
+            };
+
+            let current = parse_version(&locked.version);
+            if current.as_ref() != Some(wanted) || current.as_ref() != Some(latest) {
+                outdated.push(OutdatedPackage {
+                    name: name.clone(),
+                    current: locked.version.clone(),
+                    wanted: wanted.to_string(),
+                    latest: latest.to_string(),
+                });
             }
+        }
+        Ok(outdated)
+    }
+
+    /// Render the locked dependency graph as a tree under the project.
+    /// A package whose dependencies were already shown is marked `(*)`.
+    pub fn dependency_tree(&self) -> Result<String, String> {
+        let manifest = self.read_manifest()?;
+        let lock = self.read_lock()?;
+
+        let mut out = format!("{} {}\n", manifest.name, manifest.version);
+        let mut shown = HashSet::new();
+        let roots = |dependencies: &HashMap<String, PackageDependency>| -> Vec<String> {
+            sorted_dependencies(dependencies)
+                .into_iter()
+                .map(|(name, _)| name.clone())
+                .filter(|name| lock.packages.contains_key(name))
+                .collect()
+        };
+        render_tree(
+            &roots(&manifest.dependencies),
+            "",
+            &lock,
+            &mut shown,
+            &mut out,
+        );
+
+        let dev = roots(&manifest.dev_dependencies);
+        if !dev.is_empty() {
+            out.push_str("\n[dev-dependencies]\n");
+            render_tree(&dev, "", &lock, &mut shown, &mut out);
+        }
+        Ok(out)
+    }
+
+    /// Resolve the manifest, preferring the `preferred` locked versions, and
+    /// write the result to `mage.lock`.
+    fn lock_dependencies(
+        &self,
+        preferred: &HashMap<String, LockedPackage>,
+    ) -> Result<HashMap<String, LockedPackage>, String> {
+        let manifest = self.read_manifest()?;
+        let mut resolved_packages = HashMap::new();
 
-            // Resolve package version and source
-            let resolved_version = self.resolve_version(&name, &dep)?;
-            let checksum = self.calculate_checksum(&name, &resolved_version, &dep.source)?;
+        for (name, package) in self.resolve_graph(&manifest, preferred)? {
+            let resolution = package.resolution;
+            let checksum =
+                self.calculate_checksum(&name, &resolution.version, &resolution.source)?;
+            let tree_checksum =
+                self.calculate_tree_checksum(&name, &resolution.version, &resolution.source)?;
 
             let locked_package = LockedPackage {
-                version: resolved_version,
-                source: dep.source.clone(),
-                checksum: Some(checksum),
-                dependencies: Vec::new(), // TODO: Parse transitive dependencies
+                version: resolution.version,
+                source: resolution.source,
+                checksum,
+                tree_checksum,
+                dependencies: package.dependencies,
                 resolved_at: chrono::Utc::now().to_rfc3339(),
+                requested_rev: resolution.requested_rev,
             };
 
             resolved_packages.insert(name, locked_package);
@@ -201,52 +508,468 @@ impl PackageResolver {
         };
 
         self.write_lock(&lock)?;
-        Ok(())
+        Ok(lock.packages)
     }
 
-    pub fn install_dependencies(&self, dev: bool) -> Result<(), String> {
-        let manifest = self.read_manifest()?;
-        let lock = self.read_lock().ok();
+    /// Resolve the project's dependencies and everything they depend on.
+    ///
+    /// Each resolved package's own `mage.toml` is read and its dependencies
+    /// queued, so every package appears once with a version that satisfies
+    /// all of its dependents. When a later constraint rules out a version that
+    /// was already picked, the package is resolved again and the constraints
+    /// its old version imposed are withdrawn.
+    fn resolve_graph(
+        &self,
+        manifest: &PackageManifest,
+        preferred: &HashMap<String, LockedPackage>,
+    ) -> Result<HashMap<String, ResolvedPackage>, String> {
+        // The first declaration of each package decides its source
+        let mut declared: HashMap<String, (PackageDependency, Vec<String>)> = HashMap::new();
+        let mut requirements: HashMap<String, Vec<Requirement>> = HashMap::new();
+        let mut queue: VecDeque<String> = VecDeque::new();
+        let mut roots: Vec<String> = Vec::new();
+
+        let dev_root = format!("{} (dev)", manifest.name);
+        let direct = sorted_dependencies(&manifest.dependencies)
+            .into_iter()
+            .map(|dep| (dep, &manifest.name))
+            .chain(
+                sorted_dependencies(&manifest.dev_dependencies)
+                    .into_iter()
+                    .map(|dep| (dep, &dev_root)),
+            );
+        for ((name, dep), root) in direct {
+            // Skip platform-specific packages
+            if let Some(platform) = &dep.platform
+                && platform != std::env::consts::OS
+            {
+                continue;
+            }
 
-        fs::create_dir_all(&self.packages_dir)
-            .map_err(|e| format!("Failed to create packages directory: {}", e))?;
+            requirements
+                .entry(name.clone())
+                .or_default()
+                .push(Requirement {
+                    constraint: dep.version.clone(),
+                    chain: vec![root.clone()],
+                    from: None,
+                });
+            if !declared.contains_key(name) {
+                declared.insert(name.clone(), (dep.clone(), vec![root.clone()]));
+                roots.push(name.clone());
+                queue.push_back(name.clone());
+            }
+        }
+
+        let mut resolved: HashMap<String, ResolvedPackage> = HashMap::new();
+        let mut steps = 0;
+        while let Some(name) = queue.pop_front() {
+            steps += 1;
+            if steps > 1000 {
+                return Err(format!(
+                    "Dependency resolution did not settle (last package: '{}')",
+                    name
+                ));
+            }
 
-        // Install regular dependencies
-        for (name, dep) in &manifest.dependencies {
-            self.install_package(name, dep, &lock)?;
+            let (dep, chain) = declared[&name].clone();
+            let resolution =
+                self.resolve_version(&name, &dep, &requirements[&name], preferred.get(&name))?;
+            if let Some(existing) = resolved.get(&name)
+                && existing.resolution.version == resolution.version
+            {
+                continue;
+            }
+
+            // Withdraw the constraints this package imposed at a previous version
+            for reqs in requirements.values_mut() {
+                reqs.retain(|req| req.from.as_deref() != Some(name.as_str()));
+            }
+
+            let mut chain = chain;
+            chain.push(format!("{} {}", name, resolution.version));
+
+            let mut dependencies = Vec::new();
+            if let Some(package_manifest) = self.package_manifest(&name, &dep, &resolution)? {
+                for (child, child_dep) in sorted_dependencies(&package_manifest.dependencies) {
+                    validate_package_name(child)
+                        .and_then(|_| validate_source(&child_dep.source))
+                        .map_err(|e| format!("{} (required by {})", e, chain.join(" -> ")))?;
+                    if child_dep.optional
+                        || child_dep
+                            .platform
+                            .as_ref()
+                            .is_some_and(|platform| platform != std::env::consts::OS)
+                    {
+                        continue;
+                    }
+                    if child == &manifest.name {
+                        return Err(format!(
+                            "Dependency cycle detected: {} -> {}",
+                            chain.join(" -> "),
+                            child
+                        ));
+                    }
+
+                    let child_dep = rebase_dependency(&name, &dep.source, child, child_dep)?;
+                    match declared.get(child) {
+                        Some((existing, existing_chain))
+                            if !same_source(&existing.source, &child_dep.source) =>
+                        {
+                            return Err(format!(
+                                "Conflicting sources for '{}':\n  {} uses {}\n  {} uses {}",
+                                child,
+                                existing_chain.join(" -> "),
+                                describe_source(&existing.source),
+                                chain.join(" -> "),
+                                describe_source(&child_dep.source)
+                            ));
+                        }
+                        Some(_) => {}
+                        None => {
+                            declared.insert(child.clone(), (child_dep.clone(), chain.clone()));
+                        }
+                    }
+
+                    requirements
+                        .entry(child.clone())
+                        .or_default()
+                        .push(Requirement {
+                            constraint: child_dep.version.clone(),
+                            chain: chain.clone(),
+                            from: Some(name.clone()),
+                        });
+                    dependencies.push(child.clone());
+                    queue.push_back(child.clone());
+                }
+            }
+
+            resolved.insert(
+                name,
+                ResolvedPackage {
+                    resolution,
+                    dependencies,
+                },
+            );
         }
 
-        // Install dev dependencies if requested
-        if dev {
-            for (name, dep) in &manifest.dev_dependencies {
-                self.install_package(name, dep, &lock)?;
+        // Drop packages that were only needed by a version that got replaced
+        let mut reachable: HashSet<String> = HashSet::new();
+        let mut pending = roots.clone();
+        while let Some(name) = pending.pop() {
+            if reachable.insert(name.clone())
+                && let Some(package) = resolved.get(&name)
+            {
+                pending.extend(package.dependencies.iter().cloned());
             }
         }
+        resolved.retain(|name, _| reachable.contains(name));
 
-        Ok(())
+        if let Some(cycle) = find_cycle(&roots, &resolved) {
+            return Err(format!("Dependency cycle detected: {}", cycle.join(" -> ")));
+        }
+
+        Ok(resolved)
     }
 
-    fn install_package(
+    /// The `mage.toml` of a resolved package, if it has one.
+    ///
+    /// Path packages are read in place and git packages at the resolved
+    /// revision. Mage registry packages list their dependencies in the
+    /// registry index, and URL packages are read from their unpacked
+    /// download.
+    fn package_manifest(
         &self,
         name: &str,
         dep: &PackageDependency,
-        _lock: &Option<PackageLock>,
-    ) -> Result<(), String> {
-        // Check if package is platform-specific
-        if let Some(platform) = &dep.platform
-            && platform != &std::env::consts::OS.to_string()
-        {
-            println!(
-                "⏭️  Skipping {} (not for platform {})",
+        resolution: &Resolution,
+    ) -> Result<Option<PackageManifest>, String> {
+        let (content, origin) = match &resolution.source {
+            PackageSource::Registry(manager) if manager == MAGE_REGISTRY => {
+                let entry = self.registry_entry(name, &resolution.version)?;
+                return Ok(Some(PackageManifest {
+                    name: name.to_string(),
+                    version: entry.version,
+                    description: entry.description,
+                    author: None,
+                    license: None,
+                    dependencies: entry.dependencies,
+                    dev_dependencies: HashMap::new(),
+                    scripts: HashMap::new(),
+                    keywords: Vec::new(),
+                    repository: None,
+                    homepage: None,
+                }));
+            }
+            PackageSource::Registry(_) => return Ok(None),
+            PackageSource::Path(path) => {
+                let manifest_path = self.project_root().join(path).join("mage.toml");
+                match fs::read_to_string(&manifest_path) {
+                    Ok(content) => (content, manifest_path.display().to_string()),
+                    Err(_) => return Ok(None),
+                }
+            }
+            PackageSource::Git { url, rev } => {
+                match self.git_file(url, rev.as_deref(), "mage.toml")? {
+                    Some(content) => (content, format!("mage.toml of {}", url)),
+                    None => return Ok(None),
+                }
+            }
+            PackageSource::Url(_) => {
+                let manifest_path = self
+                    .unpacked_package(name, &resolution.version, &resolution.source, None)?
+                    .join("mage.toml");
+                match fs::read_to_string(&manifest_path) {
+                    Ok(content) => (content, manifest_path.display().to_string()),
+                    Err(_) => return Ok(None),
+                }
+            }
+        };
+
+        toml::from_str(&content).map(Some).map_err(|e| {
+            format!(
+                "Failed to parse {} (required by {} {}): {}",
+                origin, name, dep.version, e
+            )
+        })
+    }
+
+    /// A bare mirror of a git repository under `.mage/cache/git`, fetched
+    /// at most once per resolver.
+    fn git_mirror(&self, url: &str) -> Result<PathBuf, String> {
+        use std::process::Command;
+
+        let mirror = self
+            .project_root()
+            .join(".mage/cache/git")
+            .join(format!("{:x}", md5::compute(url)));
+        if self.fetched.borrow().contains(url) {
+            return Ok(mirror);
+        }
+
+        let output = if mirror.exists() {
+            Command::new("git")
+                .arg("--git-dir")
+                .arg(&mirror)
+                .args(["fetch", "--quiet", "--tags", "--force", "origin"])
+                .arg("+refs/heads/*:refs/heads/*")
+                .output()
+        } else {
+            Command::new("git")
+                .args(["clone", "--bare", "--quiet", "--", url])
+                .arg(&mirror)
+                .output()
+        }
+        .map_err(|e| format!("Failed to fetch {}: {}", url, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to fetch {}: {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        self.fetched.borrow_mut().insert(url.to_string());
+        Ok(mirror)
+    }
+
+    /// The commit hash a branch, tag or commit of a git repository points to.
+    fn git_commit(&self, url: &str, rev: &str) -> Result<String, String> {
+        let output = std::process::Command::new("git")
+            .arg("--git-dir")
+            .arg(self.git_mirror(url)?)
+            .args(["rev-parse", "--verify", "--quiet", "--end-of-options"])
+            .arg(format!("{}^{{commit}}", rev))
+            .output()
+            .map_err(|e| format!("Failed to read {}: {}", url, e))?;
+        if !output.status.success() {
+            return Err(format!("Revision '{}' not found in {}", rev, url));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Read `file` from a git repository at `rev` (or the default branch).
+    fn git_file(&self, url: &str, rev: Option<&str>, file: &str) -> Result<Option<String>, String> {
+        let commit = self.git_commit(url, rev.unwrap_or("HEAD"))?;
+        let output = std::process::Command::new("git")
+            .arg("--git-dir")
+            .arg(self.git_mirror(url)?)
+            .args(["show", "--end-of-options"])
+            .arg(format!("{}:{}", commit, file))
+            .output()
+            .map_err(|e| format!("Failed to read {}: {}", url, e))?;
+        Ok(output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).to_string()))
+    }
+
+    /// The index a `Registry(manager)` dependency resolves from. `mage`
+    /// always uses the registry; `auto` uses it when the package has been
+    /// published there and otherwise falls back to the system package
+    /// manager. Each index is read once per resolver.
+    fn registry_index(&self, name: &str, manager: &str) -> Result<Option<RegistryIndex>, String> {
+        if manager != MAGE_REGISTRY && manager != "auto" {
+            return Ok(None);
+        }
+        let Some(registry) = &self.registry else {
+            if manager == MAGE_REGISTRY {
+                return Err(format!(
+                    "'{}' comes from the mage registry, but no registry is configured \
+                     (set MAGE_REGISTRY or registry= in .mageconfig)",
+                    name
+                ));
+            }
+            return Ok(None);
+        };
+
+        if !self.indexes.borrow().contains_key(name) {
+            let index = registry.index(name)?;
+            self.indexes.borrow_mut().insert(name.to_string(), index);
+        }
+        let index = self.indexes.borrow()[name].clone();
+        if index.is_none() && manager == MAGE_REGISTRY {
+            return Err(format!(
+                "'{}' is not in the registry at {}",
                 name,
-                std::env::consts::OS
+                registry.location()
+            ));
+        }
+        Ok(index)
+    }
+
+    /// The registry entry of a mage registry package at `version`.
+    fn registry_entry(&self, name: &str, version: &str) -> Result<RegistryEntry, String> {
+        self.registry_index(name, MAGE_REGISTRY)?
+            .and_then(|index| index.versions.into_iter().find(|e| e.version == version))
+            .ok_or_else(|| {
+                format!(
+                    "{} {} is not in the registry at {}",
+                    name,
+                    version,
+                    self.registry.as_ref().map_or("", |r| r.location())
+                )
+            })
+    }
+
+    /// Where to download a mage registry package's archive from.
+    fn registry_archive(&self, name: &str, version: &str) -> Result<String, String> {
+        let entry = self.registry_entry(name, version)?;
+        Ok(self
+            .registry
+            .as_ref()
+            .map(|registry| registry.archive_location(&entry))
+            .unwrap_or(entry.archive))
+    }
+
+    /// Where a URL package's download is kept.
+    fn download_path(&self, url: &str) -> PathBuf {
+        self.project_root()
+            .join(".mage/cache/downloads")
+            .join(format!("{:x}", md5::compute(url)))
+    }
+
+    /// Where the archive of a URL or mage registry package is kept.
+    fn archive_path(&self, name: &str, locked: &LockedPackage) -> PathBuf {
+        self.download_path(&archive_key(name, &locked.version, &locked.source))
+    }
+
+    fn project_root(&self) -> &Path {
+        self.manifest_path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Install exactly what `mage.lock` records: every package reachable
+    /// from the manifest's dependencies (and dev dependencies with `dev`),
+    /// at its locked version, revision and checksum. Packages are copied
+    /// from `vendor/` or the package cache when either has them.
+    ///
+    /// A missing or outdated lock file is regenerated first, unless `frozen`
+    /// is set, in which case it is an error.
+    pub fn install_dependencies(&self, dev: bool, frozen: bool) -> Result<(), String> {
+        let manifest = self.read_manifest()?;
+        let lock = self.current_lock(&manifest, frozen)?;
+
+        fs::create_dir_all(&self.packages_dir)
+            .map_err(|e| format!("Failed to create packages directory: {}", e))?;
+
+        let mut pending: Vec<String> = sorted_dependencies(&manifest.dependencies)
+            .into_iter()
+            .chain(if dev {
+                sorted_dependencies(&manifest.dev_dependencies)
+            } else {
+                Vec::new()
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        pending.reverse();
+
+        let mut installed: HashSet<String> = HashSet::new();
+        while let Some(name) = pending.pop() {
+            // Platform-specific packages are not in the lock file
+            let Some(locked) = lock.packages.get(&name) else {
+                println!(
+                    "⏭️  Skipping {} (not for platform {})",
+                    name,
+                    std::env::consts::OS
+                );
+                continue;
+            };
+            if !installed.insert(name.clone()) {
+                continue;
+            }
+
+            self.install_package(&name, locked)?;
+            pending.extend(locked.dependencies.iter().rev().cloned());
+        }
+
+        Ok(())
+    }
+
+    /// `mage.lock`, regenerated first if it is missing or no longer matches
+    /// `manifest`. Regenerating is an error when `frozen` or offline.
+    fn current_lock(
+        &self,
+        manifest: &PackageManifest,
+        frozen: bool,
+    ) -> Result<PackageLock, String> {
+        let stale = match self.read_lock() {
+            Ok(lock) => match lock_mismatch(manifest, &lock) {
+                None => return Ok(lock),
+                Some(reason) if frozen => {
+                    return Err(format!("mage.lock is out of date: {}", reason));
+                }
+                Some(reason) => format!("mage.lock is out of date: {}", reason),
+            },
+            Err(e) if frozen && self.lock_path.exists() => return Err(e),
+            Err(_) if frozen => {
+                return Err("mage.lock is missing; run without --frozen to create it".to_string());
+            }
+            Err(_) => "mage.lock is missing".to_string(),
+        };
+        if self.offline {
+            return Err(format!("{}, and it cannot be updated offline", stale));
+        }
+        self.resolve_dependencies()?;
+        self.read_lock()
+    }
+
+    fn install_package(&self, name: &str, locked: &LockedPackage) -> Result<(), String> {
+        let system_package = matches!(locked.source, PackageSource::Registry(_))
+            && !from_mage_registry(&locked.source);
+        if self.offline && system_package {
+            println!(
+                "⏭️  Skipping {} (system packages cannot be installed offline)",
+                name
             );
             return Ok(());
         }
+        println!("📦 Installing {} {}...", name, locked.version);
 
-        println!("📦 Installing {}...", name);
-
-        match &dep.source {
+        match &locked.source {
+            PackageSource::Registry(manager) if manager == MAGE_REGISTRY => {
+                let archive = self.fetch_archive(name, locked)?;
+                self.unpack_archive(name, &archive, name)?;
+            }
             PackageSource::Registry(manager) => {
                 if manager == "auto" {
                     // Use system package manager
@@ -262,16 +985,39 @@ impl PackageResolver {
                 }
             }
             PackageSource::Git { url, rev } => {
-                self.install_from_git(name, url, rev.as_deref())?;
+                let commit = rev
+                    .as_deref()
+                    .ok_or_else(|| format!("mage.lock has no commit for {}", name))?;
+                self.install_from_git(name, url, commit, locked.checksum.as_deref())?;
             }
             PackageSource::Path(path) => {
                 self.install_from_path(name, path)?;
             }
             PackageSource::Url(url) => {
-                self.install_from_url(name, url)?;
+                let archive = self.fetch_archive(name, locked)?;
+                self.unpack_archive(name, &archive, url_file_name(url, name))?;
             }
         }
 
+        if let Some(expected) = installed_digest(locked)
+            && let Some(actual) = self.installed_checksum(name, locked)?
+            && &actual != expected
+        {
+            self.discard_install(name);
+            return Err(format!(
+                "Checksum mismatch for {}: mage.lock has {}, but the installed copy hashes to {}",
+                name, expected, actual
+            ));
+        }
+        if let Some(checksum) = &locked.checksum {
+            self.cache_package(name, locked, checksum);
+        }
+
+        if matches!(locked.source, PackageSource::Git { .. }) || from_mage_registry(&locked.source)
+        {
+            self.run_install_script(name)?;
+        }
+
         println!("✅ Installed {}", name);
         Ok(())
     }
@@ -304,43 +1050,85 @@ impl PackageResolver {
         }
     }
 
-    fn install_from_git(&self, name: &str, url: &str, rev: Option<&str>) -> Result<(), String> {
-        let package_dir = self.packages_dir.join(name);
+    /// Check out `commit` of a git package, reusing an existing checkout.
+    /// Without one, the tree is copied from `vendor/` or the package cache
+    /// when either has `checksum`, and cloned otherwise.
+    fn install_from_git(
+        &self,
+        name: &str,
+        url: &str,
+        commit: &str,
+        checksum: Option<&str>,
+    ) -> Result<(), String> {
+        use std::process::Command;
 
-        // Clone or update repository
-        if package_dir.exists() {
-            // Update existing repo
-            use std::process::Command;
-            let output = Command::new("git")
-                .args(["pull"])
-                .current_dir(&package_dir)
+        let package_dir = self.packages_dir.join(name);
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(&package_dir)
+                .args(args)
                 .output()
-                .map_err(|e| format!("Failed to update git repo: {}", e))?;
+                .map_err(|e| format!("Failed to run git for {}: {}", name, e))
+        };
 
+        let checked_out = package_dir.join(".git").exists();
+        if checked_out {
+            let head = git(&["rev-parse", "HEAD"])?;
+            if String::from_utf8_lossy(&head.stdout).trim() == commit {
+                return Ok(());
+            }
+        }
+        if let Some(tree) = checksum.and_then(|checksum| self.cached(checksum, PackageCache::tree))
+        {
+            self.discard_install(name);
+            return copy_tree(&tree, &package_dir);
+        }
+        if self.offline {
+            return Err(self.not_cached(name));
+        }
+
+        if checked_out {
+            let output = git(&["fetch", "--quiet", "--tags", "origin"])?;
             if !output.status.success() {
-                return Err("Failed to update git repository".to_string());
+                return Err(format!(
+                    "Failed to update git repository for {}: {}",
+                    name,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
             }
         } else {
-            // Clone new repo
-            use std::process::Command;
-            let mut args = vec!["clone", url];
-            if let Some(rev) = rev {
-                args.extend(&["--branch", rev]);
-            }
-            args.push(package_dir.to_str().unwrap());
-
+            self.discard_install(name);
             let output = Command::new("git")
-                .args(&args)
+                .args(["clone", "--quiet", "--", url])
+                .arg(&package_dir)
                 .output()
                 .map_err(|e| format!("Failed to clone git repo: {}", e))?;
-
             if !output.status.success() {
-                return Err("Failed to clone git repository".to_string());
+                return Err(format!(
+                    "Failed to clone git repository for {}: {}",
+                    name,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
             }
         }
 
-        // Run package-specific install script if it exists
-        let install_script = package_dir.join("install.mage");
+        let output = git(&["switch", "--quiet", "--detach", "--end-of-options", commit])?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to check out {} of {}: {}",
+                commit,
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Run a git or registry package's `install.mage`, if it has one.
+    fn run_install_script(&self, name: &str) -> Result<(), String> {
+        let install_script = self.packages_dir.join(name).join("install.mage");
         if install_script.exists() {
             use std::process::Command;
             let output = Command::new("mage")
@@ -357,13 +1145,25 @@ impl PackageResolver {
         Ok(())
     }
 
-    fn install_from_path(&self, name: &str, path: &str) -> Result<(), String> {
-        let source_path = Path::new(path);
+    /// Remove a package that failed verification. Path packages are
+    /// symlinks, so only the link is removed.
+    fn discard_install(&self, name: &str) {
         let package_dir = self.packages_dir.join(name);
+        let _ = match fs::symlink_metadata(&package_dir) {
+            Ok(meta) if meta.is_dir() => fs::remove_dir_all(&package_dir),
+            _ => fs::remove_file(&package_dir),
+        };
+    }
 
-        if !source_path.exists() {
-            return Err(format!("Source path does not exist: {}", path));
-        }
+    fn install_from_path(&self, name: &str, path: &str) -> Result<(), String> {
+        let source_path = self
+            .project_root()
+            .join(path)
+            .canonicalize()
+            .map_err(|_| format!("Source path does not exist: {}", path))?;
+        let source_path = source_path.as_path();
+        let package_dir = self.packages_dir.join(name);
+        self.discard_install(name);
 
         // Create symlink to local package
         #[cfg(unix)]
@@ -388,54 +1188,568 @@ impl PackageResolver {
         Ok(())
     }
 
-    fn install_from_url(&self, name: &str, url: &str) -> Result<(), String> {
+    /// Put the archive of a URL or mage registry package at its
+    /// [`archive_path`](Self::archive_path): copied from `vendor/` or the
+    /// package cache when either has its checksum, and downloaded otherwise.
+    /// A download is checked against the checksum before anything is
+    /// unpacked, and the previous copy is kept until the new one checks out.
+    fn fetch_archive(&self, name: &str, locked: &LockedPackage) -> Result<PathBuf, String> {
+        let path = self.archive_path(name, locked);
+        let checksum = locked.checksum.as_deref();
+        if let Some(cached) =
+            checksum.and_then(|checksum| self.cached(checksum, PackageCache::file))
+        {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create download cache: {}", e))?;
+            }
+            fs::copy(&cached, &path)
+                .map_err(|e| format!("Failed to copy {} from the package cache: {}", name, e))?;
+            return Ok(path);
+        }
+        if self.offline {
+            return Err(self.not_cached(name));
+        }
+
+        let url = match &locked.source {
+            PackageSource::Url(url) => url.clone(),
+            _ => self.registry_archive(name, &locked.version)?,
+        };
+        let fresh = path.with_extension("new");
+        download(&url, &fresh)?;
+        if let Some(expected) = checksum {
+            let actual = hash_file(&fresh)?;
+            if actual != expected {
+                let _ = fs::remove_file(&fresh);
+                return Err(format!(
+                    "Checksum mismatch for {}: mage.lock has {}, but {} hashes to {}",
+                    name, expected, url, actual
+                ));
+            }
+        }
+        fs::rename(&fresh, &path)
+            .map_err(|e| format!("Failed to store download of {}: {}", url, e))?;
+        Ok(path)
+    }
+
+    /// Extract a fetched archive into a fresh package directory with
+    /// [`unpack`].
+    fn unpack_archive(&self, name: &str, archive: &Path, file_name: &str) -> Result<(), String> {
+        self.discard_install(name);
         let package_dir = self.packages_dir.join(name);
         fs::create_dir_all(&package_dir)
             .map_err(|e| format!("Failed to create package directory: {}", e))?;
+        unpack(archive, &package_dir, file_name)
+            .map_err(|e| format!("Failed to install {}: {}", name, e))
+    }
 
-        // Download and extract
-        let download_path = package_dir.join("download");
-        let _ = crate::builtins::call_builtin(
-            "download",
-            vec![url.to_string(), download_path.to_string_lossy().to_string()],
-            &mut crate::output::OutputCollector::direct(),
-        )
-        .map_err(|e| format!("Failed to download package: {}", e))?;
+    /// A verified copy of the package with `checksum`, from `vendor/` or the
+    /// package cache. A damaged cache entry is dropped so the next install
+    /// replaces it.
+    fn cached(
+        &self,
+        checksum: &str,
+        lookup: impl Fn(&PackageCache, &str) -> Option<PathBuf>,
+    ) -> Option<PathBuf> {
+        if let Some(path) = lookup(&self.vendor, checksum) {
+            return Some(path);
+        }
+        let cache = self.cache.as_ref()?;
+        let path = lookup(cache, checksum);
+        if path.is_none() {
+            cache.remove(checksum);
+        }
+        path
+    }
 
-        // TODO: Handle different archive formats (zip, tar.gz, etc.)
-        Ok(())
+    /// Keep a verified package in the package cache for later installs.
+    /// Failing to write the cache does not fail the install.
+    fn cache_package(&self, name: &str, locked: &LockedPackage, checksum: &str) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        if cache.contains(checksum) {
+            return;
+        }
+        let stored = match &locked.source {
+            PackageSource::Git { .. } => cache.store_tree(checksum, &self.packages_dir.join(name)),
+            source if matches!(source, PackageSource::Url(_)) || from_mage_registry(source) => {
+                cache.store_file(checksum, &self.archive_path(name, locked))
+            }
+            _ => return,
+        };
+        if let Err(e) = stored {
+            println!("⚠️  Could not cache {}: {}", name, e);
+        }
+    }
+
+    fn not_cached(&self, name: &str) -> String {
+        match &self.cache {
+            Some(cache) => format!(
+                "Cannot install {} offline: it is not in vendor/ or the package cache at {}",
+                name,
+                cache.root().display()
+            ),
+            None => format!("Cannot install {} offline: it is not in vendor/", name),
+        }
     }
 
-    fn resolve_version(&self, _name: &str, dep: &PackageDependency) -> Result<String, String> {
-        // Simple version resolution - in a real implementation you'd:
-        // 1. Parse version constraints (^1.0.0, ~1.2.0, >=1.0.0, etc.)
-        // 2. Query package registry for available versions
-        // 3. Find best matching version
-        // 4. Handle conflicts between dependencies
+    /// Copy every locked package into `vendor/` under its checksum, the same
+    /// layout as the package cache, so installs work without the network.
+    /// Entries `mage.lock` no longer has are removed. Path packages are
+    /// already local and system packages cannot be copied, so both are
+    /// skipped.
+    pub fn vendor(&self) -> Result<VendorReport, String> {
+        let manifest = self.read_manifest()?;
+        let lock = self.current_lock(&manifest, false)?;
+        let mut names: Vec<&String> = lock.packages.keys().collect();
+        names.sort();
+
+        let mut packages = Vec::new();
+        let mut kept = HashSet::new();
+        for name in names {
+            let locked = &lock.packages[name];
+            let status = match (&locked.source, &locked.checksum) {
+                (PackageSource::Path(_), _) => VendorStatus::Skipped("local path"),
+                (PackageSource::Registry(_), _) if !from_mage_registry(&locked.source) => {
+                    VendorStatus::Skipped("system package")
+                }
+                (_, None) => VendorStatus::Skipped("no checksum in mage.lock"),
+                (_, Some(checksum)) => {
+                    kept.insert(checksum.clone());
+                    self.vendor_package(name, locked, checksum)?
+                }
+            };
+            packages.push(VendoredPackage {
+                name: name.clone(),
+                version: locked.version.clone(),
+                status,
+            });
+        }
+
+        let mut removed = 0;
+        for checksum in self.vendor.checksums()? {
+            if !kept.contains(&checksum) {
+                self.vendor.remove(&checksum);
+                removed += 1;
+            }
+        }
+        Ok(VendorReport { packages, removed })
+    }
+
+    fn vendor_package(
+        &self,
+        name: &str,
+        locked: &LockedPackage,
+        checksum: &str,
+    ) -> Result<VendorStatus, String> {
+        let PackageSource::Git { url, rev } = &locked.source else {
+            if self.vendor.file(checksum).is_some() {
+                return Ok(VendorStatus::AlreadyVendored);
+            }
+            let archive = self.fetch_archive(name, locked)?;
+            self.vendor.store_file(checksum, &archive)?;
+            return Ok(VendorStatus::Vendored);
+        };
+
+        if self.vendor.tree(checksum).is_some() {
+            return Ok(VendorStatus::AlreadyVendored);
+        }
+        if let Some(tree) = self.cached(checksum, PackageCache::tree) {
+            self.vendor.store_tree(checksum, &tree)?;
+            return Ok(VendorStatus::Vendored);
+        }
+        if self.offline {
+            return Err(self.not_cached(name));
+        }
+
+        let commit = rev
+            .as_deref()
+            .ok_or_else(|| format!("mage.lock has no commit for {}", name))?;
+        let staging = self
+            .project_root()
+            .join(".mage/cache")
+            .join(format!("vendor-{}", name));
+        let _ = fs::remove_dir_all(&staging);
+        let exported = export_git_tree(&self.git_mirror(url)?, commit, &staging)
+            .and_then(|_| hash_tree(&staging))
+            .and_then(|actual| {
+                if actual == checksum {
+                    self.vendor.store_tree(checksum, &staging)
+                } else {
+                    Err(format!(
+                        "Checksum mismatch for {}: mage.lock has {}, but {} hashes to {}",
+                        name, checksum, commit, actual
+                    ))
+                }
+            });
+        let _ = fs::remove_dir_all(&staging);
+        exported?;
+        Ok(VendorStatus::Vendored)
+    }
+
+    /// Pick the newest version of `name` that satisfies every requirement.
+    ///
+    /// Git sources discover versions from their tags, Path and Url sources
+    /// from the package's own `mage.toml` and mage registry packages from the
+    /// registry index, and git revisions are locked to a commit hash. A git
+    /// dependency whose version is not a constraint, such as `dev`, follows
+    /// the branch or revision of that name. System package managers pick
+    /// their own versions, so they are locked as [`SYSTEM_VERSION`], and a
+    /// Url package without a `mage.toml` version as [`UNVERSIONED`].
+    ///
+    /// A git tag, branch commit or registry version from `locked` is kept
+    /// while it satisfies every requirement, so re-resolving does not upgrade
+    /// packages behind the user's back.
+    fn resolve_version(
+        &self,
+        name: &str,
+        dep: &PackageDependency,
+        requirements: &[Requirement],
+        locked: Option<&LockedPackage>,
+    ) -> Result<Resolution, String> {
+        let registry_index = match &dep.source {
+            PackageSource::Registry(manager) => self.registry_index(name, manager)?,
+            _ => None,
+        };
+        let satisfies_all = |version: &semver::Version| {
+            requirements
+                .iter()
+                .all(|req| parse_constraint(&req.constraint).is_ok_and(|c| c.matches(version)))
+        };
+
+        let candidates: Vec<(semver::Version, PackageSource)> = match &dep.source {
+            // A pinned revision is used as-is, down to the commit it names
+            PackageSource::Git {
+                url,
+                rev: Some(rev),
+            } => {
+                return Ok(Resolution {
+                    version: dep.version.clone(),
+                    source: PackageSource::Git {
+                        url: url.clone(),
+                        rev: Some(self.git_commit(url, rev)?),
+                    },
+                    requested_rev: Some(rev.clone()),
+                });
+            }
+            PackageSource::Git { url, rev: None } if let Some(branch) = git_branch(dep) => {
+                let source = match locked {
+                    Some(locked)
+                        if locked.version == branch
+                            && matches!(&locked.source, PackageSource::Git {
+                                url: locked_url,
+                                rev: Some(_),
+                            } if locked_url == url) =>
+                    {
+                        locked.source.clone()
+                    }
+                    _ => PackageSource::Git {
+                        url: url.clone(),
+                        rev: Some(self.git_commit(url, branch)?),
+                    },
+                };
+                return Ok(Resolution {
+                    version: branch.to_string(),
+                    source,
+                    requested_rev: None,
+                });
+            }
+            PackageSource::Git { url, rev: None }
+                if let Some(locked) = locked
+                    && let PackageSource::Git {
+                        url: locked_url,
+                        rev: Some(_),
+                    } = &locked.source
+                    && locked_url == url
+                    && let Some(version) = parse_version(&locked.version)
+                    && satisfies_all(&version) =>
+            {
+                return Ok(Resolution {
+                    version: locked.version.clone(),
+                    source: locked.source.clone(),
+                    requested_rev: None,
+                });
+            }
+            PackageSource::Git { url, rev: None } => git_tag_versions(url)?
+                .into_iter()
+                .map(|(version, tag)| {
+                    let source = PackageSource::Git {
+                        url: url.clone(),
+                        rev: Some(tag),
+                    };
+                    (version, source)
+                })
+                .collect(),
+            PackageSource::Path(path) => {
+                let version = self.path_package_version(path)?;
+                vec![(version, dep.source.clone())]
+            }
+            PackageSource::Registry(_)
+                if let Some(index) = &registry_index
+                    && let Some(locked) = locked
+                    && from_mage_registry(&locked.source)
+                    && index.versions.iter().any(|e| e.version == locked.version)
+                    && let Some(version) = parse_version(&locked.version)
+                    && satisfies_all(&version) =>
+            {
+                return Ok(Resolution {
+                    version: locked.version.clone(),
+                    source: locked.source.clone(),
+                    requested_rev: None,
+                });
+            }
+            PackageSource::Registry(_) if let Some(index) = &registry_index => index
+                .versions
+                .iter()
+                .filter_map(|entry| parse_version(&entry.version))
+                .map(|version| (version, PackageSource::Registry(MAGE_REGISTRY.to_string())))
+                .collect(),
+            PackageSource::Registry(_) => {
+                return Ok(Resolution {
+                    version: SYSTEM_VERSION.to_string(),
+                    source: dep.source.clone(),
+                    requested_rev: None,
+                });
+            }
+            PackageSource::Url(_) => {
+                let manifest_path = self
+                    .unpacked_package(name, &dep.version, &dep.source, locked)?
+                    .join("mage.toml");
+                if !manifest_path.exists() {
+                    return Ok(Resolution {
+                        version: UNVERSIONED.to_string(),
+                        source: dep.source.clone(),
+                        requested_rev: None,
+                    });
+                }
+                vec![(manifest_version(&manifest_path)?, dep.source.clone())]
+            }
+        };
+
+        let constraints = requirements
+            .iter()
+            .map(|req| {
+                parse_constraint(&req.constraint)
+                    .map_err(|e| format!("{} ({})", e, req.describe(name)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (version, source) = candidates
+            .iter()
+            .filter(|(version, _)| constraints.iter().all(|c| c.matches(version)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .ok_or_else(|| {
+                let mut available: Vec<_> = candidates.iter().map(|(v, _)| v).collect();
+                available.sort();
+                available.dedup();
+                let available: Vec<String> = available.iter().map(|v| v.to_string()).collect();
+                let mut message = format!(
+                    "Version conflict for '{}': no version satisfies every requirement",
+                    name
+                );
+                for req in requirements {
+                    message.push_str(&format!("\n  {}", req.describe(name)));
+                }
+                message.push_str(&format!(
+                    "\n  available: {}",
+                    if available.is_empty() {
+                        "none".to_string()
+                    } else {
+                        available.join(", ")
+                    }
+                ));
+                message
+            })?;
+
+        // Lock the tag's commit, so moving the tag does not change the install
+        let source = match source {
+            PackageSource::Git {
+                url,
+                rev: Some(tag),
+            } => PackageSource::Git {
+                url: url.clone(),
+                rev: Some(self.git_commit(url, tag)?),
+            },
+            source => source.clone(),
+        };
+        Ok(Resolution {
+            version: version.to_string(),
+            source,
+            requested_rev: None,
+        })
+    }
 
-        // For now, just return the specified version
-        Ok(dep.version.clone())
+    /// The version declared in the `mage.toml` of a local package.
+    fn path_package_version(&self, path: &str) -> Result<semver::Version, String> {
+        manifest_version(&self.project_root().join(path).join("mage.toml"))
     }
 
+    /// A URL or mage registry package's archive, unpacked under
+    /// `.mage/cache/unpacked` so its `mage.toml` can be read and its files
+    /// hashed before it is installed. Each archive is fetched and unpacked
+    /// once per resolver.
+    ///
+    /// An earlier download of a URL is reused only while it has the checksum
+    /// `locked` records, so `mage pkg update` sees new content at the URL. A
+    /// registry archive must match the checksum in the registry index.
+    fn unpacked_package(
+        &self,
+        name: &str,
+        version: &str,
+        source: &PackageSource,
+        locked: Option<&LockedPackage>,
+    ) -> Result<PathBuf, String> {
+        let key = archive_key(name, version, source);
+        let dir = self
+            .project_root()
+            .join(".mage/cache/unpacked")
+            .join(format!("{:x}", md5::compute(&key)));
+        if self.unpacked.borrow().contains(&key) {
+            return Ok(dir);
+        }
+
+        let archive = self.download_path(&key);
+        let file_name = match source {
+            PackageSource::Url(url) => {
+                let checksum = locked
+                    .filter(|locked| same_source(&locked.source, source))
+                    .and_then(|locked| locked.checksum.as_deref());
+                refresh_download(url, &archive, checksum)?;
+                url_file_name(url, name)
+            }
+            _ => {
+                let expected = self.registry_entry(name, version)?.checksum;
+                let url = self.registry_archive(name, version)?;
+                refresh_download(&url, &archive, Some(&expected))?;
+                let actual = hash_file(&archive)?;
+                if actual != expected {
+                    let _ = fs::remove_file(&archive);
+                    return Err(format!(
+                        "Checksum mismatch for {} {}: the registry index has {}, but {} hashes to {}",
+                        name, version, expected, url, actual
+                    ));
+                }
+                name
+            }
+        };
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        unpack(&archive, &dir, file_name)?;
+        self.unpacked.borrow_mut().insert(key);
+        Ok(dir)
+    }
+
+    /// The SHA-256 digest recorded in `mage.lock` for a resolved source:
+    /// the downloaded archive for URL packages, the tree at the pinned
+    /// revision for git packages and the directory contents for path
+    /// packages. Mage registry packages use the archive checksum from the
+    /// registry index. Packages from a system package manager have no
+    /// checksum.
     fn calculate_checksum(
         &self,
         name: &str,
         version: &str,
-        _source: &PackageSource,
-    ) -> Result<String, String> {
-        // In a real implementation, you'd calculate actual checksums
-        // For now, return a placeholder
-        Ok(format!(
-            "sha256:{:x}",
-            md5::compute(format!("{}-{}", name, version))
-        ))
+        source: &PackageSource,
+    ) -> Result<Option<String>, String> {
+        match source {
+            PackageSource::Registry(manager) if manager == MAGE_REGISTRY => {
+                Ok(Some(self.registry_entry(name, version)?.checksum))
+            }
+            PackageSource::Registry(_) => Ok(None),
+            PackageSource::Git { url, rev } => {
+                let mirror = self.git_mirror(url)?;
+                git_tree_checksum(&mirror, rev.as_deref().unwrap_or("HEAD")).map(Some)
+            }
+            PackageSource::Path(path) => hash_tree(&self.project_root().join(path)).map(Some),
+            PackageSource::Url(_) => {
+                self.unpacked_package(name, version, source, None)?;
+                hash_file(&self.download_path(&archive_key(name, version, source))).map(Some)
+            }
+        }
+    }
+
+    /// The SHA-256 digest of a URL or mage registry package's unpacked
+    /// files, which installs and `mage verify` check the package against.
+    /// `None` for other sources, whose checksum already covers their files.
+    fn calculate_tree_checksum(
+        &self,
+        name: &str,
+        version: &str,
+        source: &PackageSource,
+    ) -> Result<Option<String>, String> {
+        if matches!(source, PackageSource::Url(_)) || from_mage_registry(source) {
+            hash_tree(&self.unpacked_package(name, version, source, None)?).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The digest of an installed package's files, to compare with its
+    /// [`installed_digest`]. `None` when the package is not installed or has
+    /// nothing to hash.
+    fn installed_checksum(
+        &self,
+        name: &str,
+        locked: &LockedPackage,
+    ) -> Result<Option<String>, String> {
+        let package_dir = self.packages_dir.join(name);
+        if !package_dir.exists() {
+            return Ok(None);
+        }
+        match &locked.source {
+            PackageSource::Registry(_) if !from_mage_registry(&locked.source) => Ok(None),
+            _ => hash_tree(&package_dir).map(Some),
+        }
+    }
+
+    /// Re-hash every installed package and compare it with `mage.lock`.
+    pub fn verify_installed(&self) -> Result<Vec<IntegrityCheck>, String> {
+        let lock = self.read_lock()?;
+        let mut names: Vec<&String> = lock.packages.keys().collect();
+        names.sort();
+
+        let mut checks = Vec::new();
+        for name in names {
+            let locked = &lock.packages[name];
+            let status = match installed_digest(locked) {
+                None => IntegrityStatus::Unchecked,
+                Some(expected) => match self.installed_checksum(name, locked)? {
+                    None => IntegrityStatus::NotInstalled,
+                    Some(actual) if &actual == expected => IntegrityStatus::Verified,
+                    Some(actual) => IntegrityStatus::Mismatch {
+                        expected: expected.clone(),
+                        actual,
+                    },
+                },
+            };
+            checks.push(IntegrityCheck {
+                name: name.clone(),
+                status,
+            });
+        }
+        Ok(checks)
     }
 
     pub fn read_manifest(&self) -> Result<PackageManifest, String> {
         let content = fs::read_to_string(&self.manifest_path)
             .map_err(|e| format!("Failed to read manifest: {}", e))?;
 
-        toml::from_str(&content).map_err(|e| format!("Failed to parse manifest: {}", e))
+        let manifest: PackageManifest =
+            toml::from_str(&content).map_err(|e| format!("Failed to parse manifest: {}", e))?;
+        for (name, dependency) in manifest
+            .dependencies
+            .iter()
+            .chain(&manifest.dev_dependencies)
+        {
+            validate_package_name(name)
+                .map_err(|e| format!("{} in {}", e, self.manifest_path.display()))?;
+            validate_source(&dependency.source)
+                .map_err(|e| format!("{} for {} in {}", e, name, self.manifest_path.display()))?;
+        }
+        Ok(manifest)
     }
 
     fn write_manifest(&self, manifest: &PackageManifest) -> Result<(), String> {
@@ -450,7 +1764,17 @@ impl PackageResolver {
         let content = fs::read_to_string(&self.lock_path)
             .map_err(|e| format!("Failed to read lock file: {}", e))?;
 
-        toml::from_str(&content).map_err(|e| format!("Failed to parse lock file: {}", e))
+        let lock: PackageLock =
+            toml::from_str(&content).map_err(|e| format!("Failed to parse lock file: {}", e))?;
+        for (name, locked) in &lock.packages {
+            for name in std::iter::once(name).chain(&locked.dependencies) {
+                validate_package_name(name)
+                    .map_err(|e| format!("{} in {}", e, self.lock_path.display()))?;
+            }
+            validate_source(&locked.source)
+                .map_err(|e| format!("{} for {} in {}", e, name, self.lock_path.display()))?;
+        }
+        Ok(lock)
     }
 
     fn write_lock(&self, lock: &PackageLock) -> Result<(), String> {
@@ -493,3 +1817,717 @@ impl PackageResolver {
         Ok(())
     }
 }
+
+/// Reject git URLs, revisions and download URLs that start with `-`, which
+/// `git` would read as options, such as `--upload-pack=<command>`.
+pub fn validate_source(source: &PackageSource) -> Result<(), String> {
+    let (kind, value) = match source {
+        PackageSource::Git { url, .. } if url.starts_with('-') => ("git URL", url),
+        PackageSource::Git { rev: Some(rev), .. } if rev.starts_with('-') => ("git revision", rev),
+        PackageSource::Url(url) if url.starts_with('-') => ("URL", url),
+        _ => return Ok(()),
+    };
+    Err(format!(
+        "Invalid {} '{}': it cannot start with '-'",
+        kind, value
+    ))
+}
+
+/// Check that `name` can name a package. Package names come from manifests,
+/// lock files and registry indexes and become directories under
+/// `.mage/packages` and the registry, so they cannot contain path separators
+/// or be `.` or `..`.
+pub fn validate_package_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\', ':', '\0'])
+        && !Path::new(name).is_absolute();
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid package name '{}': names cannot contain path separators or be '.' or '..'",
+            name
+        ))
+    }
+}
+
+/// The branch or revision a git dependency without a `rev` follows when its
+/// version is not a constraint, as in `version = "dev"`.
+fn git_branch(dep: &PackageDependency) -> Option<&str> {
+    match &dep.source {
+        PackageSource::Git { rev: None, .. } if parse_constraint(&dep.version).is_err() => {
+            Some(dep.version.trim())
+        }
+        _ => None,
+    }
+}
+
+/// Parse a version constraint such as `^1.2`, `~1.2.3`, `>=1, <2` or `*`.
+/// `latest` and an empty constraint accept any version.
+fn parse_constraint(text: &str) -> Result<semver::VersionReq, String> {
+    match text.trim() {
+        "" | "latest" => Ok(semver::VersionReq::STAR),
+        constraint => semver::VersionReq::parse(constraint).map_err(|e| {
+            format!(
+                "Invalid version constraint '{}': {}. Versions are semver constraints \
+                 such as \"^1.2\" or \"*\"; only git dependencies may name a branch or \
+                 revision instead",
+                constraint, e
+            )
+        }),
+    }
+}
+
+/// Parse a version, accepting a leading `v` and a missing minor or patch
+/// number as found in git tags (`v1.2` is 1.2.0).
+fn parse_version(text: &str) -> Option<semver::Version> {
+    let text = text.trim();
+    let text = text.strip_prefix('v').unwrap_or(text);
+    if let Ok(version) = semver::Version::parse(text) {
+        return Some(version);
+    }
+    let parts: Vec<&str> = text.split('.').collect();
+    if parts.is_empty() || parts.len() > 3 || parts.iter().any(|p| p.parse::<u64>().is_err()) {
+        return None;
+    }
+    let number = |i: usize| parts.get(i).map_or(Ok(0), |p| p.parse::<u64>()).ok();
+    Some(semver::Version::new(number(0)?, number(1)?, number(2)?))
+}
+
+/// The version declared in the `mage.toml` at `manifest_path`.
+fn manifest_version(manifest_path: &Path) -> Result<semver::Version, String> {
+    let content = fs::read_to_string(manifest_path)
+        .map_err(|e| format!("Failed to read {}: {}", manifest_path.display(), e))?;
+    let table: toml::Table = toml::from_str(&content)
+        .map_err(|e| format!("Failed to parse {}: {}", manifest_path.display(), e))?;
+    let version = table
+        .get("version")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| format!("{} has no version", manifest_path.display()))?;
+    parse_version(version).ok_or_else(|| {
+        format!(
+            "Invalid version '{}' in {}",
+            version,
+            manifest_path.display()
+        )
+    })
+}
+
+/// The versions published as tags of a git repository, with their tag names.
+fn git_tag_versions(url: &str) -> Result<Vec<(semver::Version, String)>, String> {
+    let output = std::process::Command::new("git")
+        .args(["ls-remote", "--tags", "--refs", "--", url])
+        .output()
+        .map_err(|e| format!("Failed to list tags of {}: {}", url, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to list tags of {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1)?.strip_prefix("refs/tags/"))
+        .filter_map(|tag| parse_version(tag).map(|version| (version, tag.to_string())))
+        .collect())
+}
+
+/// Dependencies in name order, so resolution and its errors are repeatable.
+fn sorted_dependencies(
+    dependencies: &HashMap<String, PackageDependency>,
+) -> Vec<(&String, &PackageDependency)> {
+    let mut sorted: Vec<_> = dependencies.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+    sorted
+}
+
+/// Make a dependency declared by `parent` relative to the project: path
+/// dependencies of a local package are relative to that package.
+fn rebase_dependency(
+    parent: &str,
+    parent_source: &PackageSource,
+    name: &str,
+    dep: &PackageDependency,
+) -> Result<PackageDependency, String> {
+    let PackageSource::Path(path) = &dep.source else {
+        return Ok(dep.clone());
+    };
+    let PackageSource::Path(parent_path) = parent_source else {
+        return Err(format!(
+            "'{}' has a path dependency on '{}', which only works for local packages",
+            parent, name
+        ));
+    };
+
+    let joined = Path::new(parent_path).join(path);
+    let mut parts: Vec<&str> = Vec::new();
+    for part in joined.iter() {
+        match part.to_str().unwrap_or_default() {
+            "." => {}
+            ".." if parts.last().is_some_and(|last| *last != "..") => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    let mut rebased = dep.clone();
+    rebased.source = PackageSource::Path(parts.join("/"));
+    Ok(rebased)
+}
+
+/// Whether two declarations refer to the same package. Git revisions are not
+/// compared, since the resolver picks the revision. `auto` matches the mage
+/// registry, since that is where `auto` resolves published packages.
+fn same_source(a: &PackageSource, b: &PackageSource) -> bool {
+    match (a, b) {
+        (PackageSource::Registry(a), PackageSource::Registry(b)) => {
+            let mut managers = [a.as_str(), b.as_str()];
+            managers.sort_unstable();
+            a == b || managers == ["auto", MAGE_REGISTRY]
+        }
+        (PackageSource::Git { url: a, .. }, PackageSource::Git { url: b, .. }) => a == b,
+        (PackageSource::Path(a), PackageSource::Path(b)) => a == b,
+        (PackageSource::Url(a), PackageSource::Url(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Whether a resolved source is a package from the mage registry.
+fn from_mage_registry(source: &PackageSource) -> bool {
+    matches!(source, PackageSource::Registry(manager) if manager == MAGE_REGISTRY)
+}
+
+fn describe_source(source: &PackageSource) -> String {
+    match source {
+        PackageSource::Registry(manager) => format!("registry '{}'", manager),
+        PackageSource::Git { url, .. } => format!("git {}", url),
+        PackageSource::Path(path) => format!("path {}", path),
+        PackageSource::Url(url) => format!("url {}", url),
+    }
+}
+
+/// The first dependency cycle in the graph, as the names along it.
+fn find_cycle(roots: &[String], graph: &HashMap<String, ResolvedPackage>) -> Option<Vec<String>> {
+    fn visit(
+        name: &str,
+        graph: &HashMap<String, ResolvedPackage>,
+        path: &mut Vec<String>,
+        done: &mut HashSet<String>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = path.iter().position(|visited| visited == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.to_string());
+            return Some(cycle);
+        }
+        if done.contains(name) {
+            return None;
+        }
+
+        path.push(name.to_string());
+        for dependency in graph.get(name).map_or(&[][..], |p| &p.dependencies) {
+            if let Some(cycle) = visit(dependency, graph, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.insert(name.to_string());
+        None
+    }
+
+    let mut done = HashSet::new();
+    roots
+        .iter()
+        .find_map(|root| visit(root, graph, &mut Vec::new(), &mut done))
+}
+
+/// The `sha256:` digest of a file's contents.
+pub(crate) fn hash_file(path: &Path) -> Result<String, String> {
+    let content =
+        fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(format!("sha256:{:x}", Sha256::digest(&content)))
+}
+
+/// The `sha256:` digest of a directory: every file's relative path and the
+/// digest of its contents, in path order. Symlinks contribute their target,
+/// and `.git` and `.mage` at the top level are left out, so a git checkout
+/// hashes the same as the tree it was checked out from.
+pub(crate) fn hash_tree(root: &Path) -> Result<String, String> {
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    let walker = walkdir::WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() != 1 || !is_ignored_in_tree(entry.file_name()));
+    for entry in walker {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", root.display(), e))?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(root)
+            .unwrap_or(entry.path())
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let content = if entry.path_is_symlink() {
+            fs::read_link(entry.path()).map(|target| target.to_string_lossy().as_bytes().to_vec())
+        } else {
+            fs::read(entry.path())
+        }
+        .map_err(|e| format!("Failed to read {}: {}", entry.path().display(), e))?;
+        entries.push((relative, content));
+    }
+    Ok(digest_entries(entries))
+}
+
+/// Copy a directory into `dest`, leaving out `.git` and `.mage` at the top
+/// level like [`hash_tree`] does. Symlinks are copied as links.
+pub(crate) fn copy_tree(root: &Path, dest: &Path) -> Result<(), String> {
+    fs::create_dir_all(dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+    let walker = walkdir::WalkDir::new(root)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.depth() != 1 || !is_ignored_in_tree(entry.file_name()));
+    for entry in walker {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", root.display(), e))?;
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let target = dest.join(relative);
+        let copied = if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)
+        } else if entry.path_is_symlink() {
+            fs::read_link(entry.path()).and_then(|link| make_symlink(&link, &target))
+        } else {
+            fs::copy(entry.path(), &target).map(|_| ())
+        };
+        copied.map_err(|e| format!("Failed to copy {}: {}", entry.path().display(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn make_symlink(link: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(link, target)
+}
+
+/// Without symlinks, the link is written as a file holding its target,
+/// which [`hash_tree`] hashes the same way
+#[cfg(not(unix))]
+fn make_symlink(link: &Path, target: &Path) -> std::io::Result<()> {
+    fs::write(target, link.to_string_lossy().as_bytes())
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// A file in a git tree.
+struct GitFile {
+    path: String,
+    /// The git file mode, such as `100644`, `100755` or `120000` for symlinks
+    mode: String,
+    /// The file contents, or a symlink's target
+    content: Vec<u8>,
+}
+
+/// The files of the tree at `rev`, read from the blobs `git ls-tree` lists
+/// without checking anything out. The blobs are read as committed, like a
+/// clone checks them out, so attributes such as `export-ignore` that only
+/// apply to `git archive` make no difference. `.git` and `.mage` at the top
+/// level are left out, as in [`hash_tree`].
+fn git_tree_files(mirror: &Path, rev: &str) -> Result<Vec<GitFile>, String> {
+    use std::io::{BufRead, Read, Write};
+    use std::process::{Command, Stdio};
+
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(mirror)
+        .args([
+            "ls-tree",
+            "-r",
+            "-z",
+            "--full-tree",
+            "--end-of-options",
+            rev,
+        ])
+        .output()
+        .map_err(|e| format!("Failed to list {}: {}", rev, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to list {}: {}",
+            rev,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // Each record is `<mode> <type> <object>\t<path>`; submodules are
+    // `commit` entries, which a clone leaves as empty directories
+    let mut blobs = Vec::new();
+    for record in output.stdout.split(|&byte| byte == 0) {
+        let record = String::from_utf8_lossy(record);
+        let Some((info, path)) = record.split_once('\t') else {
+            continue;
+        };
+        let mut fields = info.split(' ');
+        if let (Some(mode), Some("blob"), Some(object)) =
+            (fields.next(), fields.next(), fields.next())
+        {
+            let ignored = path
+                .split('/')
+                .next()
+                .is_some_and(|first| is_ignored_in_tree(std::ffi::OsStr::new(first)));
+            if !ignored {
+                blobs.push((path.to_string(), mode.to_string(), object.to_string()));
+            }
+        }
+    }
+
+    let mut cat_file = Command::new("git")
+        .arg("--git-dir")
+        .arg(mirror)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to read {}: {}", rev, e))?;
+    let mut stdin = cat_file.stdin.take().expect("stdin is piped");
+    let objects: String = blobs
+        .iter()
+        .map(|(_, _, object)| format!("{}\n", object))
+        .collect();
+    let writer = std::thread::spawn(move || stdin.write_all(objects.as_bytes()));
+
+    let mut stdout = std::io::BufReader::new(cat_file.stdout.take().expect("stdout is piped"));
+    let mut files = Vec::new();
+    for (path, mode, _) in blobs {
+        // `<object> blob <size>\n<content>\n`
+        let mut header = String::new();
+        stdout
+            .read_line(&mut header)
+            .map_err(|e| format!("Failed to read {}: {}", rev, e))?;
+        let size = header
+            .split_whitespace()
+            .nth(2)
+            .and_then(|size| size.parse::<usize>().ok())
+            .ok_or_else(|| format!("Failed to read {} in {}: {}", path, rev, header.trim()))?;
+        let mut content = vec![0; size + 1];
+        stdout
+            .read_exact(&mut content)
+            .map_err(|e| format!("Failed to read {} in {}: {}", path, rev, e))?;
+        content.truncate(size);
+        files.push(GitFile {
+            path,
+            mode,
+            content,
+        });
+    }
+    let _ = writer.join();
+    let _ = cat_file.wait();
+    Ok(files)
+}
+
+/// Write the tree at `rev` into `dest` without a checkout. The result
+/// hashes to [`git_tree_checksum`].
+fn export_git_tree(mirror: &Path, rev: &str, dest: &Path) -> Result<(), String> {
+    for file in git_tree_files(mirror, rev)? {
+        let target = dest.join(&file.path);
+        let written = target
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| match file.mode.as_str() {
+                "120000" => {
+                    make_symlink(Path::new(&*String::from_utf8_lossy(&file.content)), &target)
+                }
+                "100755" => fs::write(&target, &file.content).and_then(|_| set_executable(&target)),
+                _ => fs::write(&target, &file.content),
+            });
+        written.map_err(|e| format!("Failed to write {}: {}", target.display(), e))?;
+    }
+    Ok(())
+}
+
+/// The digest [`hash_tree`] would give a checkout of `rev`, computed from
+/// [`git_tree_files`].
+fn git_tree_checksum(mirror: &Path, rev: &str) -> Result<String, String> {
+    let entries = git_tree_files(mirror, rev)?
+        .into_iter()
+        .map(|file| (file.path, file.content))
+        .collect();
+    Ok(digest_entries(entries))
+}
+
+pub(crate) fn is_ignored_in_tree(name: &std::ffi::OsStr) -> bool {
+    name == ".git" || name == ".mage"
+}
+
+fn digest_entries(mut entries: Vec<(String, Vec<u8>)>) -> String {
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut hasher = Sha256::new();
+    for (path, content) in &entries {
+        hasher.update(path.as_bytes());
+        hasher.update([0]);
+        hasher.update(Sha256::digest(content));
+    }
+    format!("sha256:{:x}", hasher.finalize())
+}
+
+/// Why `lock` no longer matches `manifest`, if it does not: a dependency is
+/// missing from the lock, has a different source, or is locked to a version
+/// its constraint rejects, or the lock holds packages nothing depends on.
+fn lock_mismatch(manifest: &PackageManifest, lock: &PackageLock) -> Option<String> {
+    // Locks written before tree checksums cannot verify unpacked archives
+    let mut names: Vec<&String> = lock.packages.keys().collect();
+    names.sort();
+    if let Some(name) = names.into_iter().find(|name| {
+        let locked = &lock.packages[*name];
+        locked.checksum.is_some() && installed_digest(locked).is_none()
+    }) {
+        return Some(format!("'{}' has no checksum of its unpacked files", name));
+    }
+
+    let direct = sorted_dependencies(&manifest.dependencies)
+        .into_iter()
+        .chain(sorted_dependencies(&manifest.dev_dependencies))
+        .filter(|(_, dep)| {
+            dep.platform
+                .as_ref()
+                .is_none_or(|platform| platform == std::env::consts::OS)
+        });
+
+    let mut roots = Vec::new();
+    for (name, dep) in direct {
+        let Some(locked) = lock.packages.get(name) else {
+            return Some(format!("'{}' is not locked", name));
+        };
+        if !same_source(&dep.source, &locked.source) {
+            return Some(format!(
+                "'{}' is locked from {} but mage.toml uses {}",
+                name,
+                describe_source(&locked.source),
+                describe_source(&dep.source)
+            ));
+        }
+
+        let matches_constraint = || match (
+            parse_constraint(&dep.version),
+            parse_version(&locked.version),
+        ) {
+            (Ok(constraint), Some(version)) => constraint.matches(&version),
+            _ => false,
+        };
+        // The lock must have pinned the revision mage.toml asks for
+        if let (PackageSource::Git { rev: Some(rev), .. }, PackageSource::Git { rev: pinned, .. }) =
+            (&dep.source, &locked.source)
+            && locked.requested_rev.as_ref() != Some(rev)
+            && pinned.as_ref() != Some(rev)
+        {
+            return Some(format!(
+                "'{}' is locked at revision {}, but mage.toml asks for {}",
+                name,
+                locked
+                    .requested_rev
+                    .as_deref()
+                    .or(pinned.as_deref())
+                    .unwrap_or("HEAD"),
+                rev
+            ));
+        }
+
+        let satisfied = match (&dep.source, &locked.source) {
+            (PackageSource::Git { rev: Some(_), .. }, _) => true,
+            (PackageSource::Git { .. }, _) if let Some(branch) = git_branch(dep) => {
+                locked.version == branch
+            }
+            (PackageSource::Url(_), _) if locked.version == UNVERSIONED => true,
+            (PackageSource::Git { .. } | PackageSource::Path(_) | PackageSource::Url(_), _) => {
+                matches_constraint()
+            }
+            (_, source) if from_mage_registry(source) => matches_constraint(),
+            _ => locked.version == SYSTEM_VERSION,
+        };
+        if !satisfied {
+            return Some(format!(
+                "'{}' is locked at {}, which does not satisfy {}",
+                name, locked.version, dep.version
+            ));
+        }
+        roots.push(name.clone());
+    }
+
+    let mut reachable: HashSet<&String> = HashSet::new();
+    let mut pending: Vec<&String> = roots.iter().collect();
+    while let Some(name) = pending.pop() {
+        if reachable.insert(name)
+            && let Some(locked) = lock.packages.get(name)
+        {
+            pending.extend(locked.dependencies.iter());
+        }
+    }
+    let mut unused: Vec<&String> = lock
+        .packages
+        .keys()
+        .filter(|name| !reachable.contains(name))
+        .collect();
+    unused.sort();
+    unused
+        .first()
+        .map(|name| format!("'{}' is locked but no longer required", name))
+}
+
+/// Download `url` to `path` with `curl`. Local paths and `file://` URLs, as
+/// used by registries in a local directory, are copied.
+fn download(url: &str, path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create download cache: {}", e))?;
+    }
+    let local = url
+        .strip_prefix("file://")
+        .or((!url.contains("://")).then_some(url));
+    if let Some(local) = local {
+        return fs::copy(local, path)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to copy package from {}: {}", local, e));
+    }
+    let output = std::process::Command::new("curl")
+        .args(["-fsSL", "-o"])
+        .arg(path)
+        .args(["--", url])
+        .output()
+        .map_err(|e| format!("Failed to run curl for {}: {}", url, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to download package from {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// The checksum an installed package's files are verified against: the tree
+/// checksum of URL and mage registry packages, whose `checksum` is that of
+/// their archive, and the checksum of anything else.
+fn installed_digest(locked: &LockedPackage) -> Option<&String> {
+    if matches!(locked.source, PackageSource::Url(_)) || from_mage_registry(&locked.source) {
+        locked.tree_checksum.as_ref()
+    } else {
+        locked.checksum.as_ref()
+    }
+}
+
+/// The name a URL or mage registry package's archive is downloaded and
+/// unpacked under.
+fn archive_key(name: &str, version: &str, source: &PackageSource) -> String {
+    match source {
+        PackageSource::Url(url) => url.clone(),
+        _ => format!("{}:{}@{}", MAGE_REGISTRY, name, version),
+    }
+}
+
+/// Download `url` to `path`, unless `path` already holds a file with
+/// `checksum`. The previous copy is kept until the download completes.
+fn refresh_download(url: &str, path: &Path, checksum: Option<&str>) -> Result<(), String> {
+    if let Some(checksum) = checksum
+        && path.is_file()
+        && hash_file(path).is_ok_and(|actual| actual == checksum)
+    {
+        return Ok(());
+    }
+    let fresh = path.with_extension("new");
+    download(url, &fresh)?;
+    fs::rename(&fresh, path).map_err(|e| format!("Failed to store download of {}: {}", url, e))
+}
+
+/// Unpack `archive` into `dest`. An archive whose entries all sit in one
+/// top-level directory is unpacked without it; anything that is not an
+/// archive is copied in as a single file named `file_name`.
+fn unpack(archive: &Path, dest: &Path, file_name: &str) -> Result<(), String> {
+    if crate::archive::ArchiveFormat::detect(archive)?.is_some() {
+        let entries = crate::archive::archive_entries(archive)?;
+        let strip = usize::from(has_single_root(&entries));
+        crate::archive::extract_archive(archive, dest, strip).map(|_| ())
+    } else {
+        fs::copy(archive, dest.join(file_name))
+            .map(|_| ())
+            .map_err(|e| format!("Failed to copy {}: {}", archive.display(), e))
+    }
+}
+
+/// The file name a URL's download is saved under when it is not an
+/// archive: its last path segment, or `fallback`.
+fn url_file_name<'a>(url: &'a str, fallback: &'a str) -> &'a str {
+    url.rsplit('/')
+        .next()
+        .filter(|segment| !segment.is_empty())
+        .unwrap_or(fallback)
+}
+
+/// Whether every archive entry lives under the same top-level directory, as
+/// in release tarballs that unpack to `tool-1.2.0/`.
+fn has_single_root(entries: &[String]) -> bool {
+    let mut root: Option<&str> = None;
+    let mut nested = false;
+    for entry in entries {
+        let mut parts = entry
+            .split('/')
+            .filter(|part| !part.is_empty() && *part != ".");
+        let Some(first) = parts.next() else {
+            continue;
+        };
+        if root.is_some_and(|root| root != first) {
+            return false;
+        }
+        root = Some(first);
+        nested |= parts.next().is_some();
+    }
+    nested
+}
+
+/// Whether two sources point at the same revision.
+fn same_revision(a: &PackageSource, b: &PackageSource) -> bool {
+    match (a, b) {
+        (PackageSource::Git { rev: a, .. }, PackageSource::Git { rev: b, .. }) => a == b,
+        _ => same_source(a, b),
+    }
+}
+
+fn render_tree(
+    names: &[String],
+    prefix: &str,
+    lock: &PackageLock,
+    shown: &mut HashSet<String>,
+    out: &mut String,
+) {
+    for (index, name) in names.iter().enumerate() {
+        let Some(package) = lock.packages.get(name) else {
+            continue;
+        };
+        let last = index + 1 == names.len();
+        let repeated = !shown.insert(name.clone()) && !package.dependencies.is_empty();
+        out.push_str(&format!(
+            "{}{}{} {}{}\n",
+            prefix,
+            if last { "└── " } else { "├── " },
+            name,
+            package.version,
+            if repeated { " (*)" } else { "" }
+        ));
+        if !repeated {
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            render_tree(&package.dependencies, &prefix, lock, shown, out);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Mage package registries.
+//!
+//! A registry is a directory, or a static web server hosting one, with an
+//! index file per package and the published archives:
+//!
+//! ```text
+//! index/<name>.toml
+//! packages/<name>/<name>-<version>.tar.gz
+//! ```
+//!
+//! Each index file lists the published versions of a package with the
+//! archive's location, its SHA-256 checksum and the package's dependencies,
+//! so versions can be resolved without downloading anything.
+
+use crate::package::{
+    PackageDependency, PackageResolver, PackageSource, copy_tree, hash_file, validate_package_name,
+    validate_source,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The `PackageSource::Registry` manager name of packages from a mage registry.
+pub const MAGE_REGISTRY: &str = "mage";
+
+#[derive(Debug, Clone)]
+pub struct Registry {
+    location: String,
+}
+
+/// The published versions of one package.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RegistryIndex {
+    #[serde(default)]
+    pub versions: Vec<RegistryEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub version: String,
+    /// Archive location, relative to the registry root or as a full URL
+    pub archive: String,
+    pub checksum: String,
+    #[serde(default)]
+    pub dependencies: HashMap<String, PackageDependency>,
+    pub description: Option<String>,
+    pub published_at: String,
+}
+
+impl Registry {
+    /// A registry at a local directory or an `http(s)://` URL.
+    pub fn new(location: &str) -> Self {
+        Self {
+            location: location.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// The registry named by `MAGE_REGISTRY`, or by `registry=` in `.mageconfig`.
+    pub fn configured() -> Option<Self> {
+        std::env::var("MAGE_REGISTRY")
+            .ok()
+            .or_else(|| {
+                crate::config::MageConfig::find_config()
+                    .and_then(|config| config.options.get("registry").cloned())
+            })
+            .filter(|location| !location.trim().is_empty())
+            .map(|location| Self::new(location.trim()))
+    }
+
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+
+    fn is_remote(&self) -> bool {
+        self.location.contains("://")
+    }
+
+    /// The index of `name`, or `None` if it has never been published here.
+    pub fn index(&self, name: &str) -> Result<Option<RegistryIndex>, String> {
+        validate_package_name(name)?;
+        let relative = format!("index/{}.toml", name);
+        let content = if self.is_remote() {
+            match fetch_remote(&format!("{}/{}", self.location, relative))? {
+                Some(content) => content,
+                None => return Ok(None),
+            }
+        } else {
+            let path = Path::new(&self.location).join(&relative);
+            if !path.exists() {
+                return Ok(None);
+            }
+            fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        };
+
+        let index: RegistryIndex = toml::from_str(&content).map_err(|e| {
+            format!(
+                "Failed to parse the registry index for '{}' at {}: {}",
+                name, self.location, e
+            )
+        })?;
+        for entry in &index.versions {
+            for (dependency, requirement) in &entry.dependencies {
+                validate_package_name(dependency)
+                    .and_then(|_| validate_source(&requirement.source))
+                    .map_err(|e| {
+                        format!(
+                            "{} (required by {} {} in the registry at {})",
+                            e, name, entry.version, self.location
+                        )
+                    })?;
+            }
+        }
+        Ok(Some(index))
+    }
+
+    /// The index entry for `name` at exactly `version`.
+    pub fn entry(&self, name: &str, version: &str) -> Result<RegistryEntry, String> {
+        self.index(name)?
+            .and_then(|index| index.versions.into_iter().find(|e| e.version == version))
+            .ok_or_else(|| {
+                format!(
+                    "{} {} is not in the registry at {}",
+                    name, version, self.location
+                )
+            })
+    }
+
+    /// Where to download an entry's archive from: a URL or a local path.
+    pub fn archive_location(&self, entry: &RegistryEntry) -> String {
+        if entry.archive.contains("://") || Path::new(&entry.archive).is_absolute() {
+            entry.archive.clone()
+        } else if self.is_remote() {
+            format!("{}/{}", self.location, entry.archive)
+        } else {
+            Path::new(&self.location)
+                .join(&entry.archive)
+                .display()
+                .to_string()
+        }
+    }
+
+    /// Pack the project at `project_root` into `packages/<name>/` and add
+    /// its version to the index. The archive holds the project without
+    /// `.git` and `.mage`. Only local registries can be published to.
+    pub fn publish(&self, project_root: &Path) -> Result<RegistryEntry, String> {
+        if self.is_remote() {
+            return Err(format!(
+                "Cannot publish to {}: only local registry directories can be written",
+                self.location
+            ));
+        }
+
+        let manifest = PackageResolver::new(project_root).read_manifest()?;
+        let name = &manifest.name;
+        validate_package_name(name).map_err(|e| format!("Cannot publish: {}", e))?;
+        if semver::Version::parse(&manifest.version).is_err() {
+            return Err(format!(
+                "Cannot publish {}: version '{}' is not a semantic version",
+                name, manifest.version
+            ));
+        }
+        let mut dependency_names: Vec<&String> = manifest.dependencies.keys().collect();
+        dependency_names.sort();
+        for dependency in dependency_names {
+            if let PackageSource::Path(path) = &manifest.dependencies[dependency].source {
+                return Err(format!(
+                    "Cannot publish {}: dependency '{}' uses the local path {}",
+                    name, dependency, path
+                ));
+            }
+        }
+
+        let mut index = self.index(name)?.unwrap_or_default();
+        if index.versions.iter().any(|e| e.version == manifest.version) {
+            return Err(format!(
+                "{} {} is already published to {}",
+                name, manifest.version, self.location
+            ));
+        }
+
+        let archive = format!("packages/{}/{}-{}.tar.gz", name, name, manifest.version);
+        let archive_path = Path::new(&self.location).join(&archive);
+        if let Some(parent) = archive_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+
+        let staging =
+            std::env::temp_dir().join(format!("mage-publish-{}-{}", std::process::id(), name));
+        let package_dir = staging.join(format!("{}-{}", name, manifest.version));
+        let packed = copy_tree(project_root, &package_dir).and_then(|_| {
+            crate::archive::create_archive(&package_dir, &archive_path)?;
+            hash_file(&archive_path)
+        });
+        let _ = fs::remove_dir_all(&staging);
+        let checksum = packed?;
+
+        let entry = RegistryEntry {
+            version: manifest.version.clone(),
+            archive,
+            checksum,
+            dependencies: manifest.dependencies.clone(),
+            description: manifest.description.clone(),
+            published_at: chrono::Utc::now().to_rfc3339(),
+        };
+        index.versions.push(entry.clone());
+        index
+            .versions
+            .sort_by_key(|e| semver::Version::parse(&e.version).ok());
+
+        let index_path = Path::new(&self.location)
+            .join("index")
+            .join(format!("{}.toml", name));
+        if let Some(parent) = index_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let content = toml::to_string_pretty(&index)
+            .map_err(|e| format!("Failed to serialize registry index: {}", e))?;
+        fs::write(&index_path, content)
+            .map_err(|e| format!("Failed to write {}: {}", index_path.display(), e))?;
+
+        Ok(entry)
+    }
+}
+
+/// Fetch a file from a remote registry. `None` when the server answers with
+/// an HTTP error, such as a 404 for a package that was never published.
+fn fetch_remote(url: &str) -> Result<Option<String>, String> {
+    let output = std::process::Command::new("curl")
+        .args(["-fsSL", "--", url])
+        .output()
+        .map_err(|e| format!("Failed to run curl for {}: {}", url, e))?;
+    match output.status.code() {
+        Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned())),
+        // curl's exit code for HTTP responses of 400 and above
+        Some(22) => Ok(None),
+        _ => Err(format!(
+            "Failed to fetch {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
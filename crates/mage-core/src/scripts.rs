@@ -0,0 +1,207 @@
+//! Manifest scripts.
+//!
+//! The `[scripts]` table of `mage.toml` maps names to commands. Running a
+//! script also runs its `pre<name>` and `post<name>` hooks when they are
+//! defined. Entries of the form `mage <file>.mage` run in-process; anything
+//! else runs through the shell from the project root.
+
+use crate::RunOptions;
+use crate::interpreter::shell_command;
+use crate::package::PackageResolver;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// One command to run for a script: the script itself or one of its hooks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptStep {
+    pub name: String,
+    pub command: String,
+    /// Whether extra command line arguments are passed to this step
+    pub takes_args: bool,
+}
+
+/// The scripts defined in `mage.toml`, sorted by name.
+pub fn list_scripts(project_root: &Path) -> Result<BTreeMap<String, String>, String> {
+    let manifest = PackageResolver::new(project_root).read_manifest()?;
+    Ok(manifest.scripts.into_iter().collect())
+}
+
+/// The steps that running `name` performs, in order: `pre<name>`, `name` and
+/// `post<name>`.
+pub fn script_steps(project_root: &Path, name: &str) -> Result<Vec<ScriptStep>, String> {
+    let scripts = list_scripts(project_root)?;
+    let Some(command) = scripts.get(name) else {
+        let available = scripts.keys().cloned().collect::<Vec<_>>();
+        return Err(if available.is_empty() {
+            format!("Script '{}' is not defined: mage.toml has no scripts", name)
+        } else {
+            format!(
+                "Script '{}' is not defined in mage.toml (available: {})",
+                name,
+                available.join(", ")
+            )
+        });
+    };
+
+    let hook = |hook_name: String| {
+        scripts.get(&hook_name).map(|command| ScriptStep {
+            name: hook_name,
+            command: command.clone(),
+            takes_args: false,
+        })
+    };
+
+    let mut steps = Vec::new();
+    steps.extend(hook(format!("pre{}", name)));
+    steps.push(ScriptStep {
+        name: name.to_string(),
+        command: command.clone(),
+        takes_args: true,
+    });
+    steps.extend(hook(format!("post{}", name)));
+    Ok(steps)
+}
+
+/// Run a single step from the project root. `args` are appended to the
+/// step's own arguments when the step takes them.
+pub fn run_step(
+    project_root: &Path,
+    step: &ScriptStep,
+    args: &[String],
+    cli_shell: Option<&str>,
+    options: &RunOptions,
+) -> Result<(), String> {
+    let extra: &[String] = if step.takes_args { args } else { &[] };
+
+    if let Some((file, mut script_args)) = mage_invocation(&step.command) {
+        let path = project_root.join(&file);
+        let source = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        script_args.extend(extra.iter().cloned());
+        let options = RunOptions {
+            args: script_args,
+            ..options.clone()
+        };
+        return crate::run_with_options(&source, cli_shell, &options)
+            .map_err(|e| format!("Script '{}' failed: {}", step.name, e));
+    }
+
+    let mut command = step.command.clone();
+    for arg in extra {
+        command.push(' ');
+        command.push_str(&shell_quote(arg));
+    }
+    if options.dry_run {
+        println!("🔮 Would run: {}", command);
+        return Ok(());
+    }
+
+    let status = shell_command(&command, cli_shell)
+        .current_dir(project_root)
+        .status()
+        .map_err(|e| format!("Failed to run script '{}': {}", step.name, e))?;
+    if !status.success() {
+        return Err(format!(
+            "Script '{}' failed with exit code {}",
+            step.name,
+            status.code().unwrap_or(1)
+        ));
+    }
+    Ok(())
+}
+
+/// Split `mage [run] <file>.mage [args...]` into the script file and its
+/// arguments. Other commands return `None` and are run through the shell.
+pub fn mage_invocation(command: &str) -> Option<(String, Vec<String>)> {
+    let mut words = split_words(command)?.into_iter();
+    if words.next()? != "mage" {
+        return None;
+    }
+    let mut file = words.next()?;
+    if file == "run" {
+        file = words.next()?;
+    }
+    if !file.ends_with(".mage") {
+        return None;
+    }
+    Some((file, words.collect()))
+}
+
+/// Split a command line into words, honouring single and double quotes and
+/// backslash escapes. Returns `None` for commands that use other shell syntax
+/// (pipes, redirects, variables, command separators) or have unbalanced quotes.
+pub fn split_words(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => word.push(chars.next()?),
+                        '$' | '`' => return None,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                word.push(chars.next()?);
+            }
+            '|' | '&' | ';' | '<' | '>' | '$' | '`' | '(' | ')' => return None,
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Some(words)
+}
+
+/// Quote `arg` so the shell passes it to the command as a single word.
+#[cfg(target_family = "windows")]
+pub fn shell_quote(arg: &str) -> String {
+    if arg.is_empty() || arg.contains([' ', '\t', '"']) {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
+
+/// Quote `arg` so the shell passes it to the command as a single word.
+#[cfg(not(target_family = "windows"))]
+pub fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
@@ -0,0 +1,506 @@
+//! Make-style tasks.
+//!
+//! A task file declares tasks with `ritual`:
+//!
+//! ```text
+//! ritual build needs ["assets"] reads ["src/**/*.rs"] makes ["target/app"] {
+//!     evoke "cargo build"
+//! }
+//! ```
+//!
+//! Loading the file runs its top-level statements and collects the tasks.
+//! [`TaskFile::run`] runs the requested tasks after their dependencies. It
+//! skips tasks whose outputs are newer than their inputs and runs
+//! independent tasks in parallel.
+
+use crate::interpreter::{ExprValue, define_functions, interpret};
+use crate::output::OutputCollector;
+use crate::{MageParser, Rule, RunOptions};
+use pest::Parser;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::SystemTime;
+
+/// A task declared with `ritual`.
+#[derive(Debug, Clone, Default)]
+pub struct Task {
+    pub name: String,
+    /// Tasks that must finish before this one starts
+    pub needs: Vec<String>,
+    /// Input files or globs
+    pub reads: Vec<String>,
+    /// Output paths
+    pub makes: Vec<String>,
+    /// Source of the task body, without its braces
+    pub(crate) body: String,
+}
+
+/// What happened to a task during [`TaskFile::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskStatus {
+    Ran,
+    UpToDate,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskOutcome {
+    pub name: String,
+    pub status: TaskStatus,
+}
+
+impl Task {
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether every output exists and is at least as new as every input,
+    /// with relative paths resolved against `root`. Tasks without outputs,
+    /// and tasks with a `reads` entry that matches no files, are never up to
+    /// date.
+    pub fn is_up_to_date(&self, root: &Path) -> Result<bool, String> {
+        if self.makes.is_empty() {
+            return Ok(false);
+        }
+
+        let mut oldest_output: Option<SystemTime> = None;
+        for output in &self.makes {
+            let output = root.join(output);
+            if !output.exists() {
+                return Ok(false);
+            }
+            let modified = modified(&output)?;
+            oldest_output = Some(oldest_output.map_or(modified, |t| t.min(modified)));
+        }
+
+        let mut newest_input: Option<SystemTime> = None;
+        for pattern in &self.reads {
+            let inputs = expand_glob(root, pattern)?;
+            if inputs.is_empty() {
+                return Ok(false);
+            }
+            for input in inputs {
+                let modified = modified(&input)?;
+                newest_input = Some(newest_input.map_or(modified, |t| t.max(modified)));
+            }
+        }
+
+        Ok(match (oldest_output, newest_input) {
+            (Some(output), Some(input)) => input <= output,
+            _ => true,
+        })
+    }
+}
+
+/// The tasks of a loaded task file, along with the variables its top-level
+/// statements defined.
+pub struct TaskFile {
+    source: String,
+    /// The directory relative `reads` and `makes` paths are resolved against
+    root: PathBuf,
+    shell: Option<String>,
+    scope: HashMap<String, ExprValue>,
+    tasks: Vec<Task>,
+}
+
+impl TaskFile {
+    /// Read and run the task file at `path`, collecting its tasks.
+    pub fn load(
+        path: &Path,
+        cli_shell: Option<&str>,
+        options: &RunOptions,
+    ) -> Result<Self, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let root = path.parent().unwrap_or(Path::new(""));
+        Self::from_source(source, root, cli_shell, options)
+    }
+
+    /// Run task file `source`, whose relative paths are relative to `root`.
+    pub fn from_source(
+        source: String,
+        root: &Path,
+        cli_shell: Option<&str>,
+        options: &RunOptions,
+    ) -> Result<Self, String> {
+        let shell = crate::resolve_shell(&source, cli_shell);
+        let mut scope = crate::script_scope(options);
+        let mut output = OutputCollector::direct()
+            .with_dry_run(options.dry_run)
+            .with_strict(options.strict);
+        {
+            let pairs = MageParser::parse(Rule::program, &source)
+                .map_err(|e| format!("Parse error: {}", e))?;
+            let mut functions = HashMap::new();
+            interpret(
+                pairs,
+                shell.as_deref(),
+                &mut scope,
+                &mut functions,
+                &mut output,
+            )
+            .map_err(|e| format!("{}", e))?;
+        }
+
+        Ok(Self {
+            tasks: output.take_tasks(),
+            source,
+            root: root.to_path_buf(),
+            shell,
+            scope,
+        })
+    }
+
+    /// Tasks in declaration order.
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    pub fn task(&self, name: &str) -> Option<&Task> {
+        self.tasks.iter().find(|t| t.name == name)
+    }
+
+    /// The tasks needed to run `targets`, each after the tasks it needs.
+    pub fn plan(&self, targets: &[String]) -> Result<Vec<&Task>, String> {
+        let mut order = Vec::new();
+        let mut done = HashSet::new();
+        for target in targets {
+            self.visit(target, &mut Vec::new(), &mut done, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit<'a>(
+        &'a self,
+        name: &str,
+        path: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+        order: &mut Vec<&'a Task>,
+    ) -> Result<(), String> {
+        if done.contains(name) {
+            return Ok(());
+        }
+        let Some(task) = self.task(name) else {
+            return Err(match path.last() {
+                Some(parent) => format!("Task '{}' needs unknown task '{}'", parent, name),
+                None => format!(
+                    "Task '{}' is not defined (available: {})",
+                    name,
+                    self.tasks
+                        .iter()
+                        .map(|t| t.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        };
+        if let Some(start) = path.iter().position(|n| *n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name);
+            return Err(format!("Task cycle detected: {}", cycle.join(" -> ")));
+        }
+
+        path.push(&task.name);
+        for dependency in &task.needs {
+            self.visit(dependency, path, done, order)?;
+        }
+        path.pop();
+
+        done.insert(&task.name);
+        order.push(task);
+        Ok(())
+    }
+
+    /// Run `targets` and the tasks they need, up to `jobs` at a time. A task
+    /// is skipped when it is up to date and none of the tasks it needs ran.
+    /// After a failure no new tasks start; tasks already running finish.
+    pub fn run(
+        &self,
+        targets: &[String],
+        jobs: usize,
+        options: &RunOptions,
+    ) -> Result<Vec<TaskOutcome>, String> {
+        let mut pending = self.plan(targets)?;
+        let mut finished: HashMap<&str, TaskStatus> = HashMap::new();
+        let mut outcomes = Vec::new();
+        let mut failure = None;
+        let mut running = 0;
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::scope(|scope| {
+            loop {
+                let mut index = 0;
+                while failure.is_none() && running < jobs.max(1) && index < pending.len() {
+                    let task = pending[index];
+                    if !task.needs.iter().all(|n| finished.contains_key(n.as_str())) {
+                        index += 1;
+                        continue;
+                    }
+                    pending.remove(index);
+
+                    let needs_ran = task
+                        .needs
+                        .iter()
+                        .any(|n| finished[n.as_str()] == TaskStatus::Ran);
+                    match task.is_up_to_date(&self.root) {
+                        Ok(true) if !needs_ran => {
+                            println!("⏭️  {} (up to date)", task.name);
+                            finished.insert(&task.name, TaskStatus::UpToDate);
+                            outcomes.push(TaskOutcome {
+                                name: task.name.clone(),
+                                status: TaskStatus::UpToDate,
+                            });
+                            continue;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            failure = Some(format!("Task '{}' failed: {}", task.name, e));
+                            break;
+                        }
+                    }
+
+                    println!("▶️  {}", task.name);
+                    running += 1;
+                    let sender = sender.clone();
+                    scope.spawn(move || {
+                        let _ = sender.send((task, self.run_task(task, options)));
+                    });
+                }
+
+                if running == 0 {
+                    break;
+                }
+                let Ok((task, result)) = receiver.recv() else {
+                    break;
+                };
+                running -= 1;
+                match result {
+                    Ok(()) => {
+                        println!("✅ {}", task.name);
+                        finished.insert(&task.name, TaskStatus::Ran);
+                        outcomes.push(TaskOutcome {
+                            name: task.name.clone(),
+                            status: TaskStatus::Ran,
+                        });
+                    }
+                    Err(e) => {
+                        failure.get_or_insert(format!("Task '{}' failed: {}", task.name, e));
+                    }
+                }
+            }
+        });
+
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(outcomes),
+        }
+    }
+
+    /// Run one task body with the file's functions and a copy of its
+    /// top-level variables.
+    fn run_task(&self, task: &Task, options: &RunOptions) -> Result<(), String> {
+        let program = MageParser::parse(Rule::program, &self.source)
+            .map_err(|e| format!("Parse error: {}", e))?;
+        let mut functions = HashMap::new();
+        define_functions(program, &mut functions);
+
+        let body = MageParser::parse(Rule::program, &task.body)
+            .map_err(|e| format!("Parse error: {}", e))?;
+        let mut scope = self.scope.clone();
+        let mut output = OutputCollector::direct()
+            .with_dry_run(options.dry_run)
+            .with_strict(options.strict);
+        interpret(
+            body,
+            self.shell.as_deref(),
+            &mut scope,
+            &mut functions,
+            &mut output,
+        )
+        .map_err(|e| format!("{}", e))
+    }
+
+    /// Render the task graph: each task that no other task needs, with the
+    /// tasks it needs below it. Tasks shown before are marked `(*)`.
+    pub fn graph(&self) -> String {
+        let needed: HashSet<&str> = self
+            .tasks
+            .iter()
+            .flat_map(|t| t.needs.iter().map(String::as_str))
+            .collect();
+
+        let mut out = String::new();
+        let mut shown = HashSet::new();
+        for task in &self.tasks {
+            if needed.contains(task.name.as_str()) {
+                continue;
+            }
+            shown.insert(task.name.clone());
+            out.push_str(&format!("{}\n", describe_task(task)));
+            self.render_needs(&task.needs, "", &mut shown, &mut out);
+        }
+        // Tasks that are only reachable through a cycle
+        for task in &self.tasks {
+            if shown.insert(task.name.clone()) {
+                out.push_str(&format!("{}\n", describe_task(task)));
+                self.render_needs(&task.needs, "", &mut shown, &mut out);
+            }
+        }
+        out
+    }
+
+    fn render_needs(
+        &self,
+        names: &[String],
+        prefix: &str,
+        shown: &mut HashSet<String>,
+        out: &mut String,
+    ) {
+        for (index, name) in names.iter().enumerate() {
+            let last = index + 1 == names.len();
+            let branch = if last { "└── " } else { "├── " };
+            let Some(task) = self.task(name) else {
+                out.push_str(&format!("{}{}{} (missing)\n", prefix, branch, name));
+                continue;
+            };
+            let repeated = !shown.insert(name.clone()) && !task.needs.is_empty();
+            out.push_str(&format!(
+                "{}{}{}{}\n",
+                prefix,
+                branch,
+                describe_task(task),
+                if repeated { " (*)" } else { "" }
+            ));
+            if !repeated {
+                let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                self.render_needs(&task.needs, &prefix, shown, out);
+            }
+        }
+    }
+}
+
+fn describe_task(task: &Task) -> String {
+    let mut files = Vec::new();
+    if !task.reads.is_empty() {
+        files.push(format!("reads {}", task.reads.join(", ")));
+    }
+    if !task.makes.is_empty() {
+        files.push(format!("makes {}", task.makes.join(", ")));
+    }
+    if files.is_empty() {
+        return task.name.clone();
+    }
+    format!("{}  ({})", task.name, files.join("; "))
+}
+
+fn modified(path: &Path) -> Result<SystemTime, String> {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+/// Files matching `pattern`, which is relative to `root` unless it is
+/// absolute. `*` and `?` match within one path segment and `**` matches any
+/// number of directories. A pattern without wildcards names a file, or every
+/// file under a directory.
+pub fn expand_glob(root: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+    let segments: Vec<&str> = pattern.split('/').collect();
+    let literal = segments
+        .iter()
+        .take_while(|s| !s.contains(['*', '?']))
+        .count();
+
+    if literal == segments.len() {
+        let path = root.join(pattern);
+        if path.is_dir() {
+            return walk_files(&path);
+        }
+        return Ok(if path.exists() {
+            vec![path]
+        } else {
+            Vec::new()
+        });
+    }
+
+    let base = match segments[..literal].join("/") {
+        base if base.is_empty() && pattern.starts_with('/') => PathBuf::from("/"),
+        base if base.is_empty() && root.as_os_str().is_empty() => PathBuf::from("."),
+        base => root.join(base),
+    };
+    let rest = &segments[literal..];
+    if !base.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut matches = Vec::new();
+    for path in walk_files(&base)? {
+        let Ok(relative) = path.strip_prefix(&base) else {
+            continue;
+        };
+        let parts: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+        if match_segments(rest, &parts) {
+            matches.push(path);
+        }
+    }
+    Ok(matches)
+}
+
+fn walk_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((segment, rest)) => {
+            !path.is_empty() && match_segment(segment, path[0]) && match_segments(rest, &path[1..])
+        }
+    }
+}
+
+/// Match one path segment against a pattern with `*` and `?` wildcards.
+fn match_segment(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
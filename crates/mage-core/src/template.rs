@@ -0,0 +1,612 @@
+//! A small template language for generating config files and dotfiles.
+//!
+//! ```text
+//! Hello {{ user.name | default("friend") }}!
+//! {% if shell == "zsh" %}source ~/.zshrc{% else %}source ~/.bashrc{% endif %}
+//! {% for alias, command in aliases %}alias {{ alias }}='{{ command }}'
+//! {% endfor %}
+//! {% include "common.tmpl" %}
+//! {% raw %}{{ not substituted }}{% endraw %}
+//! {# comments are dropped #}
+//! ```
+//!
+//! Block tags that sit on a line of their own do not leave blank lines behind.
+
+use crate::interpreter::ExprValue;
+use indexmap::IndexMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Maximum include depth, to stop a template from including itself forever.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    Expr(String),
+    Tag(String),
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Expr(String),
+    If {
+        branches: Vec<(String, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        key: Option<String>,
+        value: String,
+        iterable: String,
+        body: Vec<Node>,
+    },
+    Include(String),
+}
+
+/// Render `source`, which is either a path to a template file or the template text itself.
+pub fn render_template(source: &str, vars: &ExprValue) -> Result<String, String> {
+    let path = Path::new(source);
+    let (text, base_dir) = if !source.contains('\n') && path.is_file() {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read template '{}': {}", source, e))?;
+        let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        (text, base)
+    } else {
+        (source.to_string(), PathBuf::from("."))
+    };
+
+    let root = match vars {
+        ExprValue::Map(m) => m.clone(),
+        _ => return Err("render_template() variables must be a map".to_string()),
+    };
+
+    let mut renderer = Renderer {
+        scopes: vec![root],
+        base_dir,
+        depth: 0,
+    };
+    renderer.render_text(&text)
+}
+
+struct Renderer {
+    scopes: Vec<IndexMap<String, ExprValue>>,
+    /// Directory of the template being rendered, for relative includes
+    base_dir: PathBuf,
+    depth: usize,
+}
+
+impl Renderer {
+    fn render_text(&mut self, text: &str) -> Result<String, String> {
+        let tokens = tokenize(text)?;
+        let mut iter = tokens.into_iter().peekable();
+        let (nodes, end) = parse_nodes(&mut iter, &[])?;
+        if let Some(tag) = end {
+            return Err(format!("Unexpected {{% {} %}}", tag));
+        }
+        let mut out = String::new();
+        self.render_nodes(&nodes, &mut out)?;
+        Ok(out)
+    }
+
+    fn render_nodes(&mut self, nodes: &[Node], out: &mut String) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Expr(expr) => out.push_str(&self.eval_output(expr)?),
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    let mut taken = false;
+                    for (cond, body) in branches {
+                        if self.eval_condition(cond)? {
+                            self.render_nodes(body, out)?;
+                            taken = true;
+                            break;
+                        }
+                    }
+                    if !taken {
+                        self.render_nodes(otherwise, out)?;
+                    }
+                }
+                Node::For {
+                    key,
+                    value,
+                    iterable,
+                    body,
+                } => {
+                    let items: Vec<(ExprValue, ExprValue)> = match self.eval_operand(iterable)? {
+                        Some(ExprValue::List(list)) => list
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, v)| (ExprValue::Integer(i as i64), v))
+                            .collect(),
+                        Some(ExprValue::Map(map)) => map
+                            .into_iter()
+                            .map(|(k, v)| (ExprValue::String(k), v))
+                            .collect(),
+                        Some(ExprValue::String(s)) => s
+                            .chars()
+                            .enumerate()
+                            .map(|(i, c)| {
+                                (ExprValue::Integer(i as i64), ExprValue::String(c.into()))
+                            })
+                            .collect(),
+                        Some(other) => {
+                            return Err(format!("Cannot iterate over {} in template", other));
+                        }
+                        None => return Err(format!("Undefined template variable '{}'", iterable)),
+                    };
+
+                    let len = items.len();
+                    for (index, (k, v)) in items.into_iter().enumerate() {
+                        let mut frame = IndexMap::new();
+                        match key {
+                            Some(key) => {
+                                frame.insert(key.clone(), k);
+                                frame.insert(value.clone(), v);
+                            }
+                            None => {
+                                frame.insert(value.clone(), v);
+                            }
+                        }
+                        frame.insert(
+                            "loop".to_string(),
+                            ExprValue::Map(IndexMap::from([
+                                ("index".to_string(), ExprValue::Integer(index as i64)),
+                                ("first".to_string(), ExprValue::Boolean(index == 0)),
+                                ("last".to_string(), ExprValue::Boolean(index + 1 == len)),
+                            ])),
+                        );
+                        self.scopes.push(frame);
+                        let result = self.render_nodes(body, out);
+                        self.scopes.pop();
+                        result?;
+                    }
+                }
+                Node::Include(name) => {
+                    if self.depth >= MAX_INCLUDE_DEPTH {
+                        return Err(format!("Template include depth exceeded at '{}'", name));
+                    }
+                    let path = self.base_dir.join(name);
+                    let text = fs::read_to_string(&path).map_err(|e| {
+                        format!("Failed to include template '{}': {}", path.display(), e)
+                    })?;
+                    // Includes inside the included template are relative to it
+                    let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                    let base_dir = std::mem::replace(&mut self.base_dir, parent);
+                    self.depth += 1;
+                    let rendered = self.render_text(&text);
+                    self.depth -= 1;
+                    self.base_dir = base_dir;
+                    out.push_str(&rendered?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, path: &str) -> Option<ExprValue> {
+        let mut parts = path.split('.');
+        let first = parts.next()?;
+        let mut current = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(first))?
+            .clone();
+        for part in parts {
+            current = match current {
+                ExprValue::Map(map) => map.get(part)?.clone(),
+                ExprValue::List(list) => list.get(part.parse::<usize>().ok()?)?.clone(),
+                _ => return None,
+            };
+        }
+        Some(current)
+    }
+
+    /// Evaluate a literal or variable path. Returns `None` for undefined variables.
+    fn eval_operand(&self, operand: &str) -> Result<Option<ExprValue>, String> {
+        let operand = operand.trim();
+        if let Some(literal) = parse_string_literal(operand) {
+            return Ok(Some(ExprValue::String(literal)));
+        }
+        if let Ok(number) = operand.parse::<i64>() {
+            return Ok(Some(ExprValue::Integer(number)));
+        }
+        if let Ok(number) = operand.parse::<f64>() {
+            return Ok(Some(ExprValue::Number(number)));
+        }
+        match operand {
+            "true" => return Ok(Some(ExprValue::Boolean(true))),
+            "false" => return Ok(Some(ExprValue::Boolean(false))),
+            _ => {}
+        }
+        if operand.is_empty()
+            || !operand
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+        {
+            return Err(format!("Invalid template expression '{}'", operand));
+        }
+        Ok(self.lookup(operand))
+    }
+
+    /// Evaluate `operand | filter(args) | ...` into output text.
+    fn eval_output(&self, expr: &str) -> Result<String, String> {
+        let mut parts = split_top_level(expr, '|').into_iter();
+        let operand = parts.next().unwrap_or_default();
+        let mut value = self.eval_operand(&operand)?;
+
+        for filter in parts {
+            let (name, args) = parse_filter(&filter)?;
+            value = apply_filter(&name, value, &args)?;
+        }
+
+        match value {
+            Some(v) => Ok(v.to_display_string()),
+            None => Err(format!(
+                "Undefined template variable '{}' (use | default(...) to provide a fallback)",
+                operand.trim()
+            )),
+        }
+    }
+
+    fn eval_condition(&self, cond: &str) -> Result<bool, String> {
+        let or_parts = split_keyword(cond, "or");
+        if or_parts.len() > 1 {
+            for part in or_parts {
+                if self.eval_condition(&part)? {
+                    return Ok(true);
+                }
+            }
+            return Ok(false);
+        }
+        let and_parts = split_keyword(cond, "and");
+        if and_parts.len() > 1 {
+            for part in and_parts {
+                if !self.eval_condition(&part)? {
+                    return Ok(false);
+                }
+            }
+            return Ok(true);
+        }
+
+        let cond = cond.trim();
+        if let Some(rest) = cond.strip_prefix("not ") {
+            return Ok(!self.eval_condition(rest)?);
+        }
+        for op in ["==", "!="] {
+            if let [left, right] = split_outside_quotes(cond, op).as_slice() {
+                let left = self.eval_operand(left)?;
+                let right = self.eval_operand(right)?;
+                let equal = match (left, right) {
+                    (Some(l), Some(r)) => l.to_display_string() == r.to_display_string(),
+                    (None, None) => true,
+                    _ => false,
+                };
+                return Ok(if op == "==" { equal } else { !equal });
+            }
+        }
+        Ok(self
+            .eval_operand(cond)?
+            .as_ref()
+            .is_some_and(ExprValue::is_truthy))
+    }
+}
+
+fn apply_filter(
+    name: &str,
+    value: Option<ExprValue>,
+    args: &[String],
+) -> Result<Option<ExprValue>, String> {
+    let text = |v: &Option<ExprValue>| v.as_ref().map(|v| v.to_display_string());
+    Ok(match name {
+        // Only undefined and none values are replaced; 0, false and "" are kept
+        "default" => match value {
+            Some(v) if !matches!(v, ExprValue::None) => Some(v),
+            _ => Some(ExprValue::String(args.first().cloned().unwrap_or_default())),
+        },
+        "upper" => text(&value).map(|s| ExprValue::String(s.to_uppercase())),
+        "lower" => text(&value).map(|s| ExprValue::String(s.to_lowercase())),
+        "trim" => text(&value).map(|s| ExprValue::String(s.trim().to_string())),
+        "join" => match value {
+            Some(ExprValue::List(list)) => {
+                let sep = args.first().map(String::as_str).unwrap_or("");
+                let joined = list
+                    .iter()
+                    .map(|v| v.to_display_string())
+                    .collect::<Vec<_>>()
+                    .join(sep);
+                Some(ExprValue::String(joined))
+            }
+            other => other,
+        },
+        "escape" => text(&value).map(|s| {
+            ExprValue::String(
+                s.replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+                    .replace('"', "&quot;")
+                    .replace('\'', "&#39;"),
+            )
+        }),
+        "quote" => {
+            text(&value).map(|s| ExprValue::String(format!("'{}'", s.replace('\'', "'\\''"))))
+        }
+        _ => return Err(format!("Unknown template filter '{}'", name)),
+    })
+}
+
+fn parse_string_literal(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.len() >= 2
+        && ((s.starts_with('"') && s.ends_with('"')) || (s.starts_with('\'') && s.ends_with('\'')))
+    {
+        Some(s[1..s.len() - 1].to_string())
+    } else {
+        None
+    }
+}
+
+/// Parse `name` or `name(arg, ...)` where every argument is a literal.
+fn parse_filter(filter: &str) -> Result<(String, Vec<String>), String> {
+    let filter = filter.trim();
+    let Some(open) = filter.find('(') else {
+        return Ok((filter.to_string(), Vec::new()));
+    };
+    if !filter.ends_with(')') {
+        return Err(format!("Malformed template filter '{}'", filter));
+    }
+    let name = filter[..open].trim().to_string();
+    let args = split_top_level(&filter[open + 1..filter.len() - 1], ',')
+        .into_iter()
+        .filter(|a| !a.trim().is_empty())
+        .map(|a| parse_string_literal(&a).unwrap_or_else(|| a.trim().to_string()))
+        .collect();
+    Ok((name, args))
+}
+
+/// Split on `sep`, ignoring separators inside quotes or parentheses.
+fn split_top_level(s: &str, sep: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut depth = 0;
+    for c in s.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => {
+                quote = None;
+                current.push(c);
+            }
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (None, '(') => {
+                depth += 1;
+                current.push(c);
+            }
+            (None, ')') => {
+                depth -= 1;
+                current.push(c);
+            }
+            (None, c) if c == sep && depth == 0 => parts.push(std::mem::take(&mut current)),
+            (None, c) => current.push(c),
+        }
+    }
+    parts.push(current);
+    parts
+}
+
+/// Split a condition on a whole-word keyword such as `and`/`or`, outside quotes.
+fn split_keyword(s: &str, keyword: &str) -> Vec<String> {
+    split_outside_quotes(s, &format!(" {} ", keyword))
+}
+
+/// Split on every occurrence of `pattern` that is not inside a quoted string.
+fn split_outside_quotes(s: &str, pattern: &str) -> Vec<String> {
+    let bytes = s.as_bytes();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == b'"' || c == b'\'' => quote = Some(c),
+            None if bytes[i..].starts_with(pattern.as_bytes()) => {
+                parts.push(s[start..i].to_string());
+                i += pattern.len();
+                start = i;
+                continue;
+            }
+            None => {}
+        }
+        i += 1;
+    }
+    parts.push(s[start..].to_string());
+    parts
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    let mut text_buf = String::new();
+
+    while let Some(start) = rest.find('{') {
+        let (before, from) = rest.split_at(start);
+        let close = match from.get(..2) {
+            Some("{{") => "}}",
+            Some("{%") => "%}",
+            Some("{#") => "#}",
+            _ => {
+                text_buf.push_str(before);
+                text_buf.push('{');
+                rest = &from[1..];
+                continue;
+            }
+        };
+        text_buf.push_str(before);
+
+        let end = from[2..]
+            .find(close)
+            .ok_or_else(|| format!("Unclosed '{}' in template", &from[..2]))?;
+        let inner = from[2..2 + end].trim();
+        rest = &from[2 + end + 2..];
+
+        match close {
+            "}}" => {
+                tokens.push(Token::Text(std::mem::take(&mut text_buf)));
+                tokens.push(Token::Expr(inner.to_string()));
+            }
+            "%}" if inner == "raw" => {
+                trim_block_line(&mut text_buf, &mut rest);
+                let (start, end) = find_endraw(rest).ok_or("Unclosed {% raw %} in template")?;
+                let mut raw = rest[..start].to_string();
+                rest = &rest[end..];
+                trim_block_line(&mut raw, &mut rest);
+                text_buf.push_str(&raw);
+            }
+            "%}" => {
+                trim_block_line(&mut text_buf, &mut rest);
+                tokens.push(Token::Text(std::mem::take(&mut text_buf)));
+                tokens.push(Token::Tag(inner.to_string()));
+            }
+            _ => trim_block_line(&mut text_buf, &mut rest),
+        }
+    }
+    text_buf.push_str(rest);
+    tokens.push(Token::Text(text_buf));
+    Ok(tokens)
+}
+
+/// Find `{% endraw %}` and return (start, end) byte offsets.
+fn find_endraw(s: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+    while let Some(pos) = s[offset..].find("{%") {
+        let start = offset + pos;
+        let close = s[start..].find("%}")?;
+        if s[start + 2..start + close].trim() == "endraw" {
+            return Some((start, start + close + 2));
+        }
+        offset = start + 2;
+    }
+    None
+}
+
+/// When a block tag is the only thing on its line, drop the indentation before
+/// it and the newline after it so the tag leaves no blank line behind.
+fn trim_block_line(before: &mut String, after: &mut &str) {
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let own_line = before[line_start..].chars().all(|c| c == ' ' || c == '\t');
+    let newline_after = if after.starts_with("\r\n") {
+        Some(2)
+    } else if after.starts_with('\n') || after.is_empty() {
+        Some(after.len().min(1))
+    } else {
+        None
+    };
+    if own_line && let Some(skip) = newline_after {
+        before.truncate(line_start);
+        *after = &after[skip..];
+    }
+}
+
+fn parse_nodes<I>(
+    tokens: &mut std::iter::Peekable<I>,
+    terminators: &[&str],
+) -> Result<(Vec<Node>, Option<String>), String>
+where
+    I: Iterator<Item = Token>,
+{
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Text(text) => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(text));
+                }
+            }
+            Token::Expr(expr) => nodes.push(Node::Expr(expr)),
+            Token::Tag(tag) => {
+                let keyword = tag.split_whitespace().next().unwrap_or("");
+                if terminators.contains(&keyword) {
+                    return Ok((nodes, Some(tag)));
+                }
+                let rest = tag[keyword.len()..].trim();
+                match keyword {
+                    "if" => nodes.push(parse_if(tokens, rest)?),
+                    "for" => nodes.push(parse_for(tokens, rest)?),
+                    "include" => {
+                        let name = parse_string_literal(rest)
+                            .ok_or_else(|| format!("include expects a quoted path: {}", tag))?;
+                        nodes.push(Node::Include(name));
+                    }
+                    _ => return Err(format!("Unknown template tag {{% {} %}}", tag)),
+                }
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn parse_if<I>(tokens: &mut std::iter::Peekable<I>, condition: &str) -> Result<Node, String>
+where
+    I: Iterator<Item = Token>,
+{
+    let mut branches = Vec::new();
+    let mut otherwise = Vec::new();
+    let mut condition = condition.to_string();
+
+    loop {
+        let (body, end) = parse_nodes(tokens, &["elif", "else", "endif"])?;
+        let end = end.ok_or("Missing {% endif %} in template")?;
+        branches.push((condition.clone(), body));
+
+        match end.split_whitespace().next() {
+            Some("elif") => condition = end["elif".len()..].trim().to_string(),
+            Some("else") => {
+                let (body, end) = parse_nodes(tokens, &["endif"])?;
+                end.ok_or("Missing {% endif %} in template")?;
+                otherwise = body;
+                break;
+            }
+            _ => break,
+        }
+    }
+
+    Ok(Node::If {
+        branches,
+        otherwise,
+    })
+}
+
+fn parse_for<I>(tokens: &mut std::iter::Peekable<I>, header: &str) -> Result<Node, String>
+where
+    I: Iterator<Item = Token>,
+{
+    let (vars, iterable) = header
+        .split_once(" in ")
+        .ok_or_else(|| format!("Malformed for loop: {{% for {} %}}", header))?;
+    let names: Vec<String> = vars.split(',').map(|v| v.trim().to_string()).collect();
+    let (key, value) = match names.as_slice() {
+        [value] => (None, value.clone()),
+        [key, value] => (Some(key.clone()), value.clone()),
+        _ => return Err(format!("Malformed for loop: {{% for {} %}}", header)),
+    };
+
+    let (body, end) = parse_nodes(tokens, &["endfor"])?;
+    end.ok_or("Missing {% endfor %} in template")?;
+
+    Ok(Node::For {
+        key,
+        value,
+        iterable: iterable.trim().to_string(),
+        body,
+    })
+}